@@ -0,0 +1,220 @@
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion};
+use figgie_tournament_testnet::{CardBook, PlayerId, PriceLevelBook, MAX_PRICE};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+
+
+// The engine's PriceLevelBook vs the sorted Vec book it replaced, with 10 / 100 / 1000 players on one card:
+// - refill: every player posts a bid and an ask into a book that was just reset, like after every trade
+// - requote: every player moves both of their quotes to a new price
+// - cancel: every player pulls both of their quotes
+// - top: reading the best bid + ask once per player, what the engine does before every order
+// - publish: turning the book into the CardBook that goes out in updates (a clone for the sorted book)
+// The sorted book keys on player names like the engine used to, the price level book on interned PlayerIds
+
+const PLAYERS: [usize; 3] = [10, 100, 1000];
+
+
+// =-= Sorted Vec Book =-= //
+
+#[derive(Debug, Clone)]
+struct NamedEntry {
+    price: usize,
+    player_name: String,
+}
+
+// how CardBook used to quote: scan for the player, then sort the whole side after every change
+#[derive(Debug, Clone)]
+struct SortedVecBook {
+    bids: Vec<NamedEntry>,
+    asks: Vec<NamedEntry>,
+}
+
+impl SortedVecBook {
+    fn cancel_bid(&mut self, player_name: String) {
+        self.bids.retain(|bid| bid.player_name != player_name);
+    }
+
+    fn cancel_ask(&mut self, player_name: String) {
+        self.asks.retain(|ask| ask.player_name != player_name);
+    }
+
+    fn update_bid(&mut self, price: usize, player_name: String) {
+        let mut found = false;
+        for bid in self.bids.iter_mut() {
+            if bid.player_name == player_name {
+                bid.price = price;
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            self.bids.push(NamedEntry { price, player_name });
+        }
+        self.bids.sort_by_key(|bid| Reverse(bid.price));
+    }
+
+    fn update_ask(&mut self, price: usize, player_name: String) {
+        let mut found = false;
+        for ask in self.asks.iter_mut() {
+            if ask.player_name == player_name {
+                ask.price = price;
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            self.asks.push(NamedEntry { price, player_name });
+        }
+        self.asks.sort_by_key(|ask| ask.price);
+    }
+}
+
+
+// =-= Workloads =-= //
+
+// the same calls the engine makes, so both books run the exact same workload
+trait Book: Clone {
+    type Published;
+    fn empty() -> Self;
+    fn bid(&mut self, price: usize, player: &Player);
+    fn ask(&mut self, price: usize, player: &Player);
+    fn cancel(&mut self, player: &Player);
+    fn reset(&mut self);
+    fn top(&self) -> (Option<usize>, Option<usize>);
+    fn publish(&self) -> Self::Published;
+}
+
+struct Player {
+    id: PlayerId,
+    name: String,
+}
+
+impl Book for SortedVecBook {
+    type Published = SortedVecBook;
+    fn empty() -> Self {
+        SortedVecBook { bids: Vec::new(), asks: Vec::new() }
+    }
+    fn bid(&mut self, price: usize, player: &Player) {
+        self.update_bid(price, player.name.clone());
+    }
+    fn ask(&mut self, price: usize, player: &Player) {
+        self.update_ask(price, player.name.clone());
+    }
+    fn cancel(&mut self, player: &Player) {
+        self.cancel_bid(player.name.clone());
+        self.cancel_ask(player.name.clone());
+    }
+    fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+    fn top(&self) -> (Option<usize>, Option<usize>) {
+        (self.bids.first().map(|bid| bid.price), self.asks.first().map(|ask| ask.price))
+    }
+    fn publish(&self) -> SortedVecBook {
+        self.clone()
+    }
+}
+
+impl Book for PriceLevelBook {
+    type Published = CardBook;
+    fn empty() -> Self {
+        PriceLevelBook::new()
+    }
+    fn bid(&mut self, price: usize, player: &Player) {
+        self.update_bid(price, player.id);
+    }
+    fn ask(&mut self, price: usize, player: &Player) {
+        self.update_ask(price, player.id);
+    }
+    fn cancel(&mut self, player: &Player) {
+        self.cancel_bid(player.id);
+        self.cancel_ask(player.id);
+    }
+    fn reset(&mut self) {
+        self.reset_quotes();
+    }
+    fn top(&self) -> (Option<usize>, Option<usize>) {
+        (self.best_bid().map(|bid| bid.price), self.best_ask().map(|ask| ask.price))
+    }
+    fn publish(&self) -> CardBook {
+        self.card_book()
+    }
+}
+
+// (player, bid, ask) with the bids under 50 and the asks above it so nothing would cross, same seed every run
+fn quotes(players: usize, seed: u64) -> Vec<(Player, usize, usize)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..players).map(|player| {
+        let name = format!("Player{}", player);
+        (Player { id: PlayerId::intern(&name), name }, rng.gen_range(1..50), rng.gen_range(50..=MAX_PRICE))
+    }).collect()
+}
+
+fn fill<B: Book>(book: &mut B, quotes: &[(Player, usize, usize)]) {
+    for (player, bid, ask) in quotes {
+        book.bid(*bid, player);
+        book.ask(*ask, player);
+    }
+}
+
+// one op for one book, the op's group puts both books side by side at every player count
+fn bench_op<B: Book>(group: &mut BenchmarkGroup<'_, WallTime>, name: &str, op: &str, players: usize) {
+    let first = quotes(players, 1);
+    let second = quotes(players, 2);
+    let mut full = B::empty();
+    fill(&mut full, &first);
+    let id = BenchmarkId::new(name, players);
+
+    match op {
+        "refill" => group.bench_function(id, |b| {
+            let mut book = full.clone();
+            b.iter(|| {
+                book.reset();
+                fill(&mut book, &first);
+            });
+        }),
+        "requote" => group.bench_function(id, |b| {
+            b.iter_batched(|| full.clone(), |mut book| {
+                fill(&mut book, &second);
+                book // dropped outside of the measurement
+            }, BatchSize::SmallInput);
+        }),
+        "cancel" => group.bench_function(id, |b| {
+            b.iter_batched(|| full.clone(), |mut book| {
+                for (player, _, _) in &first {
+                    book.cancel(player);
+                }
+                book
+            }, BatchSize::SmallInput);
+        }),
+        "top" => group.bench_function(id, |b| {
+            b.iter(|| {
+                for _ in 0..players {
+                    criterion::black_box(full.top());
+                }
+            });
+        }),
+        _ => group.bench_function(id, |b| {
+            b.iter(|| full.publish());
+        }),
+    };
+}
+
+fn books(c: &mut Criterion) {
+    for op in ["refill", "requote", "cancel", "top", "publish"] {
+        let mut group = c.benchmark_group(op);
+        for players in PLAYERS {
+            bench_op::<SortedVecBook>(&mut group, "sorted_vec", op, players);
+            bench_op::<PriceLevelBook>(&mut group, "price_levels", op, players);
+        }
+        group.finish();
+    }
+}
+
+
+criterion_group!(benches, books);
+criterion_main!(benches);
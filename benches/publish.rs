@@ -0,0 +1,111 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use figgie_tournament_testnet::{Card, CardBook, Event, PlayerId, PriceLevelBook, SchemaVersion, Subscription, Trade, Update};
+use figgie_tournament_testnet::deflate::{self, DeflateConfig};
+use figgie_tournament_testnet::frame::SharedFrame;
+use figgie_tournament_testnet::outbound::{Outbox, OutboundConfig, SlowConsumerPolicy};
+use figgie_tournament_testnet::publisher;
+use figgie_tournament_testnet::websocket::{PlayerWsMap, PlayerConnection, Transport};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+
+
+// Fanning one book update out to 10 / 100 / 1000 websocket subscribers (legacy v1 clients, all on the same subscription):
+// - per_subscriber: every subscriber gets the event serialized for them, how broadcasts used to work
+// - shared: serialized once, every subscriber's queue gets the same SharedFrame
+// The deflate group is the writer's side of it, compressing the message for every session vs once per frame
+
+const SUBSCRIBERS: [usize; 3] = [10, 100, 1000];
+const DEFLATE: DeflateConfig = DeflateConfig { level: 6, threshold: 256 };
+
+
+fn book(offset: usize) -> CardBook {
+    let mut book = PriceLevelBook::new();
+    for level in 0..4 {
+        book.update_bid(10 + offset + level, PlayerId::intern(&format!("BidderNumber{}", level)));
+        book.update_ask(30 + offset + level, PlayerId::intern(&format!("AskerNumber{}", level)));
+    }
+    book.last_trade = Some(20 + offset);
+    book.card_book()
+}
+
+fn update_event() -> Event {
+    Event::Update(Update {
+        spades: book(0),
+        clubs: book(1),
+        diamonds: book(2),
+        hearts: book(3),
+        trade: Some(Trade { card: Card::Spade, price: 20, buyer: PlayerId::intern("BidderNumber0"), seller: PlayerId::intern("AskerNumber0") }),
+    })
+}
+
+// every subscriber gets a queue big enough that nothing is ever dropped during a run
+fn subscribers(count: usize) -> PlayerWsMap {
+    let config = OutboundConfig { capacity: usize::MAX, policy: SlowConsumerPolicy::Block };
+    let connections = (0..count as u64).map(|session_id| {
+        let outbox = Arc::new(Outbox::new(config));
+        (session_id, PlayerConnection::new(format!("Player{}", session_id), Transport::WebSocket(outbox), SchemaVersion::V1, Subscription::legacy()))
+    }).collect::<HashMap<_, _>>();
+    Arc::new(Mutex::new(connections))
+}
+
+async fn publish_per_subscriber(seq: u64, event: &Event, player_ws_map: &PlayerWsMap) {
+    let mut player_ws_map_guard = player_ws_map.lock().await;
+    for connection in player_ws_map_guard.values_mut() {
+        if let Some(message) = event.render(seq, &connection.player_name, connection.version, &connection.subscription) {
+            let _ = connection.sender.send_event(seq, event.kind(), event.is_conflatable(&connection.subscription), SharedFrame::from(message)).await;
+        }
+    }
+}
+
+
+fn fan_out(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let event = update_event();
+
+    let mut group = c.benchmark_group("fan_out");
+    for count in SUBSCRIBERS {
+        group.bench_with_input(BenchmarkId::new("per_subscriber", count), &count, |b, &count| {
+            b.iter_batched(|| subscribers(count), |player_ws_map| {
+                runtime.block_on(publish_per_subscriber(1, &event, &player_ws_map));
+                player_ws_map // dropped outside of the measurement
+            }, BatchSize::LargeInput);
+        });
+        group.bench_with_input(BenchmarkId::new("shared", count), &count, |b, &count| {
+            b.iter_batched(|| subscribers(count), |player_ws_map| {
+                runtime.block_on(publisher::publish(1, &event, &player_ws_map, &mut None));
+                player_ws_map
+            }, BatchSize::LargeInput);
+        });
+    }
+    group.finish();
+}
+
+fn deflate(c: &mut Criterion) {
+    let message = update_event().render(1, "", SchemaVersion::V1, &Subscription::legacy()).unwrap();
+
+    let mut group = c.benchmark_group("deflate");
+    for count in SUBSCRIBERS {
+        group.bench_with_input(BenchmarkId::new("per_subscriber", count), &count, |b, &count| {
+            b.iter(|| {
+                for _ in 0..count {
+                    criterion::black_box(deflate::deflate_message(message.as_bytes(), DEFLATE.level));
+                }
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("shared", count), &count, |b, &count| {
+            b.iter_batched(|| SharedFrame::from(message.clone()), |frame| {
+                for _ in 0..count {
+                    criterion::black_box(frame.deflated(DEFLATE));
+                }
+                frame
+            }, BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, fan_out, deflate);
+criterion_main!(benches);
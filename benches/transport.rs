@@ -0,0 +1,145 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use figgie_tournament_testnet::{Card, Direction, Order, PlayerId};
+use figgie_tournament_testnet::matching_engine::MatchingEngine;
+use figgie_tournament_testnet::order_queue::{OrderQueue, QueueTransport, BOOK_STATE_INTERVAL};
+use figgie_tournament_testnet::publisher;
+use figgie_tournament_testnet::ring;
+use figgie_tournament_testnet::sequencer::Received;
+use std::thread::JoinHandle;
+use tokio::runtime::Runtime;
+use tokio::time::Duration;
+
+
+// Latency from the gateway to the engine and back, one order at a time:
+// - round_trip: OrderQueue::submit through each QueueTransport to a real engine on its own thread. The order is from a player
+//   the engine doesn't know, so it answers right away and what's left is the trip there + back (and the answer's two Strings)
+// - hop: a number through a bare queue to a thread that sends it straight back, both ends spinning. kanal vs the ring w/ a
+//   CAS for the tail (mpsc) vs the ring w/o one (spsc)
+// On a box w/ fewer cores than threads here these mostly measure the scheduler, the spinning ends yield so they still finish
+
+const QUEUE_DEPTH: usize = 1024;
+const STOP: usize = usize::MAX;
+
+
+// =-= Round Trip =-= //
+
+struct Engine {
+    order_queue: OrderQueue,
+    shutdown: tokio::sync::oneshot::Sender<()>,
+    hotpath: JoinHandle<()>,
+    _events: publisher::EventReceiver, // nothing's published, but the engine shouldn't see it closed
+}
+
+fn engine(transport: QueueTransport) -> Engine {
+    let (event_sender, events) = publisher::event_queue();
+    let (order_queue, receiver) = OrderQueue::new(QUEUE_DEPTH, Duration::from_secs(1), transport, Duration::ZERO);
+    let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel();
+    let hotpath = std::thread::spawn(move || {
+        let mut matching_engine = MatchingEngine::new(500, event_sender);
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(receiver.run(&mut matching_engine, BOOK_STATE_INTERVAL, shutdown_rx));
+    });
+    Engine { order_queue, shutdown, hotpath, _events: events }
+}
+
+fn round_trip(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let order = Order { player: PlayerId::intern("NobodyRegistered"), card: Card::Spade, direction: Direction::Buy, price: Some(10) };
+
+    let mut group = c.benchmark_group("round_trip");
+    for (name, transport) in [("kanal", QueueTransport::Kanal), ("ring", QueueTransport::Ring), ("ring_spin", QueueTransport::RingSpin)] {
+        let engine = engine(transport);
+        group.bench_function(name, |b| b.iter(|| submit(&rt, &engine.order_queue, order.clone())));
+        let _ = engine.shutdown.send(());
+        engine.hotpath.join().unwrap();
+    }
+    group.finish();
+}
+
+fn submit(rt: &Runtime, order_queue: &OrderQueue, order: Order) {
+    let answer = rt.block_on(order_queue.submit(order, Received::now())).expect("the engine answers every order");
+    assert_eq!(answer.response.status, "UNKNOWN_PLAYER");
+}
+
+
+// =-= Hop =-= //
+
+// spins on `poll` until it has something, giving the core up every so often
+fn spin<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+    let mut spins: u32 = 0;
+    loop {
+        if let Some(value) = poll() {
+            return value;
+        }
+        spins = spins.wrapping_add(1);
+        if spins.is_multiple_of(64) {
+            std::thread::yield_now();
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+fn hop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hop");
+
+    {
+        let (to_echo, from_bench) = kanal::bounded::<usize>(QUEUE_DEPTH);
+        let (to_bench, from_echo) = kanal::bounded::<usize>(QUEUE_DEPTH);
+        let echo = std::thread::spawn(move || loop {
+            let value = spin(|| from_bench.try_recv().unwrap());
+            if value == STOP {
+                break;
+            }
+            to_bench.try_send(value).unwrap();
+        });
+        group.bench_function("kanal", |b| b.iter(|| {
+            to_echo.try_send(1).unwrap();
+            spin(|| from_echo.try_recv().unwrap())
+        }));
+        to_echo.try_send(STOP).unwrap();
+        echo.join().unwrap();
+    }
+
+    {
+        let (to_echo, mut from_bench) = ring::mpsc::<usize>(QUEUE_DEPTH, true);
+        let (to_bench, mut from_echo) = ring::mpsc::<usize>(QUEUE_DEPTH, true);
+        let echo = std::thread::spawn(move || loop {
+            let value = spin(|| from_bench.pop());
+            if value == STOP {
+                break;
+            }
+            let _ = to_bench.push(value);
+        });
+        group.bench_function("ring_mpsc", |b| b.iter(|| {
+            let _ = to_echo.push(1);
+            spin(|| from_echo.pop())
+        }));
+        let _ = to_echo.push(STOP);
+        echo.join().unwrap();
+    }
+
+    {
+        let (mut to_echo, mut from_bench) = ring::spsc::<usize>(QUEUE_DEPTH, true);
+        let (mut to_bench, mut from_echo) = ring::spsc::<usize>(QUEUE_DEPTH, true);
+        let echo = std::thread::spawn(move || loop {
+            let value = spin(|| from_bench.pop());
+            if value == STOP {
+                break;
+            }
+            let _ = to_bench.push(value);
+        });
+        group.bench_function("ring_spsc", |b| b.iter(|| {
+            let _ = to_echo.push(1);
+            spin(|| from_echo.pop())
+        }));
+        let _ = to_echo.push(STOP);
+        echo.join().unwrap();
+    }
+
+    group.finish();
+}
+
+
+criterion_group!(benches, round_trip, hop);
+criterion_main!(benches);
@@ -0,0 +1,533 @@
+use super::{AdminRequest, Inventory, PlayerId, SchemaVersion, CL};
+use super::api_error::{ApiError, api_version, parse_json, respond};
+use super::auth::{Auth, Identity, PlayerKey};
+use super::link::{DirectoryChange, EngineLink, LinkReply, LinkRequest, Replicas, ADMIN_TIMEOUT};
+use super::matching_engine::{MatchingEngine, SharedView};
+use super::order_queue::OrderQueue;
+use super::rate_limit::RateLimiter;
+use super::websocket::{PlayerWsMap, Transport, close_player_sessions};
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::time::{sleep, Duration};
+
+
+const ROUND_DURATION: Duration = Duration::from_secs(60 * 3);
+const ROUND_BREAK: Duration = Duration::from_secs(15); // before every round + after the game
+const NUM_OF_ROUNDS: usize = 4;
+
+
+// =-= Admin State =-= //
+
+// everything the admin actions touch, plus the line into the running game loop
+pub struct AdminState {
+    pub started_game: Arc<AtomicBool>,
+    pub order_queue: Arc<OrderQueue>, // every change to the engine goes through here
+    pub engine_view: SharedView,
+    pub playerid_playername_map: Arc<RwLock<HashMap<String, String>>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub auth: Arc<Auth>,
+    pub player_ws_map: PlayerWsMap,
+    pub replicas: Arc<Replicas>, // gateways connected over the engine link, every change to who's registered goes out to them
+    game: Mutex<Option<mpsc::UnboundedSender<GameRequest>>>, // closed once the game loop exits
+    banned: RwLock<Banned>,
+    roster: RwLock<Option<HashSet<String>>>, // the seated players, None lets anyone register
+}
+
+#[derive(Default)]
+struct Banned {
+    player_names: HashSet<String>,
+    player_ids: HashSet<String>, // so they can't just register again under a new name
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GameCommand {
+    Pause,
+    Resume,
+    EndRound,
+    Abort,
+}
+
+type GameRequest = (GameCommand, oneshot::Sender<Result<String, ApiError>>); // the game loop answers on the oneshot
+
+enum Waited {
+    Done,
+    Aborted,
+}
+
+impl AdminState {
+    pub fn new(
+        started_game: Arc<AtomicBool>,
+        order_queue: Arc<OrderQueue>,
+        engine_view: SharedView,
+        playerid_playername_map: Arc<RwLock<HashMap<String, String>>>,
+        rate_limiter: Arc<RateLimiter>,
+        auth: Arc<Auth>,
+        player_ws_map: PlayerWsMap,
+    ) -> Self {
+        Self {
+            started_game,
+            order_queue,
+            engine_view,
+            playerid_playername_map,
+            rate_limiter,
+            auth,
+            player_ws_map,
+            replicas: Replicas::new(),
+            game: Mutex::new(None),
+            banned: RwLock::new(Banned::default()),
+            roster: RwLock::new(None),
+        }
+    }
+
+    // /register_testnet asks first, banned ids and (while players are seated) everyone else get turned away
+    pub async fn check_registration(&self, player_id: &str) -> Result<(), ApiError> {
+        if self.banned.read().await.player_ids.contains(player_id) {
+            return Err(ApiError::Unauthorized("This playerid has been banned".to_string()));
+        }
+        if self.roster.read().await.is_some() {
+            return Err(ApiError::Unauthorized("Registration is closed, only seated players can join this game".to_string()));
+        }
+        Ok(())
+    }
+
+    // the player's name, a new random one unless the playerid already has one. On a gateway the engine's process does this
+    pub async fn register_testnet(&self, player_id: &str) -> Result<String, ApiError> {
+        if let Some(engine) = self.order_queue.engine_link() {
+            return match engine.request(LinkRequest::Register { player_id: player_id.to_string() }, self.order_queue.timeout).await? {
+                LinkReply::Register(result) => result,
+                _ => Err(ApiError::EngineUnavailable),
+            };
+        }
+
+        if let Err(error) = self.check_registration(player_id).await {
+            println!("{}[!] Registration refused for {:?}: {}{}", CL::Orange.get(), player_id, error.message(), CL::End.get());
+            return Err(error);
+        }
+
+        // write lock since we'll be adding a player later if needed
+        let mut playerid_playername_map_guard = self.playerid_playername_map.write().await;
+        if let Some(player_name) = playerid_playername_map_guard.get(player_id) {
+            return Ok(player_name.clone());
+        }
+
+        let player_name = generate_random_player_name();
+
+        playerid_playername_map_guard.insert(player_id.to_string(), player_name.clone());
+        drop(playerid_playername_map_guard);
+
+        self.rate_limiter.register(&player_name).await;
+        self.replicas.broadcast(DirectoryChange::Registered { player_name: player_name.clone(), player_id: Some(player_id.to_string()) }).await;
+
+        let player = PlayerId::intern(&player_name); // the engine only ever sees this from here on
+        self.order_queue.call(move |matching_engine| {
+            matching_engine.add_new_player_with_inventory(player, Inventory { spades: 3, clubs: 3, diamonds: 3, hearts: 3 });
+            matching_engine.print_all_players();
+        }).await?;
+
+        Ok(player_name)
+    }
+
+    // hands the command to the game loop and waits for its answer
+    async fn command(&self, command: GameCommand) -> Result<String, ApiError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        match &*self.game.lock().await {
+            Some(game) if game.send((command, reply_sender)).is_ok() => {},
+            _ => return Err(ApiError::NoGame),
+        }
+        reply_receiver.await.unwrap_or(Err(ApiError::NoGame))
+    }
+}
+
+
+// =-= Admin API =-= //
+
+#[post("/admin")]
+async fn admin_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    admin: web::Data<Arc<AdminState>>,
+) -> impl Responder {
+
+    println!("{}[+] ADMIN |:| Received POST request with admin details{}", CL::DimLightBlue.get(), CL::End.get());
+    let data = match parse_json::<AdminRequest>(&req, &body) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let result = admin_action(&req, &body, data, &admin).await;
+
+    // v1 has always answered the admin in plain text
+    match (api_version(&req), result) {
+        (SchemaVersion::V1, Ok(message)) => HttpResponse::Ok().body(message),
+        (SchemaVersion::V1, Err(ApiError::GameAlreadyStarted)) => HttpResponse::Ok().body("Game started"),
+        (SchemaVersion::V1, Err(error)) => HttpResponse::Ok().body(error.message()),
+        (SchemaVersion::V2, result) => respond(&req, result),
+    }
+}
+
+async fn admin_action(req: &HttpRequest, body: &[u8], data: Result<AdminRequest, ApiError>, admin: &Arc<AdminState>) -> Result<String, ApiError> {
+    let headers = req.headers();
+
+    // a request signed w/ the admin key, or the `adminid` header matching ADMIN_ID (unless only signed requests are allowed)
+    let authenticated = match admin.auth.authenticate_http(req, body).await? {
+        Some(identity) => identity == Identity::Admin,
+        None => match headers.get("adminid") {
+            Some(admin_id) => admin.auth.check_admin_id(admin_id.to_str().unwrap_or_default()),
+            None => {
+                println!("{}[!] ADMIN |:| Admin ID not found{}", CL::Red.get(), CL::End.get());
+                return Err(ApiError::MissingHeader("Admin ID not in headers".to_string()));
+            }
+        },
+    };
+    if !authenticated {
+        println!("{}[!] ADMIN |:| Authentication Failed{}", CL::Red.get(), CL::End.get());
+        return Err(ApiError::Unauthorized("Authentication Failed".to_string()));
+    }
+    println!("{}[+] ADMIN |:| Authentication passed{}", CL::Green.get(), CL::End.get());
+
+    let data = data?;
+    println!("{}[+] ADMIN |:| Action: {} | players: {:?}{}", CL::DimLightBlue.get(), data.action, data.players, CL::End.get());
+    match admin.order_queue.engine_link() {
+        Some(engine) => forward(admin, engine, data).await,
+        None => dispatch(admin, data).await,
+    }
+}
+
+// runs an authenticated admin action, from this process' /admin or a gateway's
+pub async fn dispatch(admin: &Arc<AdminState>, data: AdminRequest) -> Result<String, ApiError> {
+    match data.action.as_str() {
+        "start_game" => start_game(admin).await,
+        "pause" => admin.command(GameCommand::Pause).await,
+        "resume" => admin.command(GameCommand::Resume).await,
+        "end_round" => admin.command(GameCommand::EndRound).await,
+        "abort" => admin.command(GameCommand::Abort).await,
+        "reset_points" => {
+            let starting_balance = admin.order_queue.call(|matching_engine| {
+                matching_engine.reset_points();
+                matching_engine.starting_balance
+            }).await?;
+            Ok(format!("Reset everyone's points to {}", starting_balance))
+        },
+        "kick" => kick(admin, &player_list(&data.players)?, false).await,
+        "ban" => kick(admin, &player_list(&data.players)?, true).await,
+        "unban" => unban(admin, &player_list(&data.players)?).await,
+        "seat" => seat(admin, &data.players).await,
+        "announce" => match data.message {
+            Some(message) if !message.trim().is_empty() => {
+                admin.order_queue.call(|matching_engine| matching_engine.announce(message)).await?;
+                Ok("Announcement sent".to_string())
+            },
+            _ => Err(ApiError::ParseError("Please send the announcement as 'message'".to_string())),
+        },
+        "sessions" => Ok(serde_json::Value::from(sessions(admin).await).to_string()),
+        "dump_state" => dump_state(admin).await,
+        "issue_keys" => issue_keys(admin, &player_list(&data.players)?).await,
+        _ => {
+            println!("{}[!] ADMIN |:| Invalid action: {}{}", CL::Red.get(), data.action, CL::End.get());
+            Err(ApiError::InvalidAction("Invalid action".to_string()))
+        }
+    }
+}
+
+// the game runs in the engine's process, a gateway only answers for its own sessions
+async fn forward(admin: &AdminState, engine: &EngineLink, data: AdminRequest) -> Result<String, ApiError> {
+    if data.action == "sessions" {
+        return Ok(serde_json::Value::from(sessions(admin).await).to_string());
+    }
+
+    let dump = data.action == "dump_state";
+    let answer = match engine.request(LinkRequest::Admin(data), ADMIN_TIMEOUT).await? {
+        LinkReply::Admin(answer) => answer?,
+        _ => return Err(ApiError::EngineUnavailable),
+    };
+    if !dump {
+        return Ok(answer);
+    }
+
+    // the engine's process has no sessions of its own
+    let mut state = serde_json::from_str::<serde_json::Value>(&answer).map_err(|_| ApiError::EngineUnavailable)?;
+    state["sessions"] = sessions(admin).await.into();
+    Ok(state.to_string())
+}
+
+// `players` is a comma separated list of player names
+fn player_list(players: &str) -> Result<Vec<String>, ApiError> {
+    let player_names = players.split(',').map(str::trim).filter(|player_name| !player_name.is_empty()).map(str::to_string).collect::<Vec<String>>();
+    match player_names.is_empty() {
+        true => Err(ApiError::ParseError("Please send 'players' as a comma separated list of player names".to_string())),
+        false => Ok(player_names),
+    }
+}
+
+
+// =-= Game Loop =-= //
+
+async fn start_game(admin: &Arc<AdminState>) -> Result<String, ApiError> {
+    let mut game = admin.game.lock().await;
+    if matches!(&*game, Some(sender) if !sender.is_closed()) {
+        println!("{}[!] ADMIN |:| Game already started{}", CL::Orange.get(), CL::End.get());
+        return Err(ApiError::GameAlreadyStarted);
+    }
+
+    println!("{}[+] ADMIN |:| Starting game{}", CL::Green.get(), CL::End.get());
+    admin.started_game.store(true, Ordering::Release);
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    *game = Some(sender);
+    tokio::task::spawn_local(run_game(Arc::clone(admin), receiver));
+
+    Ok("Game started".to_string())
+}
+
+// keeps playing games until the admin aborts one
+async fn run_game(admin: Arc<AdminState>, mut commands: mpsc::UnboundedReceiver<GameRequest>) {
+    loop {
+        for i in 0..NUM_OF_ROUNDS {
+            if let Waited::Aborted = wait(&admin, ROUND_BREAK, false, &mut commands).await {
+                return;
+            }
+
+            admin.started_game.store(true, Ordering::Release);
+            engine_task(&admin, move |matching_engine| matching_engine.start_round(i, ROUND_DURATION)).await;
+
+            if let Waited::Aborted = wait(&admin, ROUND_DURATION, true, &mut commands).await {
+                return;
+            }
+
+            admin.started_game.store(false, Ordering::Release);
+            engine_task(&admin, |matching_engine| matching_engine.end_round()).await;
+        }
+
+        engine_task(&admin, |matching_engine| matching_engine.end_game()).await;
+        println!("{}[+] ADMIN |:| Game has ended{}", CL::Green.get(), CL::End.get());
+        admin.started_game.store(false, Ordering::Release);
+
+        // Clear all players to keep the testnet lightweight
+        engine_task(&admin, |matching_engine| matching_engine.delete_all_players()).await;
+
+        admin.playerid_playername_map.write().await.clear();
+        admin.rate_limiter.clear().await;
+        admin.replicas.broadcast(DirectoryChange::Cleared).await;
+    }
+}
+
+// the game loop carries on either way, the engine would have to be stuck for a whole ENGINE_TIMEOUT to miss one of these
+async fn engine_task<F>(admin: &AdminState, task: F)
+where
+    F: FnOnce(&mut MatchingEngine) + Send + 'static,
+{
+    if let Err(error) = admin.order_queue.call(task).await {
+        println!("{}[!] ADMIN |:| The game loop's update didn't reach the matching engine: {}{}", CL::Red.get(), error.message(), CL::End.get());
+    }
+}
+
+// sleeps through `duration` while answering the admin's commands, the round's clock stops while trading is paused
+async fn wait(
+    admin: &AdminState,
+    duration: Duration,
+    in_round: bool,
+    commands: &mut mpsc::UnboundedReceiver<GameRequest>,
+) -> Waited {
+    let mut remaining = duration;
+    let mut paused = false;
+    loop {
+        let waiting_since = Instant::now();
+        let command = tokio::select! {
+            _ = sleep(remaining), if !paused => return Waited::Done,
+            command = commands.recv() => command,
+        };
+        if !paused {
+            remaining = remaining.saturating_sub(waiting_since.elapsed());
+        }
+
+        let (command, reply) = match command {
+            Some(command) => command,
+            None => return Waited::Aborted,
+        };
+        let result = match command {
+            // the clock only stops / starts again once the engine's actually paused / resumed trading
+            GameCommand::Pause if in_round && !paused => {
+                let result = admin.order_queue.call(|matching_engine| matching_engine.pause()).await;
+                paused = result.is_ok();
+                result.map(|_| format!("Trading paused with {}s left in the round", remaining.as_secs()))
+            },
+            GameCommand::Resume if paused => {
+                let result = admin.order_queue.call(move |matching_engine| matching_engine.resume(remaining)).await;
+                paused = result.is_err();
+                result.map(|_| format!("Trading resumed, {}s left in the round", remaining.as_secs()))
+            },
+            GameCommand::EndRound if in_round => {
+                let _ = reply.send(Ok("Ending the round".to_string()));
+                return Waited::Done;
+            },
+            GameCommand::Abort => {
+                admin.started_game.store(false, Ordering::Release);
+                engine_task(admin, |matching_engine| matching_engine.abort_game()).await;
+                println!("{}[!] ADMIN |:| Game aborted{}", CL::Orange.get(), CL::End.get());
+                let _ = reply.send(Ok("Game aborted".to_string()));
+                return Waited::Aborted;
+            },
+            command => Err(ApiError::InvalidAction(format!("Can't {:?} right now (the game is {})", command, if in_round { "in a round" } else { "between rounds" }))),
+        };
+        let _ = reply.send(result);
+    }
+}
+
+
+// =-= Players =-= //
+
+// anyone who isn't registered yet gets registered w/ the usual testnet inventory, true if they're new
+async fn register_player(admin: &AdminState, player_name: &str) -> Result<bool, ApiError> {
+    let registered = admin.rate_limiter.register(player_name).await;
+    if registered {
+        admin.replicas.broadcast(DirectoryChange::Registered { player_name: player_name.to_string(), player_id: None }).await;
+        let player = PlayerId::intern(player_name);
+        admin.order_queue.call(move |matching_engine| matching_engine.add_new_player_with_inventory(player, Inventory { spades: 3, clubs: 3, diamonds: 3, hearts: 3 })).await?;
+    }
+    Ok(registered)
+}
+
+// out of the game, off the books, keys revoked and every session closed. false if there was nothing to remove
+async fn remove_player(admin: &AdminState, player_name: &str, reason: &str) -> Result<bool, ApiError> {
+    let in_game = match PlayerId::lookup(player_name) {
+        Some(player) => admin.order_queue.call(move |matching_engine| matching_engine.remove_player(player)).await?,
+        None => false, // never registered, so the engine's never heard of them
+    };
+    admin.playerid_playername_map.write().await.retain(|_, name| name != player_name);
+    admin.rate_limiter.remove(player_name).await;
+    let keys = admin.auth.revoke(player_name).await;
+    let sessions = close_player_sessions(&admin.player_ws_map, player_name, reason).await;
+    admin.replicas.broadcast(DirectoryChange::Removed { player_name: player_name.to_string(), reason: reason.to_string() }).await;
+
+    println!("{}[!] ADMIN |:| Removed {:?} | {} | {} keys revoked | {} sessions closed{}", CL::Orange.get(), player_name, reason, keys, sessions, CL::End.get());
+    Ok(in_game || keys > 0 || sessions > 0)
+}
+
+async fn kick(admin: &AdminState, player_names: &[String], ban: bool) -> Result<String, ApiError> {
+    let mut removed = Vec::new();
+    for player_name in player_names {
+        if ban {
+            let player_ids = admin.playerid_playername_map.read().await.iter()
+                .filter(|(_, name)| *name == player_name)
+                .map(|(player_id, _)| player_id.clone())
+                .collect::<Vec<String>>();
+            let mut banned = admin.banned.write().await;
+            banned.player_ids.extend(player_ids);
+            banned.player_names.insert(player_name.clone());
+        }
+
+        if remove_player(admin, player_name, if ban { "banned" } else { "kicked" }).await? {
+            removed.push(player_name.as_str());
+        }
+    }
+
+    match (ban, removed.is_empty()) {
+        (false, true) => Err(ApiError::UnknownPlayer),
+        (false, false) => Ok(format!("Kicked {}", removed.join(", "))),
+        (true, _) => Ok(format!("Banned {}", player_names.join(", "))),
+    }
+}
+
+// lets the names back in, their old playerids stay banned
+async fn unban(admin: &AdminState, player_names: &[String]) -> Result<String, ApiError> {
+    let mut banned = admin.banned.write().await;
+    let unbanned = player_names.iter().filter(|player_name| banned.player_names.remove(*player_name)).cloned().collect::<Vec<String>>();
+    match unbanned.is_empty() {
+        true => Err(ApiError::UnknownPlayer),
+        false => Ok(format!("Unbanned {}", unbanned.join(", "))),
+    }
+}
+
+// only these players are in the game from now on: everyone else is removed and /register_testnet is closed, an empty list opens it again
+async fn seat(admin: &AdminState, players: &str) -> Result<String, ApiError> {
+    if players.trim().is_empty() {
+        *admin.roster.write().await = None;
+        return Ok("Registration is open to everyone again".to_string());
+    }
+
+    let seated = player_list(players)?.into_iter().collect::<HashSet<String>>();
+    let banned = admin.banned.read().await.player_names.clone();
+    if let Some(player_name) = seated.iter().find(|player_name| banned.contains(*player_name)) {
+        return Err(ApiError::Unauthorized(format!("{} is banned", player_name)));
+    }
+    *admin.roster.write().await = Some(seated.clone());
+
+    let unseated = admin.engine_view.load().players.iter()
+        .map(|player| player.name().to_string())
+        .filter(|player_name| !seated.contains(player_name))
+        .collect::<Vec<String>>();
+    for player_name in &unseated {
+        remove_player(admin, player_name, "not seated").await?;
+    }
+    for player_name in &seated {
+        register_player(admin, player_name).await?;
+    }
+
+    Ok(format!("Seated {} players, removed {}", seated.len(), unseated.len()))
+}
+
+// answers w/ a JSON list of {player_name, api_key, api_secret}, the secrets can't be looked up again afterwards
+async fn issue_keys(admin: &AdminState, player_names: &[String]) -> Result<String, ApiError> {
+    let banned = admin.banned.read().await.player_names.clone();
+    if let Some(player_name) = player_names.iter().find(|player_name| banned.contains(*player_name)) {
+        return Err(ApiError::Unauthorized(format!("{} is banned", player_name)));
+    }
+
+    let mut issued = Vec::new();
+    for player_name in player_names {
+        register_player(admin, player_name).await?;
+        let (api_key, api_secret) = admin.auth.issue(player_name).await;
+        admin.replicas.broadcast(DirectoryChange::KeyIssued(PlayerKey { api_key: api_key.clone(), player_name: player_name.clone() })).await;
+        issued.push(serde_json::json!({ "player_name": player_name, "api_key": api_key, "api_secret": api_secret }));
+    }
+
+    Ok(serde_json::to_string(&issued).unwrap())
+}
+
+
+// every open session, websockets also show how far behind their outbound queue is
+async fn sessions(admin: &AdminState) -> Vec<serde_json::Value> {
+    admin.player_ws_map.lock().await.iter()
+        .map(|(session_id, connection)| serde_json::json!({
+            "session_id": session_id,
+            "player_name": connection.player_name,
+            "transport": match connection.sender {
+                Transport::WebSocket(_) => "websocket",
+                Transport::Sse(_) => "sse",
+                Transport::Uds(_) => "uds",
+            },
+            "rtt_us": connection.rtt.map(|rtt| rtt.as_micros() as u64),
+            "outbound": connection.sender.websocket().map(|outbox| outbox.stats()),
+        }))
+        .collect()
+}
+
+// the engine's full state + who's connected, banned and seated
+async fn dump_state(admin: &AdminState) -> Result<String, ApiError> {
+    let mut state = admin.order_queue.call(|matching_engine| matching_engine.dump_state()).await?;
+    let sessions = sessions(admin).await;
+    let banned = admin.banned.read().await;
+
+    state["trading_open"] = admin.started_game.load(Ordering::Acquire).into();
+    state["sessions"] = sessions.into();
+    state["banned_players"] = banned.player_names.iter().cloned().collect::<Vec<String>>().into();
+    state["banned_player_ids"] = banned.player_ids.len().into();
+    state["seated_players"] = admin.roster.read().await.as_ref().map(|roster| roster.iter().cloned().collect::<Vec<String>>()).into();
+    state["gateways"] = admin.replicas.connected().await.into();
+
+    Ok(state.to_string())
+}
+
+
+fn generate_random_player_name() -> String {
+    let first_word = random_word::gen_len(5, random_word::Lang::En).unwrap();
+    let first_word = format!("{}{}", first_word.chars().next().unwrap().to_uppercase().collect::<String>(), &first_word[1..]);
+
+    let second_word = random_word::gen_len(5, random_word::Lang::En).unwrap();
+    let second_word = format!("{}{}", second_word.chars().next().unwrap().to_uppercase().collect::<String>(), &second_word[1..]);
+
+    format!("{}{}", first_word, second_word)
+}
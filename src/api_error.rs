@@ -0,0 +1,188 @@
+use super::{HTTPResponse, SchemaVersion, parse_body};
+use super::rate_limit::RateLimitStatus;
+use actix_web::error::JsonPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+
+// =-= API Errors =-= //
+
+// Every way a REST request can fail. v1 (the original paths) still gets the old double-encoded {"status", "message"} w/ a 200
+// for everything, the same handlers mounted under /v2 answer w/ a real status code and a plain JSON object instead
+#[derive(Debug, Clone, Serialize, Deserialize)] // serde only for the engine link, clients never see this as is
+pub enum ApiError {
+    MissingHeader(String), // message differs per endpoint
+    ParseError(String),
+    InvalidCard,
+    InvalidDirection,
+    InvalidPrice,
+    InvalidAction(String),
+    Unauthorized(String),
+    UnknownPlayer,
+    NoGame,
+    GameAlreadyStarted,
+    Rejected { code: String, message: String }, // the matching engine refused the order (INSUFFICIENT_FUNDS, NO_INVENTORY, SELF_TRADE, ..)
+    ReplayUnavailable(String),
+    RateLimit(RateLimitStatus),
+    TooManySessions(String),
+    EngineUnavailable,
+    Busy(usize), // how many orders were queued
+    EngineTimeout(u64), // ms waited
+}
+
+impl ApiError {
+    // stable, machine readable, same as the v1 `status` wherever v1 had one
+    pub fn code(&self) -> &str {
+        match self {
+            ApiError::MissingHeader(_) => "MISSING_HEADER",
+            ApiError::ParseError(_) => "PARSE_ERROR",
+            ApiError::InvalidCard => "INVALID_CARD",
+            ApiError::InvalidDirection => "INVALID_DIRECTION",
+            ApiError::InvalidPrice => "INVALID_PRICE",
+            ApiError::InvalidAction(_) => "INVALID_ACTION",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::UnknownPlayer => "UNKNOWN_PLAYER",
+            ApiError::NoGame => "NO_GAME",
+            ApiError::GameAlreadyStarted => "GAME_ALREADY_STARTED",
+            ApiError::Rejected { code, .. } => code,
+            ApiError::ReplayUnavailable(_) => "REPLAY_UNAVAILABLE",
+            ApiError::RateLimit(_) => "RATE_LIMIT",
+            ApiError::TooManySessions(_) => "TOO_MANY_SESSIONS",
+            ApiError::EngineUnavailable => "ENGINE_UNAVAILABLE",
+            ApiError::Busy(_) => "BUSY",
+            ApiError::EngineTimeout(_) => "ENGINE_TIMEOUT",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingHeader(_) | ApiError::ParseError(_) | ApiError::InvalidCard | ApiError::InvalidDirection
+            | ApiError::InvalidPrice | ApiError::InvalidAction(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::UnknownPlayer => StatusCode::NOT_FOUND,
+            ApiError::Rejected { code, .. } if code == "UNKNOWN_PLAYER" => StatusCode::NOT_FOUND,
+            ApiError::NoGame | ApiError::GameAlreadyStarted | ApiError::Rejected { .. } | ApiError::ReplayUnavailable(_) => StatusCode::CONFLICT,
+            ApiError::RateLimit(_) | ApiError::TooManySessions(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::EngineUnavailable | ApiError::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::EngineTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::MissingHeader(message)
+            | ApiError::ParseError(message)
+            | ApiError::InvalidAction(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Rejected { message, .. }
+            | ApiError::ReplayUnavailable(message)
+            | ApiError::TooManySessions(message) => message.clone(),
+            ApiError::InvalidCard => "For the card, please send either `spade`, `club`, `diamond`, or `heart`".to_string(),
+            ApiError::InvalidDirection => "For the direction, please send either `buy` or `sell`".to_string(),
+            ApiError::InvalidPrice => "For the price, please send a number between 0 and 99".to_string(),
+            ApiError::UnknownPlayer => "Player name not found. Have you sent a post to /register_testnet?".to_string(),
+            ApiError::NoGame => "Game hasn't started yet. Sit tight and make sure your websocket connection is up and connected".to_string(),
+            ApiError::GameAlreadyStarted => "Game already started".to_string(),
+            ApiError::RateLimit(status) => format!("Settle down there mate, you're out of rate limit tokens. Please wait {}ms before sending this again", status.retry_after_ms),
+            ApiError::EngineUnavailable => "Couldn't send order to matching engine".to_string(),
+            ApiError::Busy(depth) => format!("The matching engine is busy ({} orders queued), please try again in a moment", depth),
+            ApiError::EngineTimeout(timeout_ms) => format!("The matching engine didn't answer within {}ms, check your open orders before sending it again", timeout_ms),
+        }
+    }
+
+    // the engine answers every order w/ an HTTPResponse, anything but SUCCESS is a rejection
+    pub fn from_engine(response: HTTPResponse) -> Result<String, ApiError> {
+        match response.status.as_str() {
+            "SUCCESS" => Ok(response.message),
+            _ => Err(ApiError::Rejected { code: response.status, message: response.message }),
+        }
+    }
+}
+
+impl From<ApiError> for HTTPResponse {
+    fn from(error: ApiError) -> Self {
+        let status = match &error {
+            ApiError::EngineUnavailable => "ERROR".to_string(), // what v1 has always sent for it
+            error => error.code().to_string(),
+        };
+        HTTPResponse { status, message: error.message() }
+    }
+}
+
+
+// =-= Rendering =-= //
+
+#[derive(Serialize)]
+struct ApiResponse<'a> {
+    code: &'a str, // "SUCCESS" or the error's code
+    message: String,
+    request_id: String,
+}
+
+// handlers are mounted both at the root (v1) and under /v2, the path tells us which one the client asked for
+pub fn api_version(req: &HttpRequest) -> SchemaVersion {
+    match req.path().starts_with("/v2/") {
+        true => SchemaVersion::V2,
+        false => SchemaVersion::V1,
+    }
+}
+
+// echoes the client's X-Request-ID if it sent a sane one, otherwise makes one up
+pub fn request_id(req: &HttpRequest) -> String {
+    match req.headers().get("X-Request-ID").and_then(|request_id| request_id.to_str().ok()) {
+        Some(request_id) if !request_id.is_empty() && request_id.len() <= 64 => request_id.to_string(),
+        _ => format!("{:016x}", rand::random::<u64>()),
+    }
+}
+
+// v1 used to read the body w/ actix's Json extractor, so a wrong content type or bad JSON was a plain 400 before the
+// handler even ran. /v2 gets the typed PARSE_ERROR instead, once the rest of the request checks out
+pub fn parse_json<T: DeserializeOwned>(req: &HttpRequest, body: &[u8]) -> Result<Result<T, ApiError>, HttpResponse> {
+    match api_version(req) {
+        SchemaVersion::V1 => {
+            let is_json = matches!(req.mime_type(), Ok(Some(mime)) if mime.subtype() == "json" || mime.suffix().is_some_and(|suffix| suffix == "json"));
+            if !is_json {
+                return Err(JsonPayloadError::ContentType.error_response());
+            }
+            serde_json::from_slice::<T>(body)
+                .map(Ok)
+                .map_err(|e| JsonPayloadError::Deserialize(e).error_response())
+        },
+        SchemaVersion::V2 => Ok(parse_body::<T>(body)),
+    }
+}
+
+pub fn respond(req: &HttpRequest, result: Result<String, ApiError>) -> HttpResponse {
+    let rate_limit = match &result {
+        Err(ApiError::RateLimit(status)) => Some(*status),
+        _ => None,
+    };
+
+    let response = match api_version(req) {
+        SchemaVersion::V1 => {
+            let response = match result {
+                Ok(message) => HTTPResponse { status: "SUCCESS".to_string(), message },
+                Err(error) => HTTPResponse::from(error),
+            };
+            let serialized_response = serde_json::to_string(&response).unwrap();
+            HttpResponse::Ok().json(serialized_response)
+        },
+        SchemaVersion::V2 => {
+            let request_id = request_id(req);
+            let (status_code, response) = match &result {
+                Ok(message) => (StatusCode::OK, ApiResponse { code: "SUCCESS", message: message.clone(), request_id: request_id.clone() }),
+                Err(error) => (error.status_code(), ApiResponse { code: error.code(), message: error.message(), request_id: request_id.clone() }),
+            };
+            HttpResponse::build(status_code)
+                .insert_header(("X-Request-ID", request_id))
+                .json(response)
+        }
+    };
+
+    match rate_limit {
+        Some(status) => status.apply(response),
+        None => response,
+    }
+}
@@ -0,0 +1,354 @@
+use super::CL;
+use super::api_error::ApiError;
+use super::link::{EngineLink, LinkReply, LinkRequest, CHECK_TIMEOUT};
+use actix_web::HttpRequest;
+use actix_web::http::header::HeaderMap;
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+
+
+// =-= Signed Requests =-= //
+
+// Every signed request carries an API key, a unix timestamp (ms), a nonce and
+// signature = hex(HMAC-SHA256(key = hex(sha256(secret)), "{timestamp}\n{nonce}\n{method}\n{path}\n{body}"))
+// A player's secret is hex(HMAC-SHA256(master key, api key)), so all that's kept per key is who it belongs to and the signing key
+// is worked out again from the master key whenever a request comes in. The master key never leaves memory, it's derived from
+// LINK_SECRET so the engine + every gateway come up w/ the same one (random for a process without a link). A leaked key store
+// (or the directory the engine sends its gateways) can't sign a thing, the master key / LINK_SECRET can sign for everyone
+
+pub const RECV_WINDOW_MS: u64 = 5_000; // how far the timestamp can be from the server's clock (either way)
+const NONCE_PRUNE_INTERVAL_MS: u64 = 1_000;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    Player(String), // player name
+    Admin,
+}
+
+// a player's key the way the engine hands it to the gateways (see link.rs), they derive the signing key themselves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerKey {
+    pub api_key: String,
+    pub player_name: String,
+}
+
+// the four signing fields, from headers (REST / SSE) or the message itself (websocket subscribe + UDS)
+pub struct SignedRequest<'a> {
+    pub api_key: &'a str,
+    pub timestamp: u64, // unix ms, signed as a plain decimal
+    pub nonce: &'a str,
+    pub signature: &'a str,
+}
+
+impl<'a> SignedRequest<'a> {
+    // None when there's no X-API-KEY at all (an unsigned request), Err when it's there but the rest isn't
+    pub fn from_headers(headers: &'a HeaderMap) -> Option<Result<Self, ApiError>> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let api_key = header("X-API-KEY")?;
+        match (header("X-TIMESTAMP").and_then(|timestamp| timestamp.parse::<u64>().ok()), header("X-NONCE"), header("X-SIGNATURE")) {
+            (Some(timestamp), Some(nonce), Some(signature)) => Some(Ok(Self { api_key, timestamp, nonce, signature })),
+            _ => Some(Err(ApiError::MissingHeader("Signed requests need 'X-API-KEY', 'X-TIMESTAMP' (unix ms), 'X-NONCE' and 'X-SIGNATURE' headers".to_string()))),
+        }
+    }
+
+    pub fn from_fields(api_key: &'a Option<String>, timestamp: Option<u64>, nonce: &'a Option<String>, signature: &'a Option<String>) -> Option<Result<Self, ApiError>> {
+        let api_key = api_key.as_deref()?;
+        match (timestamp, nonce, signature) {
+            (Some(timestamp), Some(nonce), Some(signature)) => Some(Ok(Self { api_key, timestamp, nonce, signature })),
+            _ => Some(Err(ApiError::ParseError("Signed messages need 'api_key', 'timestamp' (unix ms), 'nonce' and 'signature'".to_string()))),
+        }
+    }
+}
+
+
+pub struct Auth {
+    master_key: hmac::Key, // every player's secret comes from this + their api key
+    player_keys: RwLock<HashMap<String, String>>, // api key -> player name
+    admin_key: Option<(String, hmac::Key)>, // ADMIN_API_KEY + the signing key from ADMIN_API_SECRET, configured so never derived
+    nonces: Nonces,
+    pub require_signed: bool, // false keeps the old `playerid` header / field working alongside signed requests
+    admin_id_hash: Option<digest::Digest>, // legacy `adminid` header, only if ADMIN_ID was set
+}
+
+// where a signed request's nonce is checked, a gateway asks the engine so a request can't be replayed on another gateway
+enum Nonces {
+    Local(Mutex<SeenNonces>),
+    Engine(Arc<EngineLink>),
+}
+
+// a nonce only has to be remembered while its timestamp is inside the window, after that the timestamp check rejects it anyway
+struct SeenNonces {
+    nonces: HashMap<(String, String), u64>, // (api key, nonce) -> timestamp
+    last_prune: u64,
+}
+
+impl Auth {
+    // no master key (no LINK_SECRET) gets a random one, the keys only have to work in this process then
+    // `engine` is the link on a gateway, its nonces are checked there
+    pub fn new(require_signed: bool, admin_id: Option<String>, admin_credentials: Option<(String, String)>, master_key: Option<hmac::Key>, engine: Option<Arc<EngineLink>>) -> Self {
+        Self {
+            master_key: master_key.unwrap_or_else(|| hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>())),
+            player_keys: RwLock::new(HashMap::new()),
+            admin_key: admin_credentials.map(|(api_key, secret)| (api_key, signing_key(&secret))),
+            nonces: match engine {
+                Some(engine) => Nonces::Engine(engine),
+                None => Nonces::Local(Mutex::new(SeenNonces { nonces: HashMap::new(), last_prune: 0 })),
+            },
+            require_signed,
+            admin_id_hash: admin_id.map(|admin_id| digest::digest(&digest::SHA256, admin_id.as_bytes())),
+        }
+    }
+
+    // (api key, secret) for the player, the secret is only ever returned here
+    pub async fn issue(&self, player_name: &str) -> (String, String) {
+        let api_key = to_hex(&rand::random::<[u8; 16]>());
+        let secret = self.player_secret(&api_key);
+        self.player_keys.write().await.insert(api_key.clone(), player_name.to_string());
+        println!("{}[+] AUTH |:| Issued an API key for {:?}{}", CL::Green.get(), player_name, CL::End.get());
+        (api_key, secret)
+    }
+
+    // the same api key always gets the same secret back, on every process w/ the same master key
+    fn player_secret(&self, api_key: &str) -> String {
+        to_hex(hmac::sign(&self.master_key, api_key.as_bytes()).as_ref())
+    }
+
+    // drops every key issued to the player, returns how many there were
+    pub async fn revoke(&self, player_name: &str) -> usize {
+        let mut player_keys = self.player_keys.write().await;
+        let before = player_keys.len();
+        player_keys.retain(|_, name| name != player_name);
+        before - player_keys.len()
+    }
+
+    // a key issued somewhere else (the engine, when this is a gateway)
+    pub async fn import(&self, player_key: PlayerKey) {
+        self.player_keys.write().await.insert(player_key.api_key, player_key.player_name);
+    }
+
+    // every player's key, the admin's never leave the process that was configured w/ them
+    pub async fn player_keys(&self) -> Vec<PlayerKey> {
+        self.player_keys.read().await.iter()
+            .map(|(api_key, player_name)| PlayerKey { api_key: api_key.clone(), player_name: player_name.clone() })
+            .collect()
+    }
+
+    // exactly these player keys from now on, the admin's stays
+    pub async fn replace_player_keys(&self, player_keys: Vec<PlayerKey>) {
+        *self.player_keys.write().await = player_keys.into_iter().map(|player_key| (player_key.api_key, player_key.player_name)).collect();
+    }
+
+    pub async fn verify(&self, request: &SignedRequest<'_>, method: &str, path: &str, body: &[u8]) -> Result<Identity, ApiError> {
+        let now = now_ms();
+        let timestamp = request.timestamp;
+        if timestamp.abs_diff(now) > RECV_WINDOW_MS {
+            return Err(ApiError::Unauthorized(format!("The timestamp is more than {}ms off the server's clock ({})", RECV_WINDOW_MS, now)));
+        }
+        if request.nonce.is_empty() || request.nonce.len() > 64 {
+            return Err(ApiError::Unauthorized("The nonce should be 1-64 characters".to_string()));
+        }
+
+        let (identity, key) = match &self.admin_key {
+            Some((api_key, key)) if api_key == request.api_key => (Identity::Admin, key.clone()),
+            _ => match self.player_keys.read().await.get(request.api_key) {
+                Some(player_name) => (Identity::Player(player_name.clone()), signing_key(&self.player_secret(request.api_key))),
+                None => return Err(ApiError::Unauthorized("Unknown API key".to_string())),
+            },
+        };
+        let signature = from_hex(request.signature).ok_or(ApiError::Unauthorized("The signature should be hex".to_string()))?;
+        let prehash = prehash(request.timestamp, request.nonce, method, path, body);
+        if hmac::verify(&key, &prehash, &signature).is_err() {
+            return Err(ApiError::Unauthorized("Invalid signature".to_string()));
+        }
+
+        // only checked once the signature is good so nobody can burn someone else's nonces
+        self.check_nonce(request.api_key, request.nonce, timestamp).await?;
+        Ok(identity)
+    }
+
+    // Err if the nonce was already used w/ this key (on this process, or on any gateway if it's the engine's)
+    pub async fn check_nonce(&self, api_key: &str, nonce: &str, timestamp: u64) -> Result<(), ApiError> {
+        let seen_nonces = match &self.nonces {
+            Nonces::Local(seen_nonces) => seen_nonces,
+            Nonces::Engine(engine) => {
+                let request = LinkRequest::Nonce { api_key: api_key.to_string(), nonce: nonce.to_string(), timestamp };
+                return match engine.request(request, CHECK_TIMEOUT).await? {
+                    LinkReply::Nonce(result) => result,
+                    _ => Err(ApiError::EngineUnavailable),
+                };
+            },
+        };
+
+        // checked again on this clock (a gateway has its own), so nothing inside the window here has been pruned yet
+        let now = now_ms();
+        if timestamp.abs_diff(now) > RECV_WINDOW_MS {
+            return Err(ApiError::Unauthorized(format!("The timestamp is more than {}ms off the server's clock ({})", RECV_WINDOW_MS, now)));
+        }
+        let mut seen_nonces = seen_nonces.lock().await;
+        if now.saturating_sub(seen_nonces.last_prune) >= NONCE_PRUNE_INTERVAL_MS {
+            seen_nonces.nonces.retain(|_, timestamp| timestamp.abs_diff(now) <= RECV_WINDOW_MS);
+            seen_nonces.last_prune = now;
+        }
+        if seen_nonces.nonces.insert((api_key.to_string(), nonce.to_string()), timestamp).is_some() {
+            return Err(ApiError::Unauthorized("Nonce already used, please send a fresh one with every request".to_string()));
+        }
+        Ok(())
+    }
+
+    // Some(identity) for a good signed request, None for an unsigned one (only let through while signing isn't required)
+    pub async fn authenticate(&self, signed_request: Option<Result<SignedRequest<'_>, ApiError>>, method: &str, path: &str, body: &[u8]) -> Result<Option<Identity>, ApiError> {
+        match signed_request {
+            Some(signed_request) => Ok(Some(self.verify(&signed_request?, method, path, body).await?)),
+            None if self.require_signed => Err(ApiError::Unauthorized("Only signed requests are accepted, please send 'X-API-KEY', 'X-TIMESTAMP', 'X-NONCE' and 'X-SIGNATURE' (or the same fields in the message)".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    // REST + SSE sign the method, the path (w/ the query string if there is one) and the raw body
+    pub async fn authenticate_http(&self, req: &HttpRequest, body: &[u8]) -> Result<Option<Identity>, ApiError> {
+        let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        self.authenticate(SignedRequest::from_headers(req.headers()), req.method().as_str(), path, body).await
+    }
+
+    pub fn check_admin_id(&self, admin_id: &str) -> bool {
+        match &self.admin_id_hash {
+            Some(admin_id_hash) => digest::digest(&digest::SHA256, admin_id.as_bytes()).as_ref() == admin_id_hash.as_ref(),
+            None => false,
+        }
+    }
+}
+
+
+fn prehash(timestamp: u64, nonce: &str, method: &str, path: &str, body: &[u8]) -> Vec<u8> {
+    let mut prehash = format!("{}\n{}\n{}\n{}\n", timestamp, nonce, method, path).into_bytes();
+    prehash.extend_from_slice(body);
+    prehash
+}
+
+// what the client signs with, hex(sha256(secret))
+fn signing_key(secret: &str) -> hmac::Key {
+    let hashed_secret = to_hex(digest::digest(&digest::SHA256, secret.as_bytes()).as_ref());
+    hmac::Key::new(hmac::HMAC_SHA256, hashed_secret.as_bytes())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as u64).unwrap_or_default()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkSecret;
+
+    // what a client does w/ the secret it got back from /register_testnet
+    fn sign(secret: &str, timestamp: u64, nonce: &str, method: &str, path: &str, body: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, to_hex(digest::digest(&digest::SHA256, secret.as_bytes()).as_ref()).as_bytes());
+        to_hex(hmac::sign(&key, format!("{}\n{}\n{}\n{}\n{}", timestamp, nonce, method, path, body).as_bytes()).as_ref())
+    }
+
+    fn auth(master_key: Option<hmac::Key>) -> Auth {
+        Auth::new(true, None, None, master_key, None)
+    }
+
+    async fn verify(auth: &Auth, api_key: &str, secret: &str, timestamp: u64, nonce: &str, body: &str) -> Result<Identity, ApiError> {
+        let signature = sign(secret, timestamp, nonce, "POST", "/order", body);
+        let request = SignedRequest { api_key, timestamp, nonce, signature: &signature };
+        auth.verify(&request, "POST", "/order", body.as_bytes()).await
+    }
+
+    fn rejected(result: Result<Identity, ApiError>, reason: &str) {
+        match result {
+            Err(ApiError::Unauthorized(message)) => assert!(message.contains(reason), "{:?} should say {:?}", message, reason),
+            other => panic!("expected an Unauthorized w/ {:?}, got {:?}", reason, other),
+        }
+    }
+
+    const BODY: &str = r#"{"card":"spade","price":5,"direction":"buy"}"#;
+
+    #[tokio::test]
+    async fn good_signature_is_the_player() {
+        let auth = auth(None);
+        let (api_key, secret) = auth.issue("AuthTestPlayer").await;
+        let identity = verify(&auth, &api_key, &secret, now_ms(), "nonce-1", BODY).await.unwrap();
+        assert_eq!(identity, Identity::Player("AuthTestPlayer".to_string()));
+    }
+
+    #[tokio::test]
+    async fn tampered_body_is_rejected() {
+        let auth = auth(None);
+        let (api_key, secret) = auth.issue("AuthTestPlayer").await;
+        let timestamp = now_ms();
+        let signature = sign(&secret, timestamp, "nonce-1", "POST", "/order", BODY);
+        let request = SignedRequest { api_key: &api_key, timestamp, nonce: "nonce-1", signature: &signature };
+
+        let tampered = BODY.replace("\"price\":5", "\"price\":50");
+        rejected(auth.verify(&request, "POST", "/order", tampered.as_bytes()).await, "Invalid signature");
+        rejected(auth.verify(&request, "POST", "/cancel", BODY.as_bytes()).await, "Invalid signature");
+        rejected(verify(&auth, &api_key, "not the secret", timestamp, "nonce-2", BODY).await, "Invalid signature");
+
+        // a bad signature doesn't burn the nonce, the real request still goes through
+        assert!(auth.verify(&request, "POST", "/order", BODY.as_bytes()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_timestamp_is_rejected() {
+        let auth = auth(None);
+        let (api_key, secret) = auth.issue("AuthTestPlayer").await;
+        let now = now_ms();
+        rejected(verify(&auth, &api_key, &secret, now - RECV_WINDOW_MS - 1_000, "nonce-1", BODY).await, "timestamp");
+        rejected(verify(&auth, &api_key, &secret, now + RECV_WINDOW_MS + 1_000, "nonce-2", BODY).await, "timestamp");
+        assert!(verify(&auth, &api_key, &secret, now - RECV_WINDOW_MS / 2, "nonce-3", BODY).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reused_nonce_is_rejected() {
+        let auth = auth(None);
+        let (api_key, secret) = auth.issue("AuthTestPlayer").await;
+        let (other_api_key, other_secret) = auth.issue("AuthTestOther").await;
+        let timestamp = now_ms();
+
+        assert!(verify(&auth, &api_key, &secret, timestamp, "nonce-1", BODY).await.is_ok());
+        rejected(verify(&auth, &api_key, &secret, timestamp, "nonce-1", BODY).await, "Nonce already used");
+        rejected(verify(&auth, &api_key, &secret, timestamp + 1, "nonce-1", BODY).await, "Nonce already used");
+
+        // nonces are per key
+        assert!(verify(&auth, &other_api_key, &other_secret, timestamp, "nonce-1", BODY).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn same_link_secret_derives_the_same_secrets() {
+        let link_secret = LinkSecret::new("a link secret both sides share").unwrap();
+        let engine = auth(Some(link_secret.player_master_key()));
+        let gateway = auth(Some(link_secret.player_master_key()));
+
+        // the engine issues, the gateway only gets the api key + player name, the client signs w/ what the engine gave it
+        let (api_key, secret) = engine.issue("AuthTestPlayer").await;
+        gateway.import(PlayerKey { api_key: api_key.clone(), player_name: "AuthTestPlayer".to_string() }).await;
+        assert_eq!(gateway.player_secret(&api_key), secret);
+        let identity = verify(&gateway, &api_key, &secret, now_ms(), "nonce-1", BODY).await.unwrap();
+        assert_eq!(identity, Identity::Player("AuthTestPlayer".to_string()));
+
+        // a process w/ another master key can't
+        let stranger = auth(Some(LinkSecret::new("some other link secret entirely").unwrap().player_master_key()));
+        stranger.import(PlayerKey { api_key: api_key.clone(), player_name: "AuthTestPlayer".to_string() }).await;
+        assert_ne!(stranger.player_secret(&api_key), secret);
+        rejected(verify(&stranger, &api_key, &secret, now_ms(), "nonce-1", BODY).await, "Invalid signature");
+    }
+}
@@ -0,0 +1,220 @@
+use super::CL;
+use super::order_queue::BOOK_STATE_INTERVAL;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::Duration;
+
+
+// =-= Config =-= //
+
+// Where the exchange listens, the starting balance + book interval, and how its three threads are laid out (which core each one
+// is pinned to + what kind of runtime it gets). Everything has a default below, `--config <file>` loads a JSON file w/ any of
+// the fields, BIND_HOST still beats the file and every field has a flag that beats both (see USAGE)
+// Everything else is still set through env vars
+
+pub const BIND_HOST: &str = "127.0.0.1";
+pub const REST_PORT: u16 = 8090;
+pub const WS_PORT: u16 = 8080;
+pub const STARTING_BALANCE: i32 = 500;
+
+pub const USAGE: &str = "\
+Usage: figgie-tournament-testnet [--config <file.json>] [--<field> <value>]...
+
+Fields (the JSON file takes the same ones w/ underscores, e.g. \"rest_port\": 9090):
+  --bind-host <addr>             REST + websocket listen address (127.0.0.1, BIND_HOST also sets it)
+  --rest-port <port>             REST API + /stream (8090)
+  --ws-port <port>               websocket (8080)
+  --starting-balance <points>    every player's points at the start of a game (500)
+  --book-interval-ms <ms>        the engine sends out the books after this long without a message (5000)
+  --actix-workers <n|default>    REST API worker threads (default: one per core)
+  --network-core <id|none>       core for the REST / websocket / unix socket thread (0)
+  --hotpath-core <id|none>       core for the matching engine's thread (1)
+  --publisher-core <id|none>     core for the publisher thread (2)
+  --network-runtime <flavor>     multi_thread (default) or current_thread
+  --hotpath-runtime <flavor>     current_thread (default) or multi_thread
+  --publisher-runtime <flavor>   current_thread (default) or multi_thread
+  --on-pin-failure <policy>      fall_back (run the thread unpinned, default) or exit
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeFlavor {
+    CurrentThread,
+    MultiThread, // its workers inherit the thread's core
+}
+
+impl RuntimeFlavor {
+    pub fn parse(flavor: &str) -> Option<Self> {
+        match flavor {
+            "current_thread" => Some(RuntimeFlavor::CurrentThread),
+            "multi_thread" => Some(RuntimeFlavor::MultiThread),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> io::Result<tokio::runtime::Runtime> {
+        match self {
+            RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread().enable_all().build(),
+            RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread().enable_all().build(),
+        }
+    }
+}
+
+// what happens when a thread can't be pinned to its core (e.g. the box has fewer cores than that)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PinFailure {
+    FallBack, // says so + runs the thread wherever the OS puts it
+    Exit,
+}
+
+impl PinFailure {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "fall_back" => Some(PinFailure::FallBack),
+            "exit" => Some(PinFailure::Exit),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_host: String,
+    pub rest_port: u16,
+    pub ws_port: u16,
+    pub starting_balance: i32,
+    pub book_interval_ms: u64,
+    pub actix_workers: Option<usize>, // None leaves it to actix (one per core)
+    pub network_core: Option<usize>, // None doesn't pin the thread
+    pub hotpath_core: Option<usize>,
+    pub publisher_core: Option<usize>,
+    pub network_runtime: RuntimeFlavor, // multi-thread shouldn't be needed but I'm unsure of the Actix Web framework and its assumptions of the environment it's in
+    pub hotpath_runtime: RuntimeFlavor,
+    pub publisher_runtime: RuntimeFlavor,
+    pub on_pin_failure: PinFailure,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_host: BIND_HOST.to_string(),
+            rest_port: REST_PORT,
+            ws_port: WS_PORT,
+            starting_balance: STARTING_BALANCE,
+            book_interval_ms: BOOK_STATE_INTERVAL.as_millis() as u64,
+            actix_workers: None,
+            network_core: Some(0),
+            hotpath_core: Some(1),
+            publisher_core: Some(2),
+            network_runtime: RuntimeFlavor::MultiThread,
+            hotpath_runtime: RuntimeFlavor::CurrentThread,
+            publisher_runtime: RuntimeFlavor::CurrentThread,
+            on_pin_failure: PinFailure::FallBack,
+        }
+    }
+}
+
+impl Config {
+    // defaults < the --config file < BIND_HOST < the other flags
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        let mut flags = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("Unexpected argument {:?}", arg));
+            };
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), value.to_string()),
+                None => (flag.to_string(), args.next().ok_or(format!("--{} needs a value", flag))?.clone()),
+            };
+            flags.push((flag, value));
+        }
+
+        let mut config = match flags.iter().rev().find(|(flag, _)| flag == "config") {
+            Some((_, path)) => {
+                let file = std::fs::read_to_string(path).map_err(|e| format!("Failed to read the config file {:?}: {}", path, e))?;
+                serde_json::from_str::<Config>(&file).map_err(|e| format!("Failed to parse the config file {:?}: {}", path, e))?
+            },
+            None => Config::default(),
+        };
+        if let Ok(bind_host) = std::env::var("BIND_HOST") {
+            config.bind_host = bind_host;
+        }
+        for (flag, value) in flags.iter().filter(|(flag, _)| flag != "config") {
+            config.set(flag, value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // what the flags already refuse, for values that came from the file (+ anything that only breaks in combination)
+    pub fn validate(&self) -> Result<(), String> {
+        if self.actix_workers == Some(0) {
+            return Err("actix_workers should be at least 1 (or left out for the default)".to_string());
+        }
+        if self.rest_port == self.ws_port {
+            return Err(format!("rest_port + ws_port are both {}, they need their own ports", self.rest_port));
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid value {:?} for --{}", value, flag);
+        let number = |value: &str| value.parse::<u64>().map_err(|_| invalid());
+        let core = |value: &str| match value {
+            "none" => Ok(None),
+            core => core.parse::<usize>().map(Some).map_err(|_| invalid()),
+        };
+        let flavor = |value: &str| RuntimeFlavor::parse(value).ok_or_else(invalid);
+
+        match flag {
+            "bind-host" => self.bind_host = value.to_string(),
+            "rest-port" => self.rest_port = value.parse().map_err(|_| invalid())?,
+            "ws-port" => self.ws_port = value.parse().map_err(|_| invalid())?,
+            "starting-balance" => self.starting_balance = value.parse().map_err(|_| invalid())?,
+            "book-interval-ms" => self.book_interval_ms = number(value)?,
+            "actix-workers" => self.actix_workers = match value {
+                "default" => None,
+                workers => Some(workers.parse::<usize>().ok().filter(|workers| *workers > 0).ok_or_else(invalid)?),
+            },
+            "network-core" => self.network_core = core(value)?,
+            "hotpath-core" => self.hotpath_core = core(value)?,
+            "publisher-core" => self.publisher_core = core(value)?,
+            "network-runtime" => self.network_runtime = flavor(value)?,
+            "hotpath-runtime" => self.hotpath_runtime = flavor(value)?,
+            "publisher-runtime" => self.publisher_runtime = flavor(value)?,
+            "on-pin-failure" => self.on_pin_failure = PinFailure::parse(value).ok_or_else(invalid)?,
+            _ => return Err(format!("Unknown flag --{}", flag)),
+        }
+        Ok(())
+    }
+
+    pub fn book_interval(&self) -> Duration {
+        Duration::from_millis(self.book_interval_ms.max(1))
+    }
+
+    // pins the thread it's called from, a thread that can't be pinned never just quietly does nothing
+    pub fn pin(&self, thread: &str, core: Option<usize>) {
+        let Some(core) = core else {
+            println!("[+] {} thread isn't pinned to a core", thread);
+            return;
+        };
+        if core_affinity::set_for_current(core_affinity::CoreId { id: core }) {
+            println!("[+] {} thread pinned to core {}", thread, core);
+            return;
+        }
+
+        let available = core_affinity::get_core_ids().map(|cores| cores.len()).unwrap_or(0);
+        match self.on_pin_failure {
+            PinFailure::FallBack => {
+                println!("{}[!] Failed to pin the {} thread to core {} ({} cores available), running it unpinned{}", CL::Orange.get(), thread, core, available, CL::End.get());
+            },
+            PinFailure::Exit => {
+                println!("{}[!] Failed to pin the {} thread to core {} ({} cores available), exiting (--on-pin-failure exit){}", CL::Red.get(), thread, core, available, CL::End.get());
+                std::process::exit(1);
+            },
+        }
+    }
+}
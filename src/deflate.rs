@@ -0,0 +1,324 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+
+// permessage-deflate (RFC 7692) on top of tungstenite, which doesn't support it itself:
+// - outgoing messages are compressed before they're handed to tungstenite as raw frames w/ RSV1 set (see `WsSender`)
+// - incoming compressed frames are inflated by `InflateStream` underneath tungstenite, so it only ever sees plain frames
+// We always answer with server_no_context_takeover, every message is compressed on its own so the same message compresses the same for everyone
+
+const EMPTY_BLOCK: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+const MAX_INFLATED_MESSAGE: usize = 1024 * 1024; // client messages are tiny, anything bigger is a zip bomb
+const MAX_FRAME: usize = 16 * 1024 * 1024; // same as tungstenite's default max frame size
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeflateConfig {
+    pub level: u32, // 0-9
+    pub threshold: usize, // messages shorter than this (in bytes) go out uncompressed
+}
+
+
+// =-= Negotiation =-= //
+
+// `offers` is the client's Sec-WebSocket-Extensions, returns what to answer with or None to leave compression off
+pub fn negotiate(offers: &str) -> Option<&'static str> {
+    for offer in offers.split(',') {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next() != Some("permessage-deflate") {
+            continue;
+        }
+
+        // a smaller server window is the only thing we can't do (miniz always uses the full 32KiB window)
+        let acceptable = params.all(|param| {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            match name {
+                "server_no_context_takeover" | "client_no_context_takeover" | "client_max_window_bits" => true,
+                "server_max_window_bits" => value == Some("15"),
+                _ => false,
+            }
+        });
+        if acceptable {
+            return Some("permessage-deflate; server_no_context_takeover");
+        }
+    }
+    None
+}
+
+
+// =-= Compression =-= //
+
+// None when compressing doesn't make the message any smaller
+pub fn deflate_message(payload: &[u8], level: u32) -> Option<Vec<u8>> {
+    let mut compress = Compress::new(Compression::new(level), false);
+    let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress.compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync).ok()?;
+        if compress.total_in() as usize == payload.len() && output.len() < output.capacity() {
+            break;
+        }
+        output.reserve(output.capacity().max(64));
+    }
+
+    // the sync flush always ends w/ an empty stored block, the spec has us drop it (the receiver adds it back)
+    if output.ends_with(&EMPTY_BLOCK) {
+        output.truncate(output.len() - EMPTY_BLOCK.len());
+    }
+    match output.len() < payload.len() {
+        true => Some(output),
+        false => None,
+    }
+}
+
+fn inflate_message(decompress: &mut Decompress, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = Vec::with_capacity(payload.len() + EMPTY_BLOCK.len());
+    input.extend_from_slice(payload);
+    input.extend_from_slice(&EMPTY_BLOCK);
+
+    let start = decompress.total_in();
+    let mut output = Vec::with_capacity(input.len() * 4);
+    loop {
+        let consumed = (decompress.total_in() - start) as usize;
+        let status = decompress.decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("failed to inflate a message: {:?}", e)))?;
+        if output.len() > MAX_INFLATED_MESSAGE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "inflated message is too big"));
+        }
+
+        let done = (decompress.total_in() - start) as usize == input.len() && output.len() < output.capacity();
+        if done || status == Status::StreamEnd {
+            return Ok(output);
+        }
+        output.reserve(output.capacity().max(64));
+    }
+}
+
+
+// =-= Bandwidth =-= //
+
+// payload bytes of the text messages sent to a websocket, before (raw) and after (wire) compression
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Bandwidth {
+    pub messages: u64,
+    pub compressed_messages: u64,
+    pub raw_bytes: u64,
+    pub wire_bytes: u64,
+}
+
+impl Bandwidth {
+    pub fn record(&mut self, raw_bytes: usize, wire_bytes: usize) {
+        self.messages += 1;
+        if wire_bytes < raw_bytes {
+            self.compressed_messages += 1;
+        }
+        self.raw_bytes += raw_bytes as u64;
+        self.wire_bytes += wire_bytes as u64;
+    }
+
+    pub fn add(&mut self, other: &Bandwidth) {
+        self.messages += other.messages;
+        self.compressed_messages += other.compressed_messages;
+        self.raw_bytes += other.raw_bytes;
+        self.wire_bytes += other.wire_bytes;
+    }
+}
+
+
+// =-= Inflate Stream =-= //
+
+// Sits between the socket and tungstenite. It passes everything through untouched until `enabled` is set (by the handshake
+// when the client negotiated compression), then it reassembles compressed messages (RSV1 on the first frame) and hands
+// tungstenite a single plain frame for each one instead. Control frames and uncompressed messages pass straight through
+pub struct InflateStream<S> {
+    inner: S,
+    enabled: Arc<AtomicBool>,
+    input: Vec<u8>, // read from the socket but not a complete frame yet
+    output: Vec<u8>, // ready for tungstenite
+    output_pos: usize,
+    message: Option<(u8, Vec<u8>)>, // (opcode, payload so far) of the compressed message being reassembled
+    decompress: Decompress,
+    eof: bool,
+}
+
+impl<S> InflateStream<S> {
+    pub fn new(inner: S, enabled: Arc<AtomicBool>) -> Self {
+        Self {
+            inner,
+            enabled,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_pos: 0,
+            message: None,
+            decompress: Decompress::new(false),
+            eof: false,
+        }
+    }
+
+    // moves every complete frame in `input` over to `output` (inflating compressed messages along the way)
+    fn process_input(&mut self) -> io::Result<()> {
+        while let Some(FrameHeader { header_len, payload_len, first_byte, mask }) = parse_frame_header(&self.input)? {
+            let total_len = header_len + payload_len;
+            if self.input.len() < total_len {
+                break;
+            }
+
+            let fin = first_byte & 0x80 != 0;
+            let rsv1 = first_byte & 0x40 != 0;
+            let opcode = first_byte & 0x0f;
+            let is_control = opcode & 0x08 != 0;
+
+            if !is_control && (rsv1 || (opcode == 0 && self.message.is_some())) {
+                let mut payload = self.input[header_len..total_len].to_vec();
+                if let Some(mask) = mask {
+                    for (i, byte) in payload.iter_mut().enumerate() {
+                        *byte ^= mask[i % 4];
+                    }
+                }
+
+                match &mut self.message {
+                    Some((_, message)) => message.extend_from_slice(&payload),
+                    None => self.message = Some((opcode, payload)),
+                }
+                if message_too_big(&self.message) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "compressed message is too big"));
+                }
+
+                if fin {
+                    if let Some((opcode, message)) = self.message.take() {
+                        let inflated = inflate_message(&mut self.decompress, &message)?;
+                        write_frame(&mut self.output, opcode, &inflated);
+                    }
+                }
+            } else {
+                self.output.extend_from_slice(&self.input[..total_len]);
+            }
+
+            self.input.drain(..total_len);
+        }
+        Ok(())
+    }
+}
+
+fn message_too_big(message: &Option<(u8, Vec<u8>)>) -> bool {
+    message.as_ref().is_some_and(|(_, message)| message.len() > MAX_INFLATED_MESSAGE)
+}
+
+struct FrameHeader {
+    header_len: usize,
+    payload_len: usize,
+    first_byte: u8, // FIN, RSV1-3 + opcode
+    mask: Option<[u8; 4]>,
+}
+
+// None if the header isn't all there yet
+fn parse_frame_header(input: &[u8]) -> io::Result<Option<FrameHeader>> {
+    if input.len() < 2 {
+        return Ok(None);
+    }
+
+    let masked = input[1] & 0x80 != 0;
+    let (length_len, payload_len) = match input[1] & 0x7f {
+        126 if input.len() >= 4 => (2, u16::from_be_bytes([input[2], input[3]]) as u64),
+        127 if input.len() >= 10 => (8, u64::from_be_bytes(input[2..10].try_into().unwrap_or_default())),
+        126 | 127 => return Ok(None),
+        length => (0, length as u64),
+    };
+    if payload_len > MAX_FRAME as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame is too big"));
+    }
+
+    let header_len = 2 + length_len + if masked { 4 } else { 0 };
+    if input.len() < header_len {
+        return Ok(None);
+    }
+    let mask = match masked {
+        true => Some([input[header_len - 4], input[header_len - 3], input[header_len - 2], input[header_len - 1]]),
+        false => None,
+    };
+    Ok(Some(FrameHeader { header_len, payload_len: payload_len as usize, first_byte: input[0], mask }))
+}
+
+// tungstenite insists on masked frames from clients, an all zero mask leaves the payload as is
+fn write_frame(output: &mut Vec<u8>, opcode: u8, payload: &[u8]) {
+    output.push(0x80 | opcode);
+    match payload.len() {
+        length if length < 126 => output.push(0x80 | length as u8),
+        length if length <= u16::MAX as usize => {
+            output.push(0x80 | 126);
+            output.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        length => {
+            output.push(0x80 | 127);
+            output.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    output.extend_from_slice(&[0, 0, 0, 0]);
+    output.extend_from_slice(payload);
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for InflateStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.enabled.load(Ordering::Acquire) {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if this.output_pos < this.output.len() {
+                let length = buf.remaining().min(this.output.len() - this.output_pos);
+                buf.put_slice(&this.output[this.output_pos..this.output_pos + length]);
+                this.output_pos += length;
+                if this.output_pos == this.output.len() {
+                    this.output.clear();
+                    this.output_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            this.process_input()?;
+            if !this.output.is_empty() {
+                continue;
+            }
+            if this.eof {
+                return Poll::Ready(Ok(())); // tungstenite sees the EOF (and any half frame left over is its problem)
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf) {
+                Poll::Ready(Ok(())) => {
+                    match chunk_buf.filled().is_empty() {
+                        true => this.eof = true,
+                        false => this.input.extend_from_slice(chunk_buf.filled()),
+                    }
+                },
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for InflateStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
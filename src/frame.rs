@@ -0,0 +1,50 @@
+use super::deflate::{self, DeflateConfig};
+use std::sync::{Arc, OnceLock};
+use tokio_tungstenite::tungstenite::{Bytes, Utf8Bytes};
+
+
+// =-= Shared Frames =-= //
+
+// A published message is serialized once into a SharedFrame and every session that gets it holds the same (refcounted) bytes,
+// tungstenite's payloads are refcounted too so they go into the socket's Message as is, fanning it out to N subscribers is N
+// pointer copies instead of N strings. The compressed payload is cached on the frame too, without context takeover it comes out
+// the same for every session that negotiated permessage-deflate
+
+#[derive(Debug, Clone)]
+pub struct SharedFrame(Arc<FrameData>);
+
+#[derive(Debug)]
+struct FrameData {
+    text: Utf8Bytes,
+    deflated: OnceLock<Option<Bytes>>, // None when compressing doesn't make it any smaller
+}
+
+impl SharedFrame {
+    pub fn new(text: String) -> Self {
+        Self(Arc::new(FrameData { text: Utf8Bytes::from(text), deflated: OnceLock::new() }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.text.as_str()
+    }
+
+    // cloning is a refcount bump, this is what goes out on sessions that didn't negotiate compression
+    pub fn text(&self) -> &Utf8Bytes {
+        &self.0.text
+    }
+
+    // compressed by the first session that asks, the rest get the same bytes
+    // (there's only ever one DeflateConfig, it's set once at startup for every session)
+    pub fn deflated(&self, deflate: DeflateConfig) -> Option<&Bytes> {
+        if self.0.text.len() < deflate.threshold {
+            return None;
+        }
+        self.0.deflated.get_or_init(|| deflate::deflate_message(self.0.text.as_bytes(), deflate.level).map(Bytes::from)).as_ref()
+    }
+}
+
+impl From<String> for SharedFrame {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
@@ -0,0 +1,33 @@
+// everything but the HTTP handlers + startup (those are in main.rs), split out so the benches can get at it too
+
+pub mod utils;
+pub use utils::*;
+
+pub mod models;
+pub use models::*;
+
+pub mod matching_engine;
+pub mod websocket;
+pub mod deflate;
+pub mod frame;
+pub mod sse;
+pub mod uds_gateway;
+pub mod multicast;
+pub mod publisher;
+pub mod outbound;
+pub mod tls;
+pub mod api_error;
+pub mod auth;
+pub mod rate_limit;
+pub mod ring;
+pub mod link;
+pub mod order_queue;
+pub mod sequencer;
+pub mod order_entry;
+pub mod admin;
+pub mod config;
+
+
+pub fn parse_body<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, api_error::ApiError> {
+    serde_json::from_slice::<T>(body).map_err(|e| api_error::ApiError::ParseError(format!("Failed to parse the JSON body: {}", e)))
+}
@@ -0,0 +1,943 @@
+use super::{Announcement, BookEntry, Card, CardBook, EndGamePointsUpdate, EndRoundUpdate, Event, GamePhase, Inventory, Order, PlayerId, RoundInfo, StatsUpdate, Trade, Update, AdminRequest, CL};
+use super::admin::{self, AdminState};
+use super::api_error::ApiError;
+use super::auth::{self, Auth, PlayerKey};
+use super::matching_engine::{EngineView, SharedReplayBuffer, SharedView};
+use super::order_queue::QueueStats;
+use super::publisher::EventSender;
+use super::rate_limit::{Endpoint, RateLimitStatus, RateLimiter};
+use super::sequencer::{OrderAnswer, Received};
+use super::websocket::{PlayerWsMap, close_all_sessions, close_player_sessions};
+use ring::hmac;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::time::{sleep, timeout, Duration};
+
+
+// =-= Engine Link =-= //
+
+// Lets the matching engine + the game run in one process (ROLE=engine) and the client facing servers in as many others as we
+// like (ROLE=gateway), over TCP or a unix socket. Frames are a u32 (big endian) length followed by that many bytes of JSON
+//
+// Both ends prove they have LINK_SECRET before anything else goes over it: the engine opens w/ a Challenge, the gateway's Hello
+// signs it (+ a nonce of its own) and the engine's Accepted signs that back. A peer that can't is dropped before it sees a thing
+//
+// gateway -> engine: a Hello w/ the last event it has, then orders, registrations, admin actions, queue stats, nonces + rate
+//                    limit charges (so they're shared by every gateway), each w/ an id the engine's Reply echoes (in any order)
+// engine -> gateway: everything the gateway needs to answer on its own: who's registered + their keys (in full after the
+//                    Hello, then every change), the engine's view, the trading flag and every event w/ the engine's seq
+//
+// The events are the sequenced part. A gateway keeps its own replay buffer + publisher, so it has to see every seq exactly once
+// and in order: the engine replays whatever the gateway missed right after the Hello (everything it has for a new gateway), the
+// gateway skips anything it's already seen and drops the link on a gap, which gets it replayed on the reconnect. An engine that
+// restarted (new epoch) or a gap the engine can no longer replay closes every session on the gateway so they resubscribe
+// A view always goes out before an event it includes, so the gateway keeps the same snapshot / from_seq guarantee as the engine
+//
+// A gateway that goes away just drops off the engine's list, the game carries on. One that can't reach the engine keeps its
+// sessions open and answers ENGINE_UNAVAILABLE until the link is back
+
+pub const ENGINE_LINK: &str = "127.0.0.1:8070"; // ENGINE_LINK to override, unix:<path> for a unix socket
+pub const ADMIN_TIMEOUT: Duration = Duration::from_secs(10); // admin actions can take a few trips through the engine (e.g. seating everyone)
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(1); // nonce checks + rate limit charges, the engine answers those without its queue
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024; // the directory + a full replay buffer go out as frames too
+const LINK_OUTBOUND_QUEUE: usize = 8192; // frames per gateway, one that falls this far behind gets cut off + catches up on the reconnect
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_LINK_SECRET_LEN: usize = 16;
+const VIEW_INTERVAL: Duration = Duration::from_millis(50); // a view that changed without an event (a player joining) goes out within this
+const RECONNECT_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_MAX: Duration = Duration::from_secs(2);
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    All, // engine + gateway in one process
+    Engine,
+    Gateway,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "all" => Some(Role::All),
+            "engine" => Some(Role::Engine),
+            "gateway" => Some(Role::Gateway),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LinkAddr {
+    Tcp(String),
+    Unix(String),
+}
+
+impl LinkAddr {
+    // "host:port" (or "tcp:host:port") / "unix:/path/to.sock"
+    pub fn parse(addr: &str) -> Self {
+        match addr.strip_prefix("unix:") {
+            Some(path) => LinkAddr::Unix(path.to_string()),
+            None => LinkAddr::Tcp(addr.strip_prefix("tcp:").unwrap_or(addr).to_string()),
+        }
+    }
+}
+
+impl fmt::Display for LinkAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkAddr::Tcp(addr) => write!(f, "tcp:{}", addr),
+            LinkAddr::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+
+// LINK_SECRET, shared by the engine + every gateway. Each side signs the other's nonce (+ its own, and which side it is so a
+// proof can't be bounced back). The players' secrets are derived from it as well, so nothing that can sign goes over the link
+#[derive(Clone)]
+pub struct LinkSecret(hmac::Key);
+
+impl LinkSecret {
+    pub fn new(secret: &str) -> Result<Self, String> {
+        if secret.len() < MIN_LINK_SECRET_LEN {
+            return Err(format!("LINK_SECRET should be at least {} characters", MIN_LINK_SECRET_LEN));
+        }
+        Ok(Self(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())))
+    }
+
+    // what Auth derives the players' secrets from, the same on the engine + every gateway
+    pub fn player_master_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, hmac::sign(&self.0, b"player keys").as_ref())
+    }
+
+    // `challenge` is the nonce of whoever checks the proof, `nonce` the prover's own
+    fn prove(&self, side: &str, challenge: &str, nonce: &str) -> String {
+        auth::to_hex(hmac::sign(&self.0, &transcript(side, challenge, nonce)).as_ref())
+    }
+
+    fn check(&self, side: &str, challenge: &str, nonce: &str, proof: &str) -> bool {
+        auth::from_hex(proof).is_some_and(|proof| hmac::verify(&self.0, &transcript(side, challenge, nonce), &proof).is_ok())
+    }
+}
+
+fn transcript(side: &str, challenge: &str, nonce: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", side, challenge, nonce).into_bytes()
+}
+
+fn new_nonce() -> String {
+    auth::to_hex(&rand::random::<[u8; 32]>())
+}
+
+
+// =-= Messages =-= //
+
+#[derive(Serialize, Deserialize)]
+enum ToEngine {
+    // the engine (epoch) + the last event this gateway has (0 for nothing), a nonce for the engine to sign + the proof for the challenge
+    Hello { epoch: Option<u64>, last_seq: u64, nonce: String, proof: String },
+    Request { id: u64, request: LinkRequest },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum LinkRequest {
+    Order(Order, Received), // stamped on the gateway, the engine's sequencer goes by that
+    Register { player_id: String }, // /register_testnet
+    Admin(AdminRequest), // already authenticated by the gateway
+    Stats,
+    Nonce { api_key: String, nonce: String, timestamp: u64 }, // a signed request's nonce, seen on any gateway is seen on all of them
+    Take { player_name: String, endpoint: Endpoint }, // a rate limit charge, every gateway takes from the engine's buckets
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum LinkReply {
+    Order(Result<OrderAnswer, ApiError>),
+    Register(Result<String, ApiError>), // the player's name
+    Admin(Result<String, ApiError>),
+    Stats(QueueStats),
+    Nonce(Result<(), ApiError>),
+    Take(Result<RateLimitStatus, ApiError>),
+}
+
+#[derive(Serialize, Deserialize)]
+enum FromEngine {
+    Challenge { nonce: String }, // the first frame on every link, nothing else goes out until the gateway's Hello signs it
+    Accepted { proof: String }, // the engine signing the gateway's nonce, right before the directory
+    Directory(Directory),
+    Change(DirectoryChange),
+    View(WireView),
+    Trading(bool),
+    Welcome { epoch: u64, seq: u64, oldest: Option<u64> }, // the engine's seq right now + the first seq of the replay that follows it
+    Event { seq: u64, event: WireEvent },
+    Reply { id: u64, reply: LinkReply },
+}
+
+// who's registered, the engine has the real thing and every gateway a copy
+#[derive(Serialize, Deserialize)]
+struct Directory {
+    players: Vec<String>,
+    player_ids: Vec<(String, String)>, // playerid -> player name
+    keys: Vec<PlayerKey>, // api key -> player, nothing to sign w/
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DirectoryChange {
+    Registered { player_name: String, player_id: Option<String> }, // seated players + the ones given keys don't have a playerid
+    Removed { player_name: String, reason: String }, // their sessions are closed w/ the reason
+    KeyIssued(PlayerKey),
+    Cleared, // after every game, keys stay
+}
+
+
+// =-= Wire Types =-= //
+
+// Events + views as the engine has them. The serde impls on the models are shaped for the clients, these just carry the data
+// (player names in place of PlayerIds, which get interned again on the other end)
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireBook {
+    bids: Vec<(usize, PlayerId)>,
+    asks: Vec<(usize, PlayerId)>,
+    last_trade: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireUpdate {
+    spades: WireBook,
+    clubs: WireBook,
+    diamonds: WireBook,
+    hearts: WireBook,
+    trade: Option<Trade>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum WireEvent {
+    Update(WireUpdate),
+    DealCards(HashMap<PlayerId, Inventory>),
+    EndRound {
+        card_count: Vec<(Card, usize)>,
+        player_inventories: HashMap<PlayerId, Inventory>,
+        player_points: HashMap<PlayerId, i32>,
+        goal_suit: Card,
+        common_suit: Card,
+    },
+    EndGame { player_points: HashMap<PlayerId, i32> },
+    Stats { round_trades: usize, round_volume: usize, player_points: HashMap<PlayerId, i32> },
+    Announcement(Announcement),
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireView {
+    seq: u64,
+    phase: GamePhase,
+    round: Option<RoundInfo>,
+    books: WireUpdate,
+    players: Vec<PlayerId>,
+    player_points: HashMap<PlayerId, i32>,
+    player_inventories: HashMap<PlayerId, Inventory>,
+}
+
+impl From<&CardBook> for WireBook {
+    fn from(book: &CardBook) -> Self {
+        Self {
+            bids: book.bids.iter().map(|entry| (entry.price, entry.player)).collect(),
+            asks: book.asks.iter().map(|entry| (entry.price, entry.player)).collect(),
+            last_trade: book.last_trade,
+        }
+    }
+}
+
+impl From<WireBook> for CardBook {
+    fn from(book: WireBook) -> Self {
+        Self {
+            bids: book.bids.into_iter().map(|(price, player)| BookEntry { price, player }).collect(),
+            asks: book.asks.into_iter().map(|(price, player)| BookEntry { price, player }).collect(),
+            last_trade: book.last_trade,
+        }
+    }
+}
+
+impl From<&Update> for WireUpdate {
+    fn from(update: &Update) -> Self {
+        Self {
+            spades: WireBook::from(&update.spades),
+            clubs: WireBook::from(&update.clubs),
+            diamonds: WireBook::from(&update.diamonds),
+            hearts: WireBook::from(&update.hearts),
+            trade: update.trade.clone(),
+        }
+    }
+}
+
+impl From<WireUpdate> for Update {
+    fn from(update: WireUpdate) -> Self {
+        Self {
+            spades: update.spades.into(),
+            clubs: update.clubs.into(),
+            diamonds: update.diamonds.into(),
+            hearts: update.hearts.into(),
+            trade: update.trade,
+        }
+    }
+}
+
+impl From<&Event> for WireEvent {
+    fn from(event: &Event) -> Self {
+        match event {
+            Event::Update(update) => WireEvent::Update(WireUpdate::from(update)),
+            Event::DealCards(player_inventories) => WireEvent::DealCards(player_inventories.clone()),
+            Event::EndRound(end_round) => WireEvent::EndRound {
+                card_count: end_round.card_count.iter().map(|(card, count)| (*card, *count)).collect(),
+                player_inventories: end_round.player_inventories.clone(),
+                player_points: end_round.player_points.clone(),
+                goal_suit: end_round.goal_suit,
+                common_suit: end_round.common_suit,
+            },
+            Event::EndGame(end_game) => WireEvent::EndGame { player_points: end_game.player_points.clone() },
+            Event::Stats(stats) => WireEvent::Stats { round_trades: stats.round_trades, round_volume: stats.round_volume, player_points: stats.player_points.clone() },
+            Event::Announcement(announcement) => WireEvent::Announcement(announcement.clone()),
+        }
+    }
+}
+
+impl From<WireEvent> for Event {
+    fn from(event: WireEvent) -> Self {
+        match event {
+            WireEvent::Update(update) => Event::Update(update.into()),
+            WireEvent::DealCards(player_inventories) => Event::DealCards(player_inventories),
+            WireEvent::EndRound { card_count, player_inventories, player_points, goal_suit, common_suit } => Event::EndRound(EndRoundUpdate {
+                card_count: card_count.into_iter().collect(),
+                player_inventories,
+                player_points,
+                goal_suit,
+                common_suit,
+            }),
+            WireEvent::EndGame { player_points } => Event::EndGame(EndGamePointsUpdate { player_points }),
+            WireEvent::Stats { round_trades, round_volume, player_points } => Event::Stats(StatsUpdate { round_trades, round_volume, player_points }),
+            WireEvent::Announcement(announcement) => Event::Announcement(announcement),
+        }
+    }
+}
+
+impl From<&EngineView> for WireView {
+    fn from(view: &EngineView) -> Self {
+        Self {
+            seq: view.seq,
+            phase: view.phase,
+            round: view.round.clone(),
+            books: WireUpdate::from(&view.books),
+            players: view.players.clone(),
+            player_points: view.player_points.clone(),
+            player_inventories: view.player_inventories.clone(),
+        }
+    }
+}
+
+impl From<WireView> for EngineView {
+    fn from(view: WireView) -> Self {
+        Self {
+            seq: view.seq,
+            phase: view.phase,
+            round: view.round,
+            books: view.books.into(),
+            players: view.players,
+            player_points: view.player_points,
+            player_inventories: view.player_inventories,
+        }
+    }
+}
+
+
+// =-= Framing =-= //
+
+type LinkReader = Box<dyn AsyncRead + Send + Unpin>;
+type LinkWriter = BufWriter<Box<dyn AsyncWrite + Send + Unpin>>;
+type Frame = Arc<Vec<u8>>; // encoded once, however many gateways it goes to
+
+fn encode<T: Serialize>(message: &T) -> Frame {
+    Arc::new(serde_json::to_vec(message).expect("link messages always serialize"))
+}
+
+// only flushed once there's nothing else waiting to go out
+async fn write_frame(writer: &mut LinkWriter, frame: &[u8]) -> io::Result<()> {
+    writer.write_u32(frame.len() as u32).await?;
+    writer.write_all(frame).await
+}
+
+async fn read_frame<T: DeserializeOwned>(reader: &mut LinkReader) -> io::Result<T> {
+    let length = reader.read_u32().await? as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame too large ({} bytes)", length)));
+    }
+    let mut frame = vec![0; length];
+    reader.read_exact(&mut frame).await?;
+    serde_json::from_slice(&frame).map_err(io::Error::from)
+}
+
+fn split<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> (LinkReader, LinkWriter) {
+    let (reader, writer) = tokio::io::split(stream);
+    (Box::new(reader), BufWriter::new(Box::new(writer)))
+}
+
+async fn connect(addr: &LinkAddr) -> io::Result<(LinkReader, LinkWriter)> {
+    match addr {
+        LinkAddr::Tcp(addr) => {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+            Ok(split(stream))
+        },
+        LinkAddr::Unix(path) => Ok(split(UnixStream::connect(path).await?)),
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    async fn bind(addr: &LinkAddr) -> io::Result<Self> {
+        match addr {
+            LinkAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            LinkAddr::Unix(path) => {
+                let _ = std::fs::remove_file(path); // left over from a previous run
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            },
+        }
+    }
+
+    // (reader, writer, who it is for the logs)
+    async fn accept(&self) -> io::Result<(LinkReader, LinkWriter, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                let (reader, writer) = split(stream);
+                Ok((reader, writer, addr.to_string()))
+            },
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                let (reader, writer) = split(stream);
+                Ok((reader, writer, path.clone()))
+            },
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+
+// =-= Engine Side =-= //
+
+// every gateway connected to this engine, empty unless it's listening on ENGINE_LINK
+pub struct Replicas {
+    gateways: Mutex<HashMap<u64, Replica>>,
+    next_id: AtomicU64,
+    epoch: u64, // tells the gateways this engine apart from the one before a restart
+}
+
+struct Replica {
+    peer: String,
+    sender: mpsc::Sender<Outbound>,
+    _cut_off: oneshot::Sender<()>, // dropped along w/ the replica, which stops its writer
+}
+
+enum Outbound {
+    Event(u64, Frame),
+    Reply(Frame),
+    Plain(Frame), // directory changes + the welcome, no view needed
+}
+
+impl Replicas {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { gateways: Mutex::new(HashMap::new()), next_id: AtomicU64::new(1), epoch: rand::random() })
+    }
+
+    // applied by every gateway to its copy of the directory
+    pub async fn broadcast(&self, change: DirectoryChange) {
+        let mut gateways = self.gateways.lock().await;
+        if gateways.is_empty() {
+            return;
+        }
+        let frame = encode(&FromEngine::Change(change));
+        gateways.retain(|_, replica| replica.push(Outbound::Plain(Arc::clone(&frame))));
+    }
+
+    // the publisher hands over every event the engine publishes, in seq order
+    pub async fn forward(&self, seq: u64, event: &Event) {
+        let mut gateways = self.gateways.lock().await;
+        if gateways.is_empty() {
+            return;
+        }
+        let frame = encode(&FromEngine::Event { seq, event: WireEvent::from(event) });
+        gateways.retain(|_, replica| replica.push(Outbound::Event(seq, Arc::clone(&frame))));
+    }
+
+    pub async fn connected(&self) -> Vec<String> {
+        self.gateways.lock().await.values().map(|replica| replica.peer.clone()).collect()
+    }
+}
+
+impl Replica {
+    // false (+ the gateway is cut off) when its queue is full
+    fn push(&self, outbound: Outbound) -> bool {
+        match self.sender.try_send(outbound) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                println!("{}[!] LINK |:| Gateway {} fell {} frames behind, cutting it off{}", CL::Red.get(), self.peer, LINK_OUTBOUND_QUEUE, CL::End.get());
+                false
+            },
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+
+// takes gateways until shutdown. Has to run inside a LocalSet since admin actions can start the game loop
+pub async fn serve(addr: LinkAddr, secret: LinkSecret, admin: Arc<AdminState>, mut shutdown: oneshot::Receiver<()>) {
+    let listener = match Listener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}[!] LINK |:| Failed to bind {}: {:?}{}", CL::Red.get(), addr, e, CL::End.get());
+            return;
+        }
+    };
+    println!("{}[+] LINK |:| Engine listening for gateways on {}{}", CL::Green.get(), addr, CL::End.get());
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                break;
+            }
+            result = listener.accept() => {
+                match result {
+                    Ok((reader, writer, peer)) => {
+                        tokio::task::spawn_local(handle_gateway(reader, writer, peer, secret.clone(), Arc::clone(&admin)));
+                    },
+                    Err(e) => println!("{}[!] LINK |:| Error accepting a gateway: {:?}{}", CL::Red.get(), e, CL::End.get()),
+                }
+            }
+        }
+    }
+}
+
+async fn handle_gateway(mut reader: LinkReader, mut writer: LinkWriter, peer: String, secret: LinkSecret, admin: Arc<AdminState>) {
+    let replicas = Arc::clone(&admin.replicas);
+    let challenge = new_nonce();
+    if write_frame(&mut writer, &encode(&FromEngine::Challenge { nonce: challenge.clone() })).await.is_err() || writer.flush().await.is_err() {
+        return;
+    }
+    let (epoch, last_seq, nonce) = match timeout(HELLO_TIMEOUT, read_frame::<ToEngine>(&mut reader)).await {
+        Ok(Ok(ToEngine::Hello { epoch, last_seq, nonce, proof })) => {
+            if !secret.check("gateway", &challenge, &nonce, &proof) {
+                println!("{}[!] LINK |:| {} couldn't prove it has LINK_SECRET, dropping it{}", CL::Red.get(), peer, CL::End.get());
+                return;
+            }
+            (epoch, last_seq, nonce)
+        },
+        _ => {
+            println!("{}[!] LINK |:| {} didn't say hello, dropping it{}", CL::Red.get(), peer, CL::End.get());
+            return;
+        }
+    };
+
+    // the directory is read + the gateway added under the same lock, so every change after this one reaches it
+    let id = replicas.next_id.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::channel(LINK_OUTBOUND_QUEUE);
+    let (cut_off_sender, cut_off) = oneshot::channel();
+    let directory = {
+        let mut gateways = replicas.gateways.lock().await;
+        let directory = Directory {
+            players: admin.rate_limiter.players().await,
+            player_ids: admin.playerid_playername_map.read().await.iter().map(|(player_id, player_name)| (player_id.clone(), player_name.clone())).collect(),
+            keys: admin.auth.player_keys().await,
+        };
+        gateways.insert(id, Replica { peer: peer.clone(), sender: sender.clone(), _cut_off: cut_off_sender });
+        directory
+    };
+
+    // the events it missed, taken after it was added so nothing falls in between (it skips whatever it gets twice)
+    let resume_from = if epoch == Some(replicas.epoch) { last_seq } else { 0 };
+    let caught_up = admin.order_queue.call(move |matching_engine| (matching_engine.seq, matching_engine.replay_buffer.after(resume_from))).await;
+    let (seq, missed) = match caught_up {
+        Ok(caught_up) => caught_up,
+        Err(error) => {
+            println!("{}[!] LINK |:| Couldn't catch {} up: {}{}", CL::Red.get(), peer, error.message(), CL::End.get());
+            replicas.gateways.lock().await.remove(&id);
+            return;
+        }
+    };
+    let mut initial = vec![
+        Outbound::Plain(encode(&FromEngine::Accepted { proof: secret.prove("engine", &nonce, &challenge) })),
+        Outbound::Plain(encode(&FromEngine::Directory(directory))),
+        Outbound::Plain(encode(&FromEngine::Welcome { epoch: replicas.epoch, seq, oldest: missed.first().map(|(seq, _)| *seq) })),
+    ];
+    initial.extend(missed.iter().map(|(seq, event)| Outbound::Event(*seq, encode(&FromEngine::Event { seq: *seq, event: WireEvent::from(&**event) }))));
+    println!("{}[+] LINK |:| Gateway {} connected | {} events to catch up on{}", CL::Green.get(), peer, missed.len(), CL::End.get());
+
+    let writing = write_to_gateway(writer, initial, receiver, cut_off, Arc::clone(&admin.engine_view), Arc::clone(&admin.started_game));
+    let reading = async {
+        loop {
+            match read_frame::<ToEngine>(&mut reader).await? {
+                ToEngine::Request { id, request } => {
+                    let (admin, sender) = (Arc::clone(&admin), sender.clone());
+                    tokio::task::spawn_local(async move {
+                        let reply = answer(&admin, request).await;
+                        let _ = sender.send(Outbound::Reply(encode(&FromEngine::Reply { id, reply }))).await;
+                    });
+                },
+                ToEngine::Hello { .. } => return Err(io::Error::new(io::ErrorKind::InvalidData, "said hello twice")),
+            }
+        }
+    };
+    let result: io::Result<()> = tokio::select! {
+        result = writing => result,
+        result = reading => result,
+    };
+
+    replicas.gateways.lock().await.remove(&id);
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => println!("{}[!] LINK |:| Gateway {} dropped: {}{}", CL::Orange.get(), peer, e, CL::End.get()),
+        _ => println!("{}[-] LINK |:| Gateway {} disconnected{}", CL::Dull.get(), peer, CL::End.get()),
+    }
+}
+
+// the view + trading flag are sent from here, whenever they changed, right before the frame that needs them
+async fn write_to_gateway(
+    mut writer: LinkWriter,
+    initial: Vec<Outbound>,
+    mut receiver: mpsc::Receiver<Outbound>,
+    mut cut_off: oneshot::Receiver<()>,
+    engine_view: SharedView,
+    started_game: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let mut sent = SentState { view: None, trading: None };
+    for outbound in initial {
+        sent.write(&mut writer, outbound, &engine_view, &started_game).await?;
+    }
+    writer.flush().await?;
+
+    let mut view_interval = tokio::time::interval(VIEW_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut cut_off => {
+                return Err(io::Error::other("fell too far behind"));
+            }
+            outbound = receiver.recv() => {
+                let Some(outbound) = outbound else {
+                    return Ok(());
+                };
+                sent.write(&mut writer, outbound, &engine_view, &started_game).await?;
+                if receiver.is_empty() {
+                    writer.flush().await?;
+                }
+            }
+            _ = view_interval.tick() => {
+                sent.sync(&mut writer, &engine_view, &started_game, None).await?;
+                writer.flush().await?;
+            }
+        }
+    }
+}
+
+struct SentState {
+    view: Option<Arc<EngineView>>,
+    trading: Option<bool>,
+}
+
+impl SentState {
+    async fn write(&mut self, writer: &mut LinkWriter, outbound: Outbound, engine_view: &SharedView, started_game: &AtomicBool) -> io::Result<()> {
+        let frame = match outbound {
+            Outbound::Event(seq, frame) => {
+                self.sync(writer, engine_view, started_game, Some(seq)).await?;
+                frame
+            },
+            Outbound::Reply(frame) => {
+                self.sync(writer, engine_view, started_game, None).await?; // so the gateway's next read sees what the request did
+                frame
+            },
+            Outbound::Plain(frame) => frame,
+        };
+        write_frame(writer, &frame).await
+    }
+
+    // an event only needs a view that includes it, anything else gets the latest if it changed
+    async fn sync(&mut self, writer: &mut LinkWriter, engine_view: &SharedView, started_game: &AtomicBool, event_seq: Option<u64>) -> io::Result<()> {
+        let stale = match (&self.view, event_seq) {
+            (Some(view), Some(seq)) => view.seq < seq,
+            (Some(view), None) => !Arc::ptr_eq(view, &engine_view.load()),
+            (None, _) => true,
+        };
+        if stale {
+            let view = engine_view.load_full();
+            write_frame(writer, &encode(&FromEngine::View(WireView::from(&*view)))).await?;
+            self.view = Some(view);
+        }
+
+        let trading = started_game.load(Ordering::Acquire);
+        if self.trading != Some(trading) {
+            write_frame(writer, &encode(&FromEngine::Trading(trading))).await?;
+            self.trading = Some(trading);
+        }
+        Ok(())
+    }
+}
+
+async fn answer(admin: &Arc<AdminState>, request: LinkRequest) -> LinkReply {
+    match request {
+        LinkRequest::Order(order, received) => LinkReply::Order(admin.order_queue.submit(order, received).await),
+        LinkRequest::Register { player_id } => LinkReply::Register(admin.register_testnet(&player_id).await),
+        LinkRequest::Admin(request) => LinkReply::Admin(admin::dispatch(admin, request).await),
+        LinkRequest::Stats => LinkReply::Stats(admin.order_queue.stats()),
+        LinkRequest::Nonce { api_key, nonce, timestamp } => LinkReply::Nonce(admin.auth.check_nonce(&api_key, &nonce, timestamp).await),
+        LinkRequest::Take { player_name, endpoint } => LinkReply::Take(admin.rate_limiter.take(&player_name, endpoint).await),
+    }
+}
+
+
+// =-= Gateway Side =-= //
+
+// a gateway's line to the engine, the OrderQueue sends everything through here
+pub struct EngineLink {
+    pub addr: LinkAddr,
+    secret: LinkSecret,
+    connection: std::sync::Mutex<Option<mpsc::Sender<Frame>>>, // None while disconnected
+    pending: std::sync::Mutex<HashMap<u64, oneshot::Sender<LinkReply>>>, // request id -> whoever's waiting on the reply
+    next_id: AtomicU64,
+    replay_buffer: SharedReplayBuffer, // the engine's events, for replays + where to pick up after a reconnect
+}
+
+// the gateway's copies of what the engine has, kept up to date by `run`
+pub struct Mirror {
+    pub engine_view: SharedView,
+    pub started_game: Arc<AtomicBool>,
+    pub playerid_playername_map: Arc<RwLock<HashMap<String, String>>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub auth: Arc<Auth>,
+    pub player_ws_map: PlayerWsMap,
+    pub events: EventSender, // the gateway's own publisher
+}
+
+impl EngineLink {
+    pub fn new(addr: LinkAddr, secret: LinkSecret) -> Arc<Self> {
+        Arc::new(Self {
+            addr,
+            secret,
+            connection: std::sync::Mutex::new(None),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            replay_buffer: SharedReplayBuffer::new(),
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.lock().unwrap().is_some()
+    }
+
+    // requests waiting on the engine
+    pub fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub async fn request(&self, request: LinkRequest, wait: Duration) -> Result<LinkReply, ApiError> {
+        let Some(sender) = self.connection.lock().unwrap().clone() else {
+            return Err(ApiError::EngineUnavailable);
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, reply_sender);
+
+        let answer = timeout(wait, async {
+            sender.send(encode(&ToEngine::Request { id, request })).await.ok()?;
+            reply_receiver.await.ok() // the sender's dropped if the link goes down
+        }).await;
+        match answer {
+            Ok(Some(reply)) => Ok(reply),
+            Ok(None) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(ApiError::EngineUnavailable)
+            },
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(ApiError::EngineTimeout(wait.as_millis() as u64))
+            },
+        }
+    }
+
+    // the gateway's sessions replay from here, it's filled before the publisher gets the events (like the engine's publisher does)
+    pub fn replay_buffer(&self) -> SharedReplayBuffer {
+        self.replay_buffer.clone()
+    }
+
+    // stays connected to the engine until shutdown, reconnecting w/ a backoff whenever the link drops
+    pub async fn run(self: Arc<Self>, mirror: Mirror, mut shutdown: oneshot::Receiver<()>) {
+        let mut epoch = None;
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            let connected = tokio::select! {
+                _ = &mut shutdown => break,
+                connected = connect(&self.addr) => connected,
+            };
+            match connected {
+                Ok((reader, writer)) => {
+                    println!("{}[+] LINK |:| Connected to the engine at {}{}", CL::Green.get(), self.addr, CL::End.get());
+                    backoff = RECONNECT_MIN;
+                    let result = tokio::select! {
+                        _ = &mut shutdown => break,
+                        result = self.session(reader, writer, &mirror, &mut epoch) => result,
+                    };
+
+                    // everyone waiting on a reply gets ENGINE_UNAVAILABLE, new requests too until we're back
+                    *self.connection.lock().unwrap() = None;
+                    self.pending.lock().unwrap().clear();
+                    println!("{}[!] LINK |:| Lost the engine at {}: {:?} | reconnecting{}", CL::Red.get(), self.addr, result.err(), CL::End.get());
+                },
+                Err(e) => println!("{}[!] LINK |:| No engine at {}: {} | trying again in {}ms{}", CL::Orange.get(), self.addr, e, backoff.as_millis(), CL::End.get()),
+            }
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = sleep(backoff) => {},
+            }
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    async fn session(&self, mut reader: LinkReader, mut writer: LinkWriter, mirror: &Mirror, epoch: &mut Option<u64>) -> io::Result<()> {
+        let challenge = match timeout(HELLO_TIMEOUT, read_frame::<FromEngine>(&mut reader)).await {
+            Ok(Ok(FromEngine::Challenge { nonce })) => nonce,
+            Ok(Err(e)) => return Err(e),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "the engine didn't send a challenge")),
+        };
+        let nonce = new_nonce();
+        let last_seq = self.replay_buffer.lock().last_seq;
+        let proof = self.secret.prove("gateway", &challenge, &nonce);
+        write_frame(&mut writer, &encode(&ToEngine::Hello { epoch: *epoch, last_seq, nonce: nonce.clone(), proof })).await?;
+        writer.flush().await?;
+
+        // an engine that doesn't like the proof just hangs up
+        match timeout(HELLO_TIMEOUT, read_frame::<FromEngine>(&mut reader)).await {
+            Ok(Ok(FromEngine::Accepted { proof })) if self.secret.check("engine", &nonce, &challenge, &proof) => {},
+            Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the engine turned us away (is LINK_SECRET the same on both ends?)")),
+            Ok(Err(e)) => return Err(e),
+            _ => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the engine couldn't prove it has LINK_SECRET")),
+        }
+
+        let (sender, mut receiver) = mpsc::channel::<Frame>(LINK_OUTBOUND_QUEUE);
+        *self.connection.lock().unwrap() = Some(sender);
+        let writing = async {
+            while let Some(frame) = receiver.recv().await {
+                write_frame(&mut writer, &frame).await?;
+                if receiver.is_empty() {
+                    writer.flush().await?;
+                }
+            }
+            Ok(())
+        };
+        let reading = async {
+            loop {
+                let message = read_frame::<FromEngine>(&mut reader).await?;
+                self.apply(message, mirror, epoch).await?;
+            }
+        };
+        tokio::select! {
+            result = writing => result,
+            result = reading => result,
+        }
+    }
+
+    async fn apply(&self, message: FromEngine, mirror: &Mirror, epoch: &mut Option<u64>) -> io::Result<()> {
+        match message {
+            FromEngine::Challenge { .. } | FromEngine::Accepted { .. } => return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake frame after the handshake")),
+            FromEngine::View(view) => mirror.engine_view.store(Arc::new(EngineView::from(view))),
+            FromEngine::Trading(trading) => mirror.started_game.store(trading, Ordering::Release),
+            FromEngine::Directory(directory) => {
+                for player_name in &directory.players {
+                    PlayerId::intern(player_name);
+                }
+                *mirror.playerid_playername_map.write().await = directory.player_ids.into_iter().collect();
+                mirror.rate_limiter.replace(&directory.players).await;
+                mirror.auth.replace_player_keys(directory.keys).await;
+            },
+            FromEngine::Change(change) => apply_change(change, mirror).await,
+            FromEngine::Welcome { epoch: engine_epoch, seq, oldest } => {
+                let resume_from = oldest.map(|oldest| oldest - 1).unwrap_or(seq); // the replay picks up right after this
+                let lost = {
+                    let mut replay_buffer = self.replay_buffer.lock();
+                    let lost = match *epoch {
+                        Some(epoch) => epoch != engine_epoch || resume_from > replay_buffer.last_seq,
+                        None => false, // nothing to lose yet, take whatever the engine has
+                    };
+                    if lost || epoch.is_none() {
+                        replay_buffer.reset(resume_from);
+                    }
+                    lost
+                };
+                if lost {
+                    let sessions = close_all_sessions(&mirror.player_ws_map, "The gateway lost track of the engine, please subscribe again").await;
+                    println!("{}[!] LINK |:| Missed events the engine can't replay anymore | closed {} sessions{}", CL::Red.get(), sessions, CL::End.get());
+                }
+                *epoch = Some(engine_epoch);
+            },
+            FromEngine::Event { seq, event } => {
+                let event = Arc::new(Event::from(event));
+                {
+                    let mut replay_buffer = self.replay_buffer.lock();
+                    if seq <= replay_buffer.last_seq {
+                        return Ok(()); // already have it (caught up on it + it was still queued for us)
+                    }
+                    if seq != replay_buffer.last_seq + 1 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("missed events {}..{}", replay_buffer.last_seq + 1, seq - 1)));
+                    }
+                    replay_buffer.push(seq, Arc::clone(&event));
+                }
+                if let Err(e) = mirror.events.try_send((seq, event)) {
+                    println!("{}[!] Failed to hand event {} to the publisher: {:?}{}", CL::Red.get(), seq, e, CL::End.get());
+                }
+            },
+            FromEngine::Reply { id, reply } => {
+                if let Some(sender) = self.pending.lock().unwrap().remove(&id) {
+                    let _ = sender.send(reply); // fine if they gave up waiting
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// the same thing the engine's admin did to its directory
+async fn apply_change(change: DirectoryChange, mirror: &Mirror) {
+    match change {
+        DirectoryChange::Registered { player_name, player_id } => {
+            PlayerId::intern(&player_name);
+            if let Some(player_id) = player_id {
+                mirror.playerid_playername_map.write().await.insert(player_id, player_name.clone());
+            }
+            mirror.rate_limiter.register(&player_name).await;
+        },
+        DirectoryChange::Removed { player_name, reason } => {
+            mirror.playerid_playername_map.write().await.retain(|_, name| *name != player_name);
+            mirror.rate_limiter.remove(&player_name).await;
+            mirror.auth.revoke(&player_name).await;
+            let sessions = close_player_sessions(&mirror.player_ws_map, &player_name, &reason).await;
+            println!("{}[!] LINK |:| The engine removed {:?} | {} | {} sessions closed{}", CL::Orange.get(), player_name, reason, sessions, CL::End.get());
+        },
+        DirectoryChange::KeyIssued(player_key) => mirror.auth.import(player_key).await,
+        DirectoryChange::Cleared => {
+            mirror.playerid_playername_map.write().await.clear();
+            mirror.rate_limiter.clear().await;
+        },
+    }
+}
@@ -1,354 +1,229 @@
-use actix_web::{post, get, web, App, HttpServer, HttpRequest, HttpResponse, Responder};
+use actix_web::{post, get, web, App, HttpMessage, HttpServer, HttpRequest, Responder};
+use actix_web::dev::Service;
 use actix_cors::Cors;
-use tokio::time::{sleep, Duration};
+use tokio::time::Duration;
 use std::collections::HashMap;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use std::sync::Arc;
-use tokio::net::TcpStream;
-use futures_util::stream::StreamExt;
-use tokio_tungstenite::tungstenite::Message;
-use tokio_tungstenite::WebSocketStream;
-use futures_util::stream::SplitSink;
-use futures_util::SinkExt;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use tokio::sync::RwLock;
-use tokio::sync::oneshot;
-use tokio::sync::oneshot::{Sender as OneshotSender};
-use kanal::AsyncSender;
 use tokio::signal::ctrl_c;
 
-mod utils;
-pub use utils::*;
-
-mod models;
-pub use models::*;
-
-mod matching_engine;
-use matching_engine::MatchingEngine;
-
-
-const STARTING_BALANCE: i32 = 500;
+use figgie_tournament_testnet::*;
+use figgie_tournament_testnet::matching_engine::{EngineView, MatchingEngine, SharedReplayBuffer, SharedView};
+use figgie_tournament_testnet::websocket::{self, PlayerWsMap, WsSettings};
+use figgie_tournament_testnet::deflate::{Bandwidth, DeflateConfig};
+use figgie_tournament_testnet::sse::stream_handler;
+use figgie_tournament_testnet::uds_gateway::{self, GatewayState};
+use figgie_tournament_testnet::multicast::{self, MulticastPublisher};
+use figgie_tournament_testnet::publisher;
+use figgie_tournament_testnet::outbound::{OutboundConfig, SlowConsumerPolicy, WS_OUTBOUND_QUEUE};
+use figgie_tournament_testnet::tls;
+use figgie_tournament_testnet::api_error::{ApiError, parse_json, respond};
+use figgie_tournament_testnet::auth::{Auth, Identity};
+use figgie_tournament_testnet::rate_limit::{Endpoint, RateLimitConfig, RateLimiter, RateLimitStatus};
+use figgie_tournament_testnet::order_queue::{EngineReceiver, OrderQueue, QueueTransport, ORDER_QUEUE_DEPTH, ENGINE_TIMEOUT};
+use figgie_tournament_testnet::order_entry::OrderEntry;
+use figgie_tournament_testnet::admin::{AdminState, admin_handler};
+use figgie_tournament_testnet::config::{Config, USAGE};
+use figgie_tournament_testnet::sequencer::{Received, SEQUENCER_WINDOW};
+use figgie_tournament_testnet::link::{self, EngineLink, LinkAddr, LinkSecret, Mirror, Role, ENGINE_LINK};
+
+
+const MISSING_PLAYERID_MESSAGE: &str = "Required headers not found, please send 'playerid' header with your request. If this is for testnet, send anything. During the tournament you'll be given a unique ID that should be placed here";
+const MAX_SESSIONS_PER_PLAYER: usize = 4; // default, can be overridden w/ the MAX_SESSIONS_PER_PLAYER env var
+const WS_DEFLATE_LEVEL: u32 = 6; // zlib's default, WS_DEFLATE_LEVEL to override (0-9)
+const WS_DEFLATE_THRESHOLD: usize = 256; // bytes, small messages barely shrink so they aren't worth the CPU, WS_DEFLATE_THRESHOLD to override
+
+
+// what the hotpath thread runs: the matching engine, or on a gateway the link to the engine's process
+enum Hotpath {
+    Engine(Box<MatchingEngine>, EngineReceiver),
+    Gateway(Arc<EngineLink>, Mirror),
+}
 
 
-fn generate_random_player_name() -> String {
-    let first_word = random_word::gen_len(5, random_word::Lang::En).unwrap();
-    let first_word = format!("{}{}", first_word.chars().next().unwrap().to_uppercase().collect::<String>(), &first_word[1..]);
+// =-= Player Checks =-= //
 
-    let second_word = random_word::gen_len(5, random_word::Lang::En).unwrap();
-    let second_word = format!("{}{}", second_word.chars().next().unwrap().to_uppercase().collect::<String>(), &second_word[1..]);
+// shared by /order, /cancel and /inventory: the game has to be running, the player has to be known and every call takes its weight out of the player's bucket
+async fn authorize_player(
+    req: &HttpRequest,
+    body: &[u8],
+    endpoint: Endpoint,
+    started_game: &Arc<AtomicBool>,
+    auth: &Auth,
+    playerid_playername_map: &Arc<RwLock<HashMap<String, String>>>,
+    rate_limiter: &RateLimiter,
+) -> Result<(String, RateLimitStatus), ApiError> {
+    if !started_game.load(Ordering::Acquire) {
+        return Err(ApiError::NoGame);
+    }
 
-    let player_name = format!("{}{}", first_word, second_word);
-    
-    return player_name;
+    let missing_header_message = match endpoint {
+        Endpoint::Inventory => "Required headers not found, please send 'playerid' header with your request",
+        Endpoint::Order | Endpoint::Cancel => MISSING_PLAYERID_MESSAGE,
+    };
+    let player_name = identify_player(req, body, missing_header_message, auth, playerid_playername_map).await?;
+
+    match rate_limiter.take(&player_name, endpoint).await {
+        Ok(rate_limit) => Ok((player_name, rate_limit)),
+        Err(ApiError::UnknownPlayer) => {
+            println!("{}[!] {:?} | Rate limit not found for playername{}", CL::Red.get(), player_name, CL::End.get());
+            Err(ApiError::UnknownPlayer)
+        },
+        Err(error) => Err(error),
+    }
 }
 
-#[post("/admin")]
-async fn admin_handler(
-    req: HttpRequest,
-    data: web::Json<AdminRequest>,
-    started_game: web::Data<Arc<AtomicBool>>,
-    matching_engine: web::Data<Arc<Mutex<MatchingEngine>>>,
-    playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
-    playername_rate_limit_map: web::Data<Arc<Mutex<HashMap<String, u8>>>>,
-) -> impl Responder {
-
-    println!("{}[+] ADMIN |:| Received POST request with admin details{}", CL::DimLightBlue.get(), CL::End.get());
-    let headers = req.headers();
-    
-    let round_duration = Duration::from_secs(60 * 3);
-    let num_of_rounds = 4;
-
-    if let Some(admin_id) = headers.get("adminid") {
-        let admin_id = admin_id.to_str().unwrap();
-        match admin_id == "admin" { // changed in prod of course
-            true => {
-                println!("{}[+] ADMIN |:| Authentication passed{}", CL::Green.get(), CL::End.get());
-
-                if data.action == "start_game" {
-                    if started_game.load(Ordering::Acquire) {
-                        println!("{}[!] ADMIN |:| Game already started{}", CL::Orange.get(), CL::End.get());
-                    } else {
-                        println!("{}[+] ADMIN |:| Starting game{}", CL::Green.get(), CL::End.get());
-                        started_game.store(true, Ordering::Release);
-
-                        let started_inside = Arc::clone(&started_game);
-                        let match_maker_inside = Arc::clone(&matching_engine);
-                        tokio::task::spawn_local(async move {
-                            loop {
-                                for i in 0..num_of_rounds {
-                                    // wait 15s before starting the next round
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
-    
-                                    started_inside.store(true, Ordering::Release);
-                                    match_maker_inside.lock().await.start_round(i).await;
-                                    
-
-                                    // wait `round_duration` before ending the round
-                                    tokio::time::sleep(round_duration).await;
-    
-                                    started_inside.store(false, Ordering::Release);
-                                    match_maker_inside.lock().await.end_round().await;
-                                }
-    
-                                match_maker_inside.lock().await.end_game().await;
-                                println!("{}[+] ADMIN |:| Game has ended{}", CL::Green.get(), CL::End.get());
-                                started_inside.store(false, Ordering::Release);
+// a signed request names the player through its API key, otherwise it's the `playerid` header (while unsigned requests are allowed)
+async fn identify_player(
+    req: &HttpRequest,
+    body: &[u8],
+    missing_header_message: &str,
+    auth: &Auth,
+    playerid_playername_map: &Arc<RwLock<HashMap<String, String>>>,
+) -> Result<String, ApiError> {
+    match auth.authenticate_http(req, body).await? {
+        Some(Identity::Player(player_name)) => return Ok(player_name),
+        Some(Identity::Admin) => return Err(ApiError::Unauthorized("The admin key can't be used to trade".to_string())),
+        None => {},
+    }
 
-                                // Clear all players to keep the testnet lightweight
-                                match_maker_inside.lock().await.delete_all_players();
+    let player_id = match req.headers().get("playerid") {
+        Some(player_id) => player_id.to_str().map_err(|_| ApiError::ParseError("Failed to read the 'playerid' header".to_string()))?,
+        None => {
+            println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
+            return Err(ApiError::MissingHeader(missing_header_message.to_string()));
+        }
+    };
 
-                                playerid_playername_map.write().await.clear();
-                                playername_rate_limit_map.lock().await.clear();
+    match playerid_playername_map.read().await.get(player_id) {
+        Some(player_name) => Ok(player_name.clone()),
+        None => Err(ApiError::UnknownPlayer),
+    }
+}
 
-                                sleep(Duration::from_secs(15)).await;
-                            }
-                        });
-                    }
-                    return HttpResponse::Ok().body("Game started");
-                }
+fn parse_card(card: &str) -> Result<Card, ApiError> {
+    match card {
+        "spade" => Ok(Card::Spade),
+        "club" => Ok(Card::Club),
+        "diamond" => Ok(Card::Diamond),
+        "heart" => Ok(Card::Heart),
+        _ => {
+            println!("{}[!] Invalid card{}", CL::Red.get(), CL::End.get());
+            Err(ApiError::InvalidCard)
+        }
+    }
+}
 
-                println!("{}[!] ADMIN |:| Invalid action: {}{}", CL::Red.get(), data.action, CL::End.get());
-                return HttpResponse::Ok().body("Invalid action");
-            },
-            false => {
-                println!("{}[!] ADMIN |:| Authentication Failed{}", CL::Red.get(), CL::End.get());
-                return HttpResponse::Ok().body("Authentication Failed");
-            }
+fn parse_direction(direction: &str) -> Result<Direction, ApiError> {
+    match direction {
+        "buy" => Ok(Direction::Buy),
+        "sell" => Ok(Direction::Sell),
+        _ => {
+            println!("{}[!] Invalid direction{}", CL::Red.get(), CL::End.get());
+            Err(ApiError::InvalidDirection)
         }
-    } else {
-        println!("{}[!] ADMIN |:| Admin ID not found{}", CL::Red.get(), CL::End.get());
-        return HttpResponse::Ok().body("Admin ID not in headers");
     }
 }
 
+// `sequence` is the order's place in line, if the engine got to it
+async fn submit_order(order_queue: &OrderQueue, order: Order, received: Received, sequence: &mut Option<u64>) -> Result<String, ApiError> {
+    let answer = order_queue.submit(order, received).await?;
+    *sequence = Some(answer.sequence);
+    ApiError::from_engine(answer.response)
+}
+
 
 #[post("/order")]
 async fn order_handler(
     req: HttpRequest,
-    data: web::Json<RawOrderData>,
+    body: web::Bytes,
     started_game: web::Data<Arc<AtomicBool>>,
-    sender_arc: web::Data<Arc<AsyncSender<(Order, OneshotSender<HTTPResponse>)>>>,
+    order_queue: web::Data<Arc<OrderQueue>>,
     playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
-    playername_rate_limit_map: web::Data<Arc<Mutex<HashMap<String, u8>>>>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    auth: web::Data<Arc<Auth>>,
 ) -> impl Responder {
     //println!("{}[+] ORDER |:| Received new order from the API{}", CL::DimLightBlue.get(), CL::End.get());
 
-    let rate_limit_per_second = 10; // rate limit is shared with /inventory and /order
-    let headers = req.headers();
-
     // in this section of the code we need to filter out bad orders, get the headers and match it with the player name
     // if it's a valid order and the player name is found, then we check if the player name is within their allowed rolling rate limit allocation
     // if this all passes, we send it through the matching engine to be processed
+    let received = Received::of(&req);
+    let data = match parse_json::<RawOrderData>(&req, &body) {
+        Ok(data) => data,
+        Err(response) => return received.apply(None, response),
+    };
+    let (player_name, rate_limit) = match authorize_player(&req, &body, Endpoint::Order, &started_game, &auth, &playerid_playername_map, &rate_limiter).await {
+        Ok(authorized) => authorized,
+        Err(error) => return received.apply(None, respond(&req, Err(error))),
+    };
+
+    let mut sequence = None;
+    let result = async {
+        let data = data?;
+        let direction = parse_direction(&data.direction)?;
+        let card = parse_card(&data.card)?;
+
+        if data.price == 0 || data.price > MAX_PRICE {
+            println!("{}[!] Invalid price{}", CL::Red.get(), CL::End.get());
+            return Err(ApiError::InvalidPrice);
+        }
 
-    if !started_game.load(Ordering::Acquire) {
-        let response = HTTPResponse { status: "NO_GAME".to_string(), message: "Game hasn't started yet. Sit tight and make sure your websocket connection is up and connected".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
-
-    if let Some(player_id) = headers.get("playerid") {
-        let player_id = player_id.to_str().unwrap();
-        let playerid_playername_map_guard = playerid_playername_map.read().await;
-        if let Some(player_name) = playerid_playername_map_guard.get(player_id) {
-            
-            // I don't want to keep a lock on the rate limit map for too long, so let's get the data, clone it, then drop it
-            let mut playername_rate_limit_map_guard = playername_rate_limit_map.lock().await;
-            let rate_limit = match playername_rate_limit_map_guard.get_mut(player_name) {
-                Some(rate_limit) => {
-                    *rate_limit += 1;
-                    *rate_limit
-                }
-                None => {
-                    println!("{}[!] {:?} | Rate limit not found for playername{}", CL::Red.get(), player_name, CL::End.get());
-                    let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string() };
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            };
-            drop(playername_rate_limit_map_guard);
-
-
-            match rate_limit > rate_limit_per_second {
-                true => {
-                    let response = HTTPResponse { status: "RATE_LIMIT".to_string(), message: "Settle down there mate, you've reached >10 orders/second. Please wait 1 second till your limits are reset".to_string()};
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                },
-                false => {
-
-                    let direction = match data.direction.as_str() {
-                        "buy" => Direction::Buy,
-                        "sell" => Direction::Sell,
-                        _ => {
-                            println!("{}[!] Invalid direction{}", CL::Red.get(), CL::End.get());
-                            let response = HTTPResponse { status: "INVALID_DIRECTION".to_string(), message: "For the direction, please send either `buy` or `sell`".to_string()};
-                            let serialized_response = serde_json::to_string(&response).unwrap();
-                            return HttpResponse::Ok().json(serialized_response);
-                        }
-                    };
-
-                    let card = match data.card.as_str() {
-                        "spade" => Card::Spade,
-                        "club" => Card::Club,
-                        "diamond" => Card::Diamond,
-                        "heart" => Card::Heart,
-                        _ => {
-                            println!("{}[!] Invalid card{}", CL::Red.get(), CL::End.get());
-                            let response = HTTPResponse { status: "INVALID_CARD".to_string(), message: "For the card, please send either `spade`, `club`, `diamond`, or `heart`".to_string()};
-                            let serialized_response = serde_json::to_string(&response).unwrap();
-                            return HttpResponse::Ok().json(serialized_response);
-                        }
-                    };
-
-                    if data.price <= 0 || data.price >= 100 {
-                        println!("{}[!] Invalid price{}", CL::Red.get(), CL::End.get());
-                        let response = HTTPResponse { status: "INVALID_PRICE".to_string(), message: "For the price, please send a number between 0 and 99".to_string()};
-                        let serialized_response = serde_json::to_string(&response).unwrap();
-                        return HttpResponse::Ok().json(serialized_response);
-                    }
-
-                    let order = Order {
-                        player_name: player_name.to_string(),
-                        card,
-                        direction,
-                        price: Some(data.price)
-                    };
-
-                    let (oneshot_sender, receiver) = oneshot::channel();
-                    if let Err(e) = sender_arc.send((order, oneshot_sender)).await {
-                        println!("{}[!] Failed to send order to matching engine: {:?}", CL::Red.get(), e);
-                        let response = HTTPResponse { status: "ERROR".to_string(), message: "Couldn't send order to matching engine".to_string()};
-                        let serialized_response = serde_json::to_string(&response).unwrap();
-                        return HttpResponse::Ok().json(serialized_response);
-                    }
+        let order = Order {
+            player: PlayerId::lookup(&player_name).ok_or(ApiError::UnknownPlayer)?,
+            card,
+            direction,
+            price: Some(data.price)
+        };
+        submit_order(&order_queue, order, received, &mut sequence).await
+    }.await;
 
-                    let response = receiver.await.unwrap();
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            }
-        } else {
-            //println!("{}[!] Player name not found{}", CL::Orange.get(), CL::End.get());
-            let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string()};
-            let serialized_response = serde_json::to_string(&response).unwrap();
-            return HttpResponse::Ok().json(serialized_response);
-        }
-    } else {
-        println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
-        let response = HTTPResponse { status: "MISSING_HEADER".to_string(), message: "Required headers not found, please send 'playerid' header with your request. If this is for testnet, send anything. During the tournament you'll be given a unique ID that should be placed here".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
+    rate_limit.apply(received.apply(sequence, respond(&req, result)))
 }
 
 
 #[post("/cancel")]
 async fn cancel_handler(
     req: HttpRequest,
-    data: web::Json<RawCancelOrderData>,
+    body: web::Bytes,
     started_game: web::Data<Arc<AtomicBool>>,
-    sender_arc: web::Data<Arc<AsyncSender<(Order, OneshotSender<HTTPResponse>)>>>,
+    order_queue: web::Data<Arc<OrderQueue>>,
     playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
-    playername_rate_limit_map: web::Data<Arc<Mutex<HashMap<String, u8>>>>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    auth: web::Data<Arc<Auth>>,
 ) -> impl Responder {
     println!("{}[+] ORDER |:| Received new cancel order from the API{}", CL::DimLightBlue.get(), CL::End.get());
 
-    let rate_limit_per_second = 10; // rate limit is shared with /inventory and /order
-    let headers = req.headers();
-
-    // in this section of the code we need to filter out bad orders, get the headers and match it with the player name
-    // if it's a valid order and the player name is found, then we check if the player name is within their allowed rolling rate limit allocation
-    // if this all passes, we send it through the matching engine to be processed
-
-    if !started_game.load(Ordering::Acquire) {
-        let response = HTTPResponse { status: "NO_GAME".to_string(), message: "Game hasn't started yet. Sit tight and make sure your websocket connection is up and connected".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
-
-    if let Some(player_id) = headers.get("playerid") {
-        let player_id = player_id.to_str().unwrap();
-        let playerid_playername_map_guard = playerid_playername_map.read().await;
-        if let Some(player_name) = playerid_playername_map_guard.get(player_id) {
-            
-            // I don't want to keep a lock on the rate limit map for too long, so let's get the data, clone it, then drop it
-            let mut playername_rate_limit_map_guard = playername_rate_limit_map.lock().await;
-            let rate_limit = match playername_rate_limit_map_guard.get_mut(player_name) {
-                Some(rate_limit) => {
-                    *rate_limit += 1;
-                    *rate_limit
-                }
-                None => {
-                    println!("{}[!] {:?} | Rate limit not found for playername{}", CL::Red.get(), player_name, CL::End.get());
-                    let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string() };
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            };
-            drop(playername_rate_limit_map_guard);
-
-
-            match rate_limit > rate_limit_per_second {
-                true => {
-                    let response = HTTPResponse { status: "RATE_LIMIT".to_string(), message: "Settle down there mate, you've reached >10 orders/second. Please wait 1 second till your limits are reset".to_string()};
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                },
-                false => {
-
-                    let card = match data.card.as_str() {
-                        "spade" => Card::Spade,
-                        "club" => Card::Club,
-                        "diamond" => Card::Diamond,
-                        "heart" => Card::Heart,
-                        _ => {
-                            println!("{}[!] Invalid card{}", CL::Red.get(), CL::End.get());
-                            let response = HTTPResponse { status: "INVALID_CARD".to_string(), message: "For the card, please send either `spade`, `club`, `diamond`, or `heart`".to_string()};
-                            let serialized_response = serde_json::to_string(&response).unwrap();
-                            return HttpResponse::Ok().json(serialized_response);
-                        }
-                    };
-
-                    let direction = match data.direction.as_str() {
-                        "buy" => Direction::Buy,
-                        "sell" => Direction::Sell,
-                        _ => {
-                            println!("{}[!] Invalid direction{}", CL::Red.get(), CL::End.get());
-                            let response = HTTPResponse { status: "INVALID_DIRECTION".to_string(), message: "For the direction, please send either `buy` or `sell`".to_string()};
-                            let serialized_response = serde_json::to_string(&response).unwrap();
-                            return HttpResponse::Ok().json(serialized_response);
-                        }
-                    };
-
-                    let order = Order {
-                        player_name: player_name.to_string(),
-                        card,
-                        direction,
-                        price: None
-                    };
-
-                    let (oneshot_sender, receiver) = oneshot::channel();
-                    sender_arc.send((order, oneshot_sender)).await.unwrap();
-
-                    let response = receiver.await.unwrap();
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            }
-        } else {
-            //println!("{}[!] Player name not found{}", CL::Orange.get(), CL::End.get());
-            let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string()};
-            let serialized_response = serde_json::to_string(&response).unwrap();
-            return HttpResponse::Ok().json(serialized_response);
-        }
-    } else {
-        println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
-        let response = HTTPResponse { status: "MISSING_HEADER".to_string(), message: "Required headers not found, please send 'playerid' header with your request. If this is for testnet, send anything. During the tournament you'll be given a unique ID that should be placed here".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
+    let received = Received::of(&req);
+    let data = match parse_json::<RawCancelOrderData>(&req, &body) {
+        Ok(data) => data,
+        Err(response) => return received.apply(None, response),
+    };
+    let (player_name, rate_limit) = match authorize_player(&req, &body, Endpoint::Cancel, &started_game, &auth, &playerid_playername_map, &rate_limiter).await {
+        Ok(authorized) => authorized,
+        Err(error) => return received.apply(None, respond(&req, Err(error))),
+    };
+
+    let mut sequence = None;
+    let result = async {
+        let data = data?;
+        let card = parse_card(&data.card)?;
+        let direction = parse_direction(&data.direction)?;
+
+        let order = Order {
+            player: PlayerId::lookup(&player_name).ok_or(ApiError::UnknownPlayer)?,
+            card,
+            direction,
+            price: None
+        };
+        submit_order(&order_queue, order, received, &mut sequence).await
+    }.await;
+
+    rate_limit.apply(received.apply(sequence, respond(&req, result)))
 }
 
 
@@ -356,68 +231,22 @@ async fn cancel_handler(
 async fn inventory_handler(
     req: HttpRequest,
     started_game: web::Data<Arc<AtomicBool>>,
-    matching_engine: web::Data<Arc<Mutex<MatchingEngine>>>,
+    engine_view: web::Data<SharedView>,
     playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
-    playername_rate_limit_map: web::Data<Arc<Mutex<HashMap<String, u8>>>>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    auth: web::Data<Arc<Auth>>,
 ) -> impl Responder {
-    let rate_limit_per_second = 10; // rate limit is shared with /inventory and /order
-    let headers = req.headers();
-
-    if !started_game.load(Ordering::Acquire) {
-        println!("{}[!] Game hasn't started yet{}", CL::Red.get(), CL::End.get());
-        let response = HTTPResponse { status: "NO_GAME".to_string(), message: "Game hasn't started yet. Sit tight and make sure your websocket connection is up and connected".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
-
-    if let Some(player_id) = headers.get("playerid") {
-        let player_id = player_id.to_str().unwrap();
-        let playerid_playername_map_guard = playerid_playername_map.read().await;
-        if let Some(player_name) = playerid_playername_map_guard.get(player_id) {
-            
-            // I don't want to keep a lock on the rate limit map for too long, so let's get the data, clone it, then drop it (there's gotta be a better way to do this)
-            let mut playername_rate_limit_map_guard = playername_rate_limit_map.lock().await;
-            let rate_limit = match playername_rate_limit_map_guard.get_mut(player_name) {
-                Some(rate_limit) => {
-                    *rate_limit += 1;
-                    *rate_limit
-                }
-                None => {
-                    println!("{}[!] {:?} | Rate limit not found for playername{}", CL::Red.get(), player_name, CL::End.get());
-                    let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string() };
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            };
-            drop(playername_rate_limit_map_guard);
-
-
-            match rate_limit > rate_limit_per_second {
-                true => {
-                    let response = HTTPResponse { status: "RATE_LIMIT".to_string(), message: "Settle down there mate, you've reached >10 orders/second. Please wait 1 second till your limits are reset".to_string()};
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                },
-                false => {
-                    let inventory = matching_engine.lock().await.get_player_inventory(player_name);
-                    let response = HTTPResponse { status: "SUCCESS".to_string(), message: format!("{},{},{},{}", inventory.spades, inventory.clubs, inventory.diamonds, inventory.hearts) };
-                    let serialized_response = serde_json::to_string(&response).unwrap();
-                    return HttpResponse::Ok().json(serialized_response);
-                }
-            }
-        } else {
-            //println!("{}[!] Player name not found{}", CL::Orange.get(), CL::End.get());
-            let response = HTTPResponse { status: "UNKNOWN_PLAYER".to_string(), message: "Player name not found. Have you sent a post to /register_testnet?".to_string()};
-            let serialized_response = serde_json::to_string(&response).unwrap();
-            return HttpResponse::Ok().json(serialized_response);
-        }
-    } else {
-        println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
-        let response = HTTPResponse { status: "MISSING_HEADER".to_string(), message: "Required headers not found, please send 'playerid' header with your request".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
-
+    let (player_name, rate_limit) = match authorize_player(&req, &[], Endpoint::Inventory, &started_game, &auth, &playerid_playername_map, &rate_limiter).await {
+        Ok(authorized) => authorized,
+        Err(error) => return respond(&req, Err(error)),
+    };
+
+    // straight from the engine's last published view, the engine itself is never touched
+    let inventory = match PlayerId::lookup(&player_name).and_then(|player| engine_view.load().player_inventories.get(&player).copied()) {
+        Some(inventory) => inventory,
+        None => return rate_limit.apply(respond(&req, Err(ApiError::UnknownPlayer))),
+    };
+    rate_limit.apply(respond(&req, Ok(format!("{},{},{},{}", inventory.spades, inventory.clubs, inventory.diamonds, inventory.hearts))))
 }
 
 
@@ -432,58 +261,67 @@ async fn player_handler(
     let player_names = playerid_playername_map_guard.values().cloned().collect::<Vec<String>>().join(",");
     drop(playerid_playername_map_guard);
 
-    let response = HTTPResponse { status: "SUCCESS".to_string(), message: player_names};
-    let serialized_response = serde_json::to_string(&response).unwrap();
-    return HttpResponse::Ok().json(serialized_response);
+    respond(&req, Ok(player_names))
+}
+
+
+#[get("/bandwidth")]
+async fn bandwidth_handler(
+    req: HttpRequest,
+    player_ws_map: web::Data<PlayerWsMap>,
+) -> impl Responder {
+
+    // websocket bytes sent per player (summed over their open sessions), raw vs what actually went out after compression
+    let mut bandwidth: HashMap<String, Bandwidth> = HashMap::new();
+    let player_ws_map_guard = player_ws_map.lock().await;
+    for connection in player_ws_map_guard.values() {
+        if let Some(outbox) = connection.sender.websocket() {
+            bandwidth.entry(connection.player_name.clone()).or_default().add(&outbox.stats().bandwidth);
+        }
+    }
+    drop(player_ws_map_guard);
+
+    respond(&req, Ok(serde_json::to_string(&bandwidth).unwrap()))
+}
+
+
+#[get("/queue")]
+async fn queue_handler(
+    req: HttpRequest,
+    order_queue: web::Data<Arc<OrderQueue>>,
+) -> impl Responder {
 
+    // how many orders are waiting on the matching engine right now, + how many were turned away (BUSY) or timed out
+    let stats = order_queue.engine_stats().await.map(|stats| serde_json::to_string(&stats).unwrap());
+    respond(&req, stats)
 }
 
 
 #[post("/register_testnet")]
 async fn register_testnet_handler(
     req: HttpRequest,
-    matching_engine: web::Data<Arc<Mutex<MatchingEngine>>>,
-    playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
-    playername_rate_limit_map: web::Data<Arc<Mutex<HashMap<String, u8>>>>,
+    admin: web::Data<Arc<AdminState>>,
 ) -> impl Responder {
     let headers = req.headers();
     // get their supplied playerid and then generate a random player_name (String format),
     // then add that player_name and playerid to the maps
     // return that name to them while echoing back their playerid
 
-    if let Some(player_id) = headers.get("playerid") {
-        let player_id = player_id.to_str().unwrap().to_owned();
-
-        // if player_id is already present, return the player_name
-        let mut playerid_playername_map_guard = playerid_playername_map.write().await; // write lock since we'll be adding a player later if needed, on the return it'll drop the lock automatically
-        if let Some(player_name) = playerid_playername_map_guard.get(&player_id) {
-            let response = HTTPResponse { status: "SUCCESS".to_string(), message: format!("Temp player name: {}. Testnet will always send out 3 cards of each suit to test with", player_name) };
-            let serialized_response = serde_json::to_string(&response).unwrap();
-            return HttpResponse::Ok().json(serialized_response);
+    let player_id = match headers.get("playerid").map(|player_id| player_id.to_str()) {
+        Some(Ok(player_id)) => player_id.to_owned(),
+        Some(Err(_)) => return respond(&req, Err(ApiError::ParseError("Failed to read the 'playerid' header".to_string()))),
+        None => {
+            println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
+            return respond(&req, Err(ApiError::MissingHeader("Required headers not found. Please send 'playerid' in your Headers with a random ID. We'll register this playerid into the testnet and send you back a temporary PlayerName".to_string())));
         }
+    };
 
-        let player_name = generate_random_player_name();
-
-        playerid_playername_map_guard.insert(player_id.clone(), player_name.clone());
-        drop(playerid_playername_map_guard);
-
-        let mut playername_rate_limit_map_guard = playername_rate_limit_map.lock().await;
-        playername_rate_limit_map_guard.insert(player_name.clone(), 0);
-        drop(playername_rate_limit_map_guard);
+    let player_name = match admin.register_testnet(&player_id).await {
+        Ok(player_name) => player_name,
+        Err(error) => return respond(&req, Err(error)),
+    };
 
-        matching_engine.lock().await.add_new_player_with_inventory(player_name.clone(), Inventory { spades: 3, clubs: 3, diamonds: 3, hearts: 3 });
-        matching_engine.lock().await.print_all_players();
-
-        let response = HTTPResponse { status: "SUCCESS".to_string(), message: format!("Temp player name: {}. Testnet will always send out 3 cards of each suit to test with", player_name) };
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-
-    } else {
-        println!("{}[!] Required headers not found, please send 'playerid' header with your request{}", CL::Orange.get(), CL::End.get());
-        let response = HTTPResponse { status: "MISSING_HEADER".to_string(), message: "Required headers not found. Please send 'playerid' in your Headers with a random ID. We'll register this playerid into the testnet and send you back a temporary PlayerName".to_string()};
-        let serialized_response = serde_json::to_string(&response).unwrap();
-        return HttpResponse::Ok().json(serialized_response);
-    }
+    respond(&req, Ok(format!("Temp player name: {}. Testnet will always send out 3 cards of each suit to test with", player_name)))
 }
 
 
@@ -493,106 +331,373 @@ async fn register_testnet_handler(
 async fn main() {
     println!("=-= Starting Figgie Testnet Exchange =-=");
 
+    // ports, the bind address, the starting balance + book interval and the thread layout, see config.rs
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return;
+    }
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}[!] {}{}", CL::Red.get(), e, CL::End.get());
+            print!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    println!("[+] Listening on {} | REST {} | websocket {} | {} actix workers", config.bind_host, config.rest_port, config.ws_port, config.actix_workers.map(|workers| workers.to_string()).unwrap_or("default".to_string()));
+    println!("[+] Starting balance {} | books every {}ms", config.starting_balance, config.book_interval().as_millis());
+
+    // ROLE=engine runs the matching engine + the game and takes gateways on ENGINE_LINK, ROLE=gateway runs the REST / WS / UDS
+    // servers against the engine on ENGINE_LINK, ROLE=all (default) is both in one process and only takes gateways if ENGINE_LINK is set
+    let role = match std::env::var("ROLE") {
+        Ok(role) => Role::parse(&role).unwrap_or_else(|| {
+            println!("{}[!] Unknown ROLE {:?}, expected all, engine or gateway. Running everything{}", CL::Orange.get(), role, CL::End.get());
+            Role::All
+        }),
+        Err(_) => Role::All,
+    };
+    let engine_link_addr = match std::env::var("ENGINE_LINK") {
+        Ok(addr) => Some(LinkAddr::parse(&addr)),
+        Err(_) if role == Role::All => None,
+        Err(_) => Some(LinkAddr::parse(ENGINE_LINK)),
+    };
+    match &engine_link_addr {
+        Some(addr) => println!("[+] Role | {:?} | engine link on {}", role, addr),
+        None => println!("[+] Role | {:?}", role),
+    }
+
+    // the engine + every gateway need the same LINK_SECRET, both ends of the link have to prove they have it
+    let link_secret = engine_link_addr.as_ref().map(|_| {
+        match std::env::var("LINK_SECRET").map_err(|_| "LINK_SECRET isn't set".to_string()).and_then(|secret| LinkSecret::new(&secret)) {
+            Ok(link_secret) => link_secret,
+            Err(e) => {
+                println!("{}[!] {}, the engine link needs it on the engine + every gateway{}", CL::Red.get(), e, CL::End.get());
+                std::process::exit(1);
+            }
+        }
+    });
+    // a gateway's line to the engine, which also checks its nonces + charges its rate limits so every gateway shares them
+    let engine_link = match (role, &engine_link_addr, &link_secret) {
+        (Role::Gateway, Some(addr), Some(link_secret)) => Some(EngineLink::new(addr.clone(), link_secret.clone())),
+        _ => None,
+    };
+
     // =-------------------------------------------------------------------------------------------------------= //
 
     let playerid_playername_map: Arc<RwLock<HashMap<String, String>>> = Arc::new(RwLock::new(HashMap::new())); // playerid -> playername
-    let playername_rate_limit_map: Arc<Mutex<HashMap<String, u8>>> = Arc::new(Mutex::new(HashMap::new())); // playername -> rate_limit
-
 
-    let player_ws_map: Arc<Mutex<HashMap<String, SplitSink<WebSocketStream<TcpStream>, Message>>>> = Arc::new(Mutex::new(HashMap::new())); // playername -> websocket
-    let player_ws_map_hotpath = Arc::clone(&player_ws_map);
 
+    let player_ws_map: PlayerWsMap = Arc::new(Mutex::new(HashMap::new())); // session id -> websocket
+    let player_ws_map_publisher = Arc::clone(&player_ws_map);
+    let max_sessions_per_player = match std::env::var("MAX_SESSIONS_PER_PLAYER").ok().and_then(|max| max.parse::<usize>().ok()) {
+        Some(max) => max,
+        None => MAX_SESSIONS_PER_PLAYER,
+    };
+    println!("[+] Allowing up to {} websocket sessions per player", max_sessions_per_player);
+
+
+    // optional multicast market data feed (e.g. MULTICAST_ADDR=239.1.1.1:9000) + a TCP snapshot service for gap recovery
+    // only from the engine's process, its events are the ones in the feed
+    let (multicast_publisher, multicast_snapshot) = match std::env::var("MULTICAST_ADDR").ok().map(|addr| addr.parse::<std::net::SocketAddr>()) {
+        Some(_) if role == Role::Gateway => {
+            println!("{}[!] Ignoring MULTICAST_ADDR, the engine's process publishes the multicast feed{}", CL::Orange.get(), CL::End.get());
+            (None, None)
+        },
+        Some(Ok(group)) => {
+            let interface = std::env::var("MULTICAST_INTERFACE").unwrap_or("0.0.0.0".to_string());
+            match MulticastPublisher::new(group, &interface) {
+                Ok((publisher, snapshot)) => {
+                    println!("[+] Publishing market data to multicast group {} from {}", group, interface);
+                    (Some(publisher), Some(snapshot))
+                },
+                Err(e) => {
+                    println!("{}[!] Failed to set up the multicast publisher: {:?}{}", CL::Red.get(), e, CL::End.get());
+                    (None, None)
+                }
+            }
+        },
+        Some(Err(e)) => {
+            println!("{}[!] Invalid MULTICAST_ADDR: {:?}{}", CL::Red.get(), e, CL::End.get());
+            (None, None)
+        },
+        None => (None, None),
+    };
+    let multicast_snapshot_addr = std::env::var("MULTICAST_SNAPSHOT_ADDR").unwrap_or("127.0.0.1:8081".to_string());
+
+
+    // optional TLS (https + wss) w/ TLS_CERT + TLS_KEY, add TLS_CLIENT_CA to also require client certificates signed by that CA
+    let tls_config = match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+        (Ok(cert_path), Ok(key_path)) => {
+            let client_ca_path = std::env::var("TLS_CLIENT_CA").ok();
+            let tls_config = tls::load_server_config(&cert_path, &key_path, client_ca_path.as_deref())
+                .unwrap_or_else(|e| panic!("[!] Failed to load the TLS config: {}", e)); // don't fall back to plaintext if TLS was asked for
+            println!("[+] TLS enabled | client certificates {}", if client_ca_path.is_some() { "required" } else { "not required" });
+            Some(tls_config)
+        },
+        _ => None,
+    };
+
+
+    // permessage-deflate for websocket clients that ask for it, WS_DEFLATE=off to never compress
+    let ws_deflate = match std::env::var("WS_DEFLATE").as_deref() {
+        Ok("off") | Ok("0") | Ok("false") => None,
+        _ => Some(DeflateConfig {
+            level: std::env::var("WS_DEFLATE_LEVEL").ok().and_then(|level| level.parse::<u32>().ok()).unwrap_or(WS_DEFLATE_LEVEL).min(9),
+            threshold: std::env::var("WS_DEFLATE_THRESHOLD").ok().and_then(|threshold| threshold.parse::<usize>().ok()).unwrap_or(WS_DEFLATE_THRESHOLD),
+        }),
+    };
+    match ws_deflate {
+        Some(config) => println!("[+] Websocket compression available | level {} | threshold {} bytes", config.level, config.threshold),
+        None => println!("[+] Websocket compression disabled"),
+    }
 
-    let started: Arc<AtomicBool> = Arc::new(AtomicBool::new(false)); // used to signal if there's an active game or not
-    let matching_engine: Arc<Mutex<MatchingEngine>> = Arc::new(Mutex::new(MatchingEngine::new(STARTING_BALANCE, player_ws_map_hotpath))); // init the matching engine
-    let matching_engine_hotpath = Arc::clone(&matching_engine);
+    // every websocket session queues up to WS_OUTBOUND_QUEUE messages, WS_SLOW_CONSUMER (drop, conflate or block) says what happens past that
+    let ws_outbound = OutboundConfig {
+        capacity: std::env::var("WS_OUTBOUND_QUEUE").ok().and_then(|capacity| capacity.parse::<usize>().ok()).unwrap_or(WS_OUTBOUND_QUEUE).max(1),
+        policy: match std::env::var("WS_SLOW_CONSUMER") {
+            Ok(policy) => SlowConsumerPolicy::parse(&policy).unwrap_or_else(|| {
+                println!("{}[!] Unknown WS_SLOW_CONSUMER {:?}, expected drop, conflate or block. Dropping slow consumers{}", CL::Orange.get(), policy, CL::End.get());
+                SlowConsumerPolicy::Drop
+            }),
+            Err(_) => SlowConsumerPolicy::Drop,
+        },
+    };
+    println!("[+] Websocket outbound queues | {} messages | slow consumers: {:?}", ws_outbound.capacity, ws_outbound.policy);
+
+
+    // API key auth: ADMIN_API_KEY + ADMIN_API_SECRET for signed admin requests, ADMIN_ID for the old `adminid` header,
+    // REQUIRE_SIGNED_REQUESTS=1 turns off everything unsigned (the `playerid` header / field and `adminid`)
+    let require_signed = matches!(std::env::var("REQUIRE_SIGNED_REQUESTS").as_deref(), Ok("1") | Ok("true") | Ok("on"));
+    let admin_id = std::env::var("ADMIN_ID").ok();
+    let admin_credentials = match (std::env::var("ADMIN_API_KEY"), std::env::var("ADMIN_API_SECRET")) {
+        (Ok(api_key), Ok(secret)) => Some((api_key, secret)),
+        _ => None,
+    };
+    if admin_id.is_none() && admin_credentials.is_none() {
+        println!("{}[!] No admin credential configured (ADMIN_ID or ADMIN_API_KEY + ADMIN_API_SECRET), /admin will refuse everything{}", CL::Orange.get(), CL::End.get());
+    }
+    println!("[+] Auth | signed requests {}", if require_signed { "required" } else { "optional (playerid still works)" });
+    let player_master_key = link_secret.as_ref().map(LinkSecret::player_master_key); // so the engine + gateways derive the same player secrets
+    let auth = Arc::new(Auth::new(require_signed, admin_id, admin_credentials, player_master_key, engine_link.clone()));
+
+
+    // per player token buckets shared by every way of placing orders (RestAPI, websocket, UDS gateway)
+    let mut rate_limit_config = RateLimitConfig::default();
+    for (var, field) in [("RATE_LIMIT_BURST", &mut rate_limit_config.burst), ("RATE_LIMIT_REFILL_PER_SECOND", &mut rate_limit_config.refill_per_second)] {
+        if let Ok(value) = std::env::var(var) {
+            match value.parse::<u32>() {
+                Ok(tokens) if tokens > 0 => *field = tokens,
+                _ => println!("{}[!] Ignoring {}: {:?} should be a whole number of tokens, at least 1{}", CL::Orange.get(), var, value, CL::End.get()),
+            }
+        }
+    }
+    if let Ok(weights) = std::env::var("RATE_LIMIT_WEIGHTS") {
+        if let Err(e) = rate_limit_config.set_weights(&weights) {
+            println!("{}[!] Ignoring RATE_LIMIT_WEIGHTS: {}{}", CL::Orange.get(), e, CL::End.get());
+        }
+    }
+    // e.g. a burst lowered under the default weights
+    if let Err(e) = rate_limit_config.validate() {
+        println!("{}[!] Ignoring the rate limit settings, {}. Using the defaults{}", CL::Orange.get(), e, CL::End.get());
+        rate_limit_config = RateLimitConfig::default();
+    }
+    println!("[+] Rate limit | {} token burst | {} tokens/s | order {} | cancel {} | inventory {}", rate_limit_config.burst, rate_limit_config.refill_per_second, rate_limit_config.order_weight, rate_limit_config.cancel_weight, rate_limit_config.inventory_weight);
+    let rate_limiter = Arc::new(match &engine_link {
+        Some(engine_link) => RateLimiter::link(rate_limit_config, Arc::clone(engine_link)),
+        None => RateLimiter::new(rate_limit_config),
+    });
 
 
-    let (sender, receiver) = kanal::unbounded_async::<(Order, OneshotSender<HTTPResponse>)>(); // channel between RestAPI and matching engine, oneshot for responses
-    let sender_arc = Arc::new(sender);
+    let started: Arc<AtomicBool> = Arc::new(AtomicBool::new(false)); // used to signal if there's an active game or not
+    let (event_sender, event_receiver) = publisher::event_queue(); // the engine's events -> the publisher thread, which does all the network IO
+
+
+    // bounded queue between the RestAPI / websocket / UDS gateway and the matching engine, ORDER_TRANSPORT (kanal, ring or ring_spin) says what carries it
+    let order_queue_depth = std::env::var("ORDER_QUEUE_DEPTH").ok().and_then(|depth| depth.parse::<usize>().ok()).unwrap_or(ORDER_QUEUE_DEPTH);
+    let engine_timeout = std::env::var("ENGINE_TIMEOUT_MS").ok().and_then(|timeout| timeout.parse::<u64>().ok()).map(Duration::from_millis).unwrap_or(ENGINE_TIMEOUT);
+    // SEQUENCER_WINDOW_US holds every order that long after a gateway first saw it so they're matched in the order they came in, see sequencer.rs
+    let sequencer_window = std::env::var("SEQUENCER_WINDOW_US").ok().and_then(|window| window.parse::<u64>().ok()).map(Duration::from_micros).unwrap_or(SEQUENCER_WINDOW);
+    let order_transport = match std::env::var("ORDER_TRANSPORT") {
+        Ok(transport) => QueueTransport::parse(&transport).unwrap_or_else(|| {
+            println!("{}[!] Unknown ORDER_TRANSPORT {:?}, expected kanal, ring or ring_spin. Using kanal{}", CL::Orange.get(), transport, CL::End.get());
+            QueueTransport::Kanal
+        }),
+        Err(_) => QueueTransport::Kanal,
+    };
+    let (order_queue, engine_view, replay_buffer, hotpath) = match role {
+        Role::Gateway => {
+            // the link keeps local copies of the engine's view, the registered players + their keys, and feeds the engine's events to this process' publisher
+            let engine_link = engine_link.clone().unwrap();
+            let engine_view: SharedView = Arc::new(arc_swap::ArcSwap::from_pointee(EngineView::new()));
+            let mirror = Mirror {
+                engine_view: Arc::clone(&engine_view),
+                started_game: Arc::clone(&started),
+                playerid_playername_map: Arc::clone(&playerid_playername_map),
+                rate_limiter: Arc::clone(&rate_limiter),
+                auth: Arc::clone(&auth),
+                player_ws_map: Arc::clone(&player_ws_map),
+                events: event_sender,
+            };
+            let replay_buffer = engine_link.replay_buffer(); // filled by the link as the engine's events come in
+            (OrderQueue::link(Arc::clone(&engine_link), engine_timeout), engine_view, replay_buffer, Hotpath::Gateway(engine_link, mirror))
+        },
+        Role::Engine | Role::All => {
+            let matching_engine = MatchingEngine::new(config.starting_balance, event_sender); // owned by the hotpath thread, everything else goes through the order queue
+            let engine_view: SharedView = Arc::clone(&matching_engine.view); // what the engine looked like after the last thing it did, for anything that only reads
+            let (order_queue, receiver) = OrderQueue::new(order_queue_depth, engine_timeout, order_transport, sequencer_window);
+            (order_queue, engine_view, SharedReplayBuffer::new(), Hotpath::Engine(Box::new(matching_engine), receiver))
+        },
+    };
+    println!("[+] Order queue | {:?} | {} orders deep | {}ms engine timeout", order_queue.transport, order_queue.stats().capacity, engine_timeout.as_millis());
+    match (role, sequencer_window.is_zero()) {
+        (Role::Gateway, _) => {},
+        (_, true) => println!("[+] Sequencer off, orders are matched in the order they reach the queue"),
+        (_, false) => println!("[+] Sequencer | orders are held {}µs after they're received, then matched in the order they came in", sequencer_window.as_micros()),
+    }
+    let order_queue = Arc::new(order_queue);
+    let admin_state = Arc::new(AdminState::new(Arc::clone(&started), Arc::clone(&order_queue), Arc::clone(&engine_view), Arc::clone(&playerid_playername_map), Arc::clone(&rate_limiter), Arc::clone(&auth), Arc::clone(&player_ws_map)));
+    let replicas = Arc::clone(&admin_state.replicas); // the publisher forwards every event to the gateways
+    let replay_buffer_publisher = (role != Role::Gateway).then(|| replay_buffer.clone()); // on a gateway the link fills it
+    let admin_state_link = Arc::clone(&admin_state);
+    let order_entry = OrderEntry { // websocket + UDS gateway
+        started_game: Arc::clone(&started),
+        order_queue: Arc::clone(&order_queue),
+        rate_limiter: Arc::clone(&rate_limiter),
+    };
+
+
+    // optional order entry + market data over a unix socket for bots on the same box (e.g. UDS_GATEWAY_PATH=/tmp/figgie.sock)
+    let uds_gateway_path = std::env::var("UDS_GATEWAY_PATH").ok();
+    let uds_gateway_state = GatewayState {
+        playerid_playername_map: Arc::clone(&playerid_playername_map),
+        order_entry: order_entry.clone(),
+        engine_view: Arc::clone(&engine_view),
+        player_ws_map: Arc::clone(&player_ws_map),
+        max_sessions: max_sessions_per_player,
+        auth: Arc::clone(&auth),
+    };
 
 
     let (ws_shutdown_tx, mut ws_shutdown_rx) = tokio::sync::oneshot::channel();
-    let (rate_shutdown_tx, mut rate_shutdown_rx) = tokio::sync::oneshot::channel();
-    let (hotpath_shutdown_tx, mut hotpath_shutdown_rx) = tokio::sync::oneshot::channel();
+    let (uds_shutdown_tx, uds_shutdown_rx) = tokio::sync::oneshot::channel();
+    let (snapshot_shutdown_tx, snapshot_shutdown_rx) = tokio::sync::oneshot::channel();
+    let (hotpath_shutdown_tx, hotpath_shutdown_rx) = tokio::sync::oneshot::channel();
+    let (publisher_shutdown_tx, publisher_shutdown_rx) = tokio::sync::oneshot::channel();
+    let (link_shutdown_tx, link_shutdown_rx) = tokio::sync::oneshot::channel();
     let ctrl_c_signal = tokio::spawn(async move {
         ctrl_c().await.expect("[!] Failed to listen for Ctrl+C signal");
-        // WS, rate limit, & hotpath cause a hang on Ctrl + C, so we'll send a shutdown signal to them
+        // WS, hotpath & publisher cause a hang on Ctrl + C, so we'll send a shutdown signal to them
         let _ = ws_shutdown_tx.send(());
-        let _ = rate_shutdown_tx.send(());
+        let _ = uds_shutdown_tx.send(());
+        let _ = snapshot_shutdown_tx.send(());
         let _ = hotpath_shutdown_tx.send(());
+        let _ = publisher_shutdown_tx.send(());
+        let _ = link_shutdown_tx.send(());
     });
 
 
     // =-------------------------------------------------------------------------------------------------------= //
 
+    let config_network = config.clone();
     let network_thread = std::thread::Builder::new()
         .spawn(move || {
-        let res = core_affinity::set_for_current(core_affinity::CoreId { id: 0 });
-        if res {
-            let rt = tokio::runtime::Builder::new_multi_thread() // multi-thread shouldn't be needed but I'm unsure of the Actix Web framework and its assumptions of the environment it's in
-                .enable_all()
-                .build()
-                .expect("build runtime");
-            rt.block_on(async {
-                let mut handles = Vec::new();
-
-                // =-= Rate Limit Monitoring =-= //
-                let playername_rate_limit_map_monitoring = Arc::clone(&playername_rate_limit_map);
-                let rate_limit_monitoring = tokio::task::spawn(async move {
-                    loop {
-                        tokio::select! {
-                            _ = &mut rate_shutdown_rx => {
-                                break;
-                            }
-                            _ = tokio::time::sleep(Duration::from_secs(1)) => {
-                                let mut playername_rate_limit_map_guard = playername_rate_limit_map_monitoring.lock().await;
-                                for (_, rate_limit) in playername_rate_limit_map_guard.iter_mut() {
-                                    *rate_limit = 0;
-                                }
-                            }
-                        }
-                    }
-                });
-                handles.push(rate_limit_monitoring);
-
+        config_network.pin("Network", config_network.network_core);
+        let rt = config_network.network_runtime.build().expect("build runtime");
+        // a LocalSet since the engine link's server + admin actions it runs can start the game loop
+        tokio::task::LocalSet::new().block_on(&rt, async {
+            let mut handles = Vec::new();
+
+            // =-= Engine Link =-= //
+            if let (Some(addr), Some(link_secret), Role::Engine | Role::All) = (engine_link_addr, link_secret, role) {
+                handles.push(tokio::task::spawn_local(link::serve(addr, link_secret, admin_state_link, link_shutdown_rx)));
+            }
 
-                // =-= REST API =-= //
+            // =-= REST API =-= //
+            if role != Role::Engine {
                 let player_password_map_rest = Arc::clone(&playerid_playername_map);
+                let engine_view_websocket = Arc::clone(&engine_view);
+                let player_ws_map_sse = Arc::clone(&player_ws_map);
+                let replay_buffer_rest = replay_buffer.clone();
+                let tls_config_rest = tls_config.clone();
+                let bind_host_rest = config_network.bind_host.clone();
+                let (rest_port, actix_workers) = (config_network.rest_port, config_network.actix_workers);
+                let auth_rest = Arc::clone(&auth);
                 let rest_api = tokio::task::spawn(async move {
-                    if let Err(e) = HttpServer::new(move || {
+                    let server = HttpServer::new(move || {
                         let cors = Cors::default()
                             .allow_any_origin()
                             .allow_any_method()
                             .allow_any_header()
                             .max_age(3600);
-                        
+                    
                         App::new()
                             .wrap(cors)
+                            .wrap_fn(|req, service| { // stamps every request before its body's read, /order + /cancel hand it to the sequencer
+                                req.extensions_mut().insert(Received::now());
+                                service.call(req)
+                            })
                             .app_data(web::Data::new(Arc::clone(&player_password_map_rest)))
-                            .app_data(web::Data::new(Arc::clone(&playername_rate_limit_map)))
+                            .app_data(web::Data::new(Arc::clone(&rate_limiter)))
                             .app_data(web::Data::new(Arc::clone(&started)))
-                            .app_data(web::Data::new(Arc::clone(&matching_engine)))
-                            .app_data(web::Data::new(Arc::clone(&sender_arc)))
+                            .app_data(web::Data::new(Arc::clone(&engine_view)))
+                            .app_data(web::Data::new(Arc::clone(&order_queue)))
+                            .app_data(web::Data::new(Arc::clone(&player_ws_map_sse)))
+                            .app_data(web::Data::new(replay_buffer_rest.clone()))
+                            .app_data(web::Data::new(max_sessions_per_player))
+                            .app_data(web::Data::new(Arc::clone(&auth_rest)))
+                            .app_data(web::Data::new(Arc::clone(&admin_state)))
                             .service(order_handler)
                             .service(cancel_handler)
                             .service(inventory_handler)
                             .service(admin_handler)
                             .service(register_testnet_handler)
                             .service(player_handler)
-                    })
-                    .bind(("127.0.0.1", 8090)).expect("[!] Failed to bind the address") // this will fail the whole exchange if something else is already binded to this port
+                            .service(stream_handler)
+                            .service(bandwidth_handler)
+                            .service(queue_handler)
+                            .service( // same handlers, they answer w/ real status codes + plain JSON when called under /v2
+                                web::scope("/v2")
+                                    .service(order_handler)
+                                    .service(cancel_handler)
+                                    .service(inventory_handler)
+                                    .service(admin_handler)
+                                    .service(register_testnet_handler)
+                                    .service(player_handler)
+                                    .service(stream_handler)
+                                    .service(bandwidth_handler)
+                                    .service(queue_handler)
+                            )
+                    });
+                    let server = match actix_workers {
+                        Some(workers) => server.workers(workers),
+                        None => server,
+                    };
+                    let server = match tls_config_rest {
+                        Some(tls_config) => server.bind_rustls_0_23((bind_host_rest.as_str(), rest_port), tls_config),
+                        None => server.bind((bind_host_rest.as_str(), rest_port)),
+                    };
+                    if let Err(e) = server.expect("[!] Failed to bind the address") // this will fail the whole exchange if something else is already binded to this port
                     .run()
                     .await {
                         println!("[!] Error with the REST API server: {:?}", e);
                     }
                 });
                 handles.push(rest_api);
-                
-                
+            
+            
                 // =-= Websocket Server =-= //
+                let auth_websocket = Arc::clone(&auth);
                 let websocket = tokio::task::spawn(async move {
-                    if let Ok(listener) = TcpListener::bind(&"127.0.0.1:8080").await {
+                    let ws_settings = WsSettings {
+                        max_sessions: max_sessions_per_player,
+                        tls_acceptor: tls_config.map(|tls_config| tokio_rustls::TlsAcceptor::from(Arc::new(tls_config))),
+                        deflate: ws_deflate,
+                        outbound: ws_outbound,
+                        order_entry,
+                        replay_buffer,
+                    };
+                    if let Ok(listener) = TcpListener::bind((config_network.bind_host.as_str(), config_network.ws_port)).await {
 
                         loop {
                             tokio::select! {
@@ -603,90 +708,8 @@ async fn main() {
                                     if let Ok((stream, addr)) = result {
                                         let player_ws_map_network_inside = Arc::clone(&player_ws_map);
                                         let playerid_playername_map_websocket = Arc::clone(&playerid_playername_map);
-                                        tokio::spawn(async move {
-                                            println!("[+] WS |:| Incoming TCP connection from: {:?}", addr);
-
-                                            match tokio_tungstenite::accept_async(stream).await {
-                                                Ok(ws_stream) => {
-                                                    println!("{}[+] WS |:| WebSocket connection established: {:?}{}", CL::Green.get(), addr, CL::End.get());
-
-                                                    let (mut sender, mut receiver) = ws_stream.split();
-                                                    while let Some(msg) = receiver.next().await {
-                                                        if let Ok(msg) = msg {
-                                                            println!("{}[-] WS |:| Received a message: {:?}{}", CL::Dull.get(), msg, CL::End.get());
-                                                
-                                                            match msg {
-                                                                Message::Text(text) => {
-                                                                    if let Ok(message) = serde_json::from_str::<SubscribeMessage>(&text) {
-                                                                        if message.action == "subscribe" {
-                                                                            println!("{}[-] WS |:| Attempting to subscribe to the exchange{}", CL::Dull.get(), CL::End.get());
-                                                    
-                                                                            match playerid_playername_map_websocket.read().await.get(&message.playerid) {
-                                                                                Some(player_name) => {
-
-                                                                                    // =-= SUCCESS =-= //
-                                                                                    println!("{}[+] WS |:| Successfully subscribed to the stream: {:?}{}", CL::DullTeal.get(), player_name, CL::End.get());
-                                                                                    let welcome_message = Message::Text(serde_json::to_string(&HTTPResponse {
-                                                                                        status: "SUCCESS".to_string(),
-                                                                                        message: format!("Welcome to the tesetnet, {}! You've been subscribed for further data updates", player_name)
-                                                                                    }).unwrap());
-                                                                                    sender.send(welcome_message).await.unwrap();
-                                                    
-
-                                                                                    player_ws_map_network_inside.lock().await.insert(player_name.clone(), sender);
-                                                                                    break;
-
-                                                                                },
-                                                                                None => {
-
-                                                                                    // =-= ACCOUNT_NOT_FOUND =-= //
-                                                                                    println!("{}[!] WS |:| Account not found for the password given: {}{}", CL::Orange.get(), message.playerid, CL::End.get());
-                                                                                    let response_message = Message::Text(serde_json::to_string(&HTTPResponse {
-                                                                                        status: "UNKNOWN_PLAYER".to_string(),
-                                                                                        message: "Player name not found. Have you sent a post to /register_testnet?".to_string()
-                                                                                    }).unwrap());
-                                                                                    sender.send(response_message).await.unwrap();
-
-                                                                                }
-                                                                            }
-                                                                        } else {
-
-                                                                            // =-= UNAUTHORIZED_ACTION =-= //
-                                                                            println!("{}[!] WS |:| Unrecognized action: {:?} | Please send 'subscribe' with 'playerid'{}", CL::Orange.get(), message.action, CL::End.get());
-                                                                            let response_message = Message::Text(serde_json::to_string(&HTTPResponse {
-                                                                                status: "UNAUTHORIZED_ACTION".to_string(),
-                                                                                message: "Unauthorized action, please send 'subscribe' as the action".to_string()
-                                                                            }).unwrap());
-                                                                            sender.send(response_message).await.unwrap();
-
-                                                                        }
-                                                                    } else {
-
-                                                                        // =-= PARSE_ERROR =-= //
-                                                                        println!("{}[!] WS |:| Failed to parse the WS message{}", CL::Orange.get(), CL::End.get());
-                                                                        let response_message = Message::Text(serde_json::to_string(&HTTPResponse {
-                                                                            status: "PARSE_ERROR".to_string(),
-                                                                            message: "Failed to parse the message. Please send a JSON message with fields 'subscribe' and 'playerid' that match up with your PlayerName (in the testnet, send a random playerid)".to_string()
-                                                                        }).unwrap());
-                                                                        sender.send(response_message).await.unwrap();
-
-                                                                    }
-                                                                },
-                                                                Message::Close(_) => {
-                                                                    println!("{}[!] WS |:| Connection has been closed{}", CL::DullRed.get(), CL::End.get());
-                                                                    // cleanup is handled in the matching_engine
-                                                                    break;
-                                                                },
-                                                                _ => {}
-                                                            }
-                                                        }
-                                                    }
-                                                },
-                                                Err(e) => {
-                                                    println!("{}[!] Error accepting WS connection{}", CL::Red.get(), CL::End.get());
-                                                }
-                                            }
-                                        });
+                                        let engine_view_websocket = Arc::clone(&engine_view_websocket);
+                                        tokio::spawn(websocket::handle_connection(stream, addr, player_ws_map_network_inside, playerid_playername_map_websocket, engine_view_websocket, Arc::clone(&auth_websocket), ws_settings.clone()));
                                     }
                                 }
                             }
@@ -699,58 +722,55 @@ async fn main() {
                 handles.push(websocket);
 
 
-                for handle in handles {
-                    handle.await.unwrap();
+                // =-= UDS Gateway =-= //
+                if let Some(uds_gateway_path) = uds_gateway_path {
+                    let uds_gateway = tokio::task::spawn(uds_gateway::run(uds_gateway_path, uds_gateway_state, uds_shutdown_rx));
+                    handles.push(uds_gateway);
                 }
-            
-            });
-        }
+            }
+
+
+            // =-= Multicast Snapshot Service =-= //
+            if let Some(multicast_snapshot) = multicast_snapshot {
+                let snapshot_service = tokio::task::spawn(multicast::run_snapshot_service(multicast_snapshot_addr, multicast_snapshot, snapshot_shutdown_rx));
+                handles.push(snapshot_service);
+            }
+
+
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        
+        });
     }).unwrap();
 
+    let config_hotpath = config.clone();
     let hotpath_thread = std::thread::Builder::new()
         .spawn(move || {
-        let res = core_affinity::set_for_current(core_affinity::CoreId { id: 1 });
-        if res {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("build runtime");
-            rt.block_on(async {
-                loop {
-                    let timer = tokio::time::sleep(Duration::from_secs(5));
-
-                    tokio::select! {
-                        _ = &mut hotpath_shutdown_rx => {
-                            break;
-                        }
-                        result = receiver.recv() => {
-                            match result {
-                                Ok((order_data, response_sender)) => {
-                                    let response = matching_engine_hotpath.lock().await.process_order(order_data).await;
-                                    if let Err(e) = response_sender.send(response) {
-                                        println!("{}[!] Failed to send the response back to the RestAPI: {:?}{}", CL::Red.get(), e, CL::End.get()); // how to handle this? assume that the HTTP Connection was dropped?
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("{}[!] Matching Engine Receiver Failed: {:?}{}", CL::Red.get(), e, CL::End.get());
-                                }
-                            }
-                        }
-                        _ = timer => {
-                            // send out the current book state
-                            matching_engine_hotpath.lock().await.send_book_state().await;
-                        }
-                    }
-                }
-            });
+        config_hotpath.pin("Hotpath", config_hotpath.hotpath_core);
+        let rt = config_hotpath.hotpath_runtime.build().expect("build runtime");
+        match hotpath {
+            Hotpath::Engine(mut matching_engine, receiver) => rt.block_on(receiver.run(&mut matching_engine, config_hotpath.book_interval(), hotpath_shutdown_rx)),
+            Hotpath::Gateway(engine_link, mirror) => rt.block_on(engine_link.run(mirror, hotpath_shutdown_rx)),
         }
     }).unwrap();
 
 
+    // renders + sends everything the engine publishes (websockets, SSE, UDS + multicast) so the hotpath never touches a socket
+    let config_publisher = config.clone();
+    let publisher_thread = std::thread::Builder::new()
+        .spawn(move || {
+        config_publisher.pin("Publisher", config_publisher.publisher_core);
+        let rt = config_publisher.publisher_runtime.build().expect("build runtime");
+        rt.block_on(publisher::run(event_receiver, player_ws_map_publisher, multicast_publisher, replicas, replay_buffer_publisher, publisher_shutdown_rx));
+    }).unwrap();
+
+
     ctrl_c_signal.await.unwrap();
 
     network_thread.join().unwrap();
     hotpath_thread.join().unwrap();
+    publisher_thread.join().unwrap();
 
     println!("{}[+] All done!{}", CL::Dull.get(), CL::End.get());
 
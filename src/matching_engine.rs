@@ -9,99 +9,222 @@ use super::{
     Trade, 
     Direction, 
     CardBook,
+    PriceLevelBook,
     CL, 
+    StatsUpdate,
+    Event,
+    Snapshot,
+    GamePhase,
+    RoundInfo,
+    OpenOrder,
+    Announcement,
+    SchemaVersion,
+    Subscription,
+    PlayerId,
 };
+use super::publisher::EventSender;
+use arc_swap::ArcSwap;
 use rand::prelude::SliceRandom;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use rand::Rng;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use futures_util::stream::SplitSink;
-use tokio_tungstenite::WebSocketStream;
-use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::Message;
-use futures_util::SinkExt;
-use serde_json::json;
+
+
+const REPLAY_BUFFER_SIZE: usize = 2048; // ~how many of the latest published messages can be replayed after a reconnect
 
 
 pub struct MatchingEngine {
-    pub player_names: Vec<String>,
+    pub players: Vec<PlayerId>,
     pub starting_balance: i32,
     pub suits: [Card; 4],
     pub goal_suit: Card,
     pub common_suit: Card,
-    pub player_points: HashMap<String, i32>,
-    pub spades_book: CardBook,
-    pub clubs_book: CardBook,
-    pub diamonds_book: CardBook,
-    pub hearts_book: CardBook,
+    pub player_points: HashMap<PlayerId, i32>,
+    pub spades_book: PriceLevelBook,
+    pub clubs_book: PriceLevelBook,
+    pub diamonds_book: PriceLevelBook,
+    pub hearts_book: PriceLevelBook,
     pub pot: usize,
     pub ante: usize,
-    pub player_inventories: HashMap<String, Inventory>,
-    pub initial_points: HashMap<String, i32>,
+    pub player_inventories: HashMap<PlayerId, Inventory>,
+    pub initial_points: HashMap<PlayerId, i32>,
     pub starting_inventory: HashMap<Card, usize>,
-    pub player_ws_map_hotpath: Arc<Mutex<HashMap<String, SplitSink<WebSocketStream<TcpStream>, Message>>>>,
+    pub publisher: EventSender, // everything that goes out to the players goes through here
     pub rng: StdRng,
+    pub round_trades: usize,
+    pub round_volume: usize,
+    pub phase: GamePhase,
+    pub round: Option<RoundInfo>,
+    pub seq: u64, // sequence number of the last published message
+    pub replay_buffer: ReplayBuffer,
+    pub view: SharedView, // republished by `flush`
+    pending: Vec<(u64, Arc<Event>)>, // published since the last flush
 }
 
 
 impl MatchingEngine {
     pub fn new(
         starting_balance: i32,
-        player_ws_map_hotpath: Arc<Mutex<HashMap<String, SplitSink<WebSocketStream<TcpStream>, Message>>>>,
+        publisher: EventSender,
     ) -> Self {
 
         Self {
-            player_names: Vec::new(),
+            players: Vec::new(),
             starting_balance,
             suits: [Card::Spade, Card::Club, Card::Diamond, Card::Heart],
             goal_suit: Card::Spade,
             common_suit: Card::Club,
             player_points: HashMap::new(),
-            spades_book: CardBook::new(),
-            clubs_book: CardBook::new(),
-            diamonds_book: CardBook::new(),
-            hearts_book: CardBook::new(),
+            spades_book: PriceLevelBook::new(),
+            clubs_book: PriceLevelBook::new(),
+            diamonds_book: PriceLevelBook::new(),
+            hearts_book: PriceLevelBook::new(),
             pot: 0,
             ante: 0,
             player_inventories: HashMap::new(),
             initial_points: HashMap::new(),
             starting_inventory: HashMap::new(),
-            player_ws_map_hotpath,
+            publisher,
             rng: StdRng::from_entropy(),
+            round_trades: 0,
+            round_volume: 0,
+            phase: GamePhase::Waiting,
+            round: None,
+            seq: 0,
+            replay_buffer: ReplayBuffer::new(),
+            view: Arc::new(ArcSwap::from_pointee(EngineView::new())),
+            pending: Vec::new(),
         }
     }
 
 
-    pub fn add_new_player_with_inventory(&mut self, player_name: String, inventory: Inventory) {
-        self.player_names.push(player_name.clone());
-        self.player_points.insert(player_name.clone(), self.starting_balance);
-        self.player_inventories.insert(player_name.clone(), inventory);
-        self.initial_points.insert(player_name.clone(), self.starting_balance);
+    pub fn add_new_player_with_inventory(&mut self, player: PlayerId, inventory: Inventory) {
+        self.players.push(player);
+        self.player_points.insert(player, self.starting_balance);
+        self.player_inventories.insert(player, inventory);
+        self.initial_points.insert(player, self.starting_balance);
     }
 
 
     pub fn print_all_players(&self) {
-        println!("Players: {:?}", self.player_names);
+        println!("Players: {:?}", self.players.iter().map(|player| player.name()).collect::<Vec<_>>());
     }
 
 
     pub fn delete_all_players(&mut self) {
-        self.player_names.clear();
+        self.players.clear();
         self.player_points.clear();
         self.player_inventories.clear();
+        self.phase = GamePhase::Waiting;
+        self.round = None;
     }
 
 
-    pub fn pick_new_common_suit(&mut self) {
-        self.common_suit = self.suits[self.rng.gen_range(0..=3)].clone();
+    // takes the player out of the game (and their quotes off the books), false if they weren't in it
+    pub fn remove_player(&mut self, player: PlayerId) -> bool {
+        if !self.player_inventories.contains_key(&player) {
+            return false;
+        }
+        self.players.retain(|other| *other != player);
+        self.player_points.remove(&player);
+        self.player_inventories.remove(&player);
+        self.initial_points.remove(&player);
+
+        let mut had_quotes = false;
+        for book in [&mut self.spades_book, &mut self.clubs_book, &mut self.diamonds_book, &mut self.hearts_book] {
+            had_quotes |= book.has_quotes(player);
+            book.cancel_bid(player);
+            book.cancel_ask(player);
+        }
+        if had_quotes {
+            self.send_books();
+        }
+        true
+    }
+
+
+    pub fn reset_points(&mut self) {
+        for points in self.player_points.values_mut() {
+            *points = self.starting_balance;
+        }
+        self.initial_points = self.player_points.clone();
+    }
+
+
+    // stops trading until `resume`, process_order rejects everything in the meantime
+    pub fn pause(&mut self) {
+        self.phase = GamePhase::Paused;
+        self.announce("Trading has been paused".to_string());
+    }
+
+
+    // the round gets `remaining` more time from now on
+    pub fn resume(&mut self, remaining: Duration) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.phase = GamePhase::Trading;
+        if let Some(round) = &mut self.round {
+            round.ends_at = (now + remaining).as_millis();
+        }
+        self.announce("Trading has resumed".to_string());
     }
 
 
-    pub fn get_player_inventory(&self, player_name: &String) -> Inventory {
-        self.player_inventories.get(player_name).unwrap().clone()
+    // no scoring, the books are cleared and everyone keeps their points from before the round
+    pub fn abort_game(&mut self) {
+        if matches!(self.phase, GamePhase::Trading | GamePhase::Paused) {
+            self.player_points = self.initial_points.clone();
+        }
+        self.phase = GamePhase::Waiting;
+        self.round = None;
+        self.spades_book.reset_full_book();
+        self.clubs_book.reset_full_book();
+        self.diamonds_book.reset_full_book();
+        self.hearts_book.reset_full_book();
+
+        self.announce("The game has been aborted".to_string());
+        self.send_books();
+    }
+
+
+    pub fn announce(&mut self, message: String) {
+        let sent_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        println!("{}[+] ANNOUNCEMENT |:| {}{}", CL::DimLightBlue.get(), message, CL::End.get());
+        self.publish(Event::Announcement(Announcement { message, sent_at }));
+    }
+
+
+    // everything the engine knows, for the admin
+    pub fn dump_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "seq": self.seq,
+            "phase": self.phase,
+            "round": self.round,
+            "goal_suit": self.goal_suit.to_string().to_lowercase(),
+            "common_suit": self.common_suit.to_string().to_lowercase(),
+            "pot": self.pot,
+            "ante": self.ante,
+            "round_trades": self.round_trades,
+            "round_volume": self.round_volume,
+            "players": self.players,
+            "player_points": self.player_points,
+            "initial_points": self.initial_points,
+            "player_inventories": self.player_inventories,
+            "books": {
+                "spades": self.spades_book.card_book(),
+                "clubs": self.clubs_book.card_book(),
+                "diamonds": self.diamonds_book.card_book(),
+                "hearts": self.hearts_book.card_book(),
+            },
+            "replay_buffer": { "len": self.replay_buffer.len(), "oldest_seq": self.replay_buffer.oldest_seq() },
+        })
+    }
+
+
+    pub fn pick_new_common_suit(&mut self) {
+        self.common_suit = self.suits[self.rng.gen_range(0..=3)].clone();
     }
 
 
@@ -142,23 +265,33 @@ impl MatchingEngine {
 
         cards.shuffle(&mut self.rng); // randomly shuffle the cards
 
-        for (_, player_name) in self.player_names.iter().enumerate() { // for the testnet, we're not going to randomly draw cards - send out 3x of each
+        for player in self.players.iter() { // for the testnet, we're not going to randomly draw cards - send out 3x of each
             let player_inventory = Inventory { spades: 3, clubs: 3, diamonds: 3, hearts: 3 };
-            self.player_inventories.insert(player_name.clone(), player_inventory.clone());
+            self.player_inventories.insert(*player, player_inventory.clone());
         }
 
         starting_inventory
     }
 
 
-    pub async fn start_round(&mut self, round_number: usize) {
+    pub fn start_round(&mut self, round_number: usize, round_duration: Duration) {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.phase = GamePhase::Trading;
+        self.round = Some(RoundInfo {
+            round_number,
+            started_at: started_at.as_millis(),
+            ends_at: (started_at + round_duration).as_millis(),
+        });
+
         self.pot = 200; // make sure the pot is always 200 no matter the number of players (this is only for the testnet)
         self.ante = 50;
+        self.round_trades = 0;
+        self.round_volume = 0;
 
         println!("{}==================== ROUND {} ===================={}", CL::Purple.get(), round_number, CL::End.get());
         println!("");
         println!("=---= Game Details =---=");
-        println!("{} - Players: {}x{}", CL::Dull.get(), self.player_names.len(), CL::End.get());
+        println!("{} - Players: {}x{}", CL::Dull.get(), self.players.len(), CL::End.get());
         println!("{} - Ante: {}{}", CL::Dull.get(), self.ante, CL::End.get());
         println!("{} - Pot: 200{}", CL::Dull.get(), CL::End.get());
         println!("");
@@ -188,55 +321,32 @@ impl MatchingEngine {
         self.diamonds_book.reset_full_book();
         self.hearts_book.reset_full_book();
 
-        self.deal_cards().await;
+        self.deal_cards();
     }
 
 
-    pub async fn deal_cards(&mut self) {
-        let mut removed_players = Vec::new();
-        for (player_name, sender) in self.player_ws_map_hotpath.lock().await.iter_mut() {
-
-            let inventory = self.player_inventories.get(player_name);
-            if let Some(inventory) = inventory {
-                let full_update = json!({
-                    "kind": "dealing_cards",
-                    "data": inventory,
-                });
-    
-                if let Err(_) = sender.send(Message::Text(full_update.to_string())).await {
-                    println!("{}[!] Error sending message to player | Deleting from the map. Player must resubscribe{}", CL::Red.get(), CL::End.get());
-                    removed_players.push(player_name.clone());
-                }
-            }
-        }
-
-        for player_name in removed_players {
-            self.player_ws_map_hotpath.lock().await.remove(&player_name);
-        }
+    pub fn deal_cards(&mut self) {
+        self.publish(Event::DealCards(self.player_inventories.clone()));
 
         println!("{}[+] Cards dealt. Let's begin!{}", CL::DullTeal.get(), CL::End.get());
 
         // send out empty books to everyone and get this going
         let book_event = Update {
-            spades: self.spades_book.clone(),
-            clubs: self.clubs_book.clone(),
-            diamonds: self.diamonds_book.clone(),
-            hearts: self.hearts_book.clone(),
+            spades: self.spades_book.card_book(),
+            clubs: self.clubs_book.card_book(),
+            diamonds: self.diamonds_book.card_book(),
+            hearts: self.hearts_book.card_book(),
             trade: None,
         };
 
-        let message = json!({
-            "kind": "update",
-            "data": book_event,
-        });
-
-        self.send_message(message).await;
+        self.publish(Event::Update(book_event));
 
     }
 
 
-    pub async fn end_round(&mut self) {
+    pub fn end_round(&mut self) {
         // =-= End the Round =-= //
+        self.phase = GamePhase::RoundOver;
 
         println!("");
         println!("{}=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-={}", CL::Pink.get(), CL::End.get());
@@ -245,7 +355,7 @@ impl MatchingEngine {
         println!("");
         
         println!("=---= Game Details =---=");
-        println!("{} - Players: {}x{}", CL::Dull.get(), self.player_names.len(), CL::End.get());
+        println!("{} - Players: {}x{}", CL::Dull.get(), self.players.len(), CL::End.get());
         println!("{} - Ante: {}{}", CL::Dull.get(), self.ante, CL::End.get());
         println!("{} - Pot: {}{}", CL::Dull.get(), self.pot, CL::End.get());
         println!("");
@@ -259,13 +369,13 @@ impl MatchingEngine {
 
         // calculate the scores, each player is awared goal_suit * 10
 
-        let mut winner: (String, usize) = ("".to_string(), 0); // player_id, goal_cards
-        let mut tied_winnders: Vec<String> = Vec::new(); // player_ids
+        let mut winner: (Option<PlayerId>, usize) = (None, 0); // player, goal_cards
+        let mut tied_winnders: Vec<PlayerId> = Vec::new();
 
         println!("=---------------------------- Inventory ----------------------------=");
-        for player_name in &self.player_names {
-            let inventory = self.player_inventories.get(player_name).unwrap();
-            let player_points = self.player_points.get_mut(player_name).unwrap();
+        for player in &self.players {
+            let inventory = self.player_inventories.get(player).unwrap();
+            let player_points = self.player_points.get_mut(player).unwrap();
             let goal_cards = match self.goal_suit {
                 Card::Spade => inventory.spades,
                 Card::Club => inventory.clubs,
@@ -280,13 +390,13 @@ impl MatchingEngine {
                 Card::Heart => (CL::Dull.get(), CL::Dull.get(), CL::Dull.get(), CL::LimeGreen.get()),
             };
 
-            println!("{}{}{}{} |:| Spades: {}{}x{} | Clubs: {}{}x{} | Diamonds: {}{}x{} | Hearts: {}{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player_name, CL::Dull.get(), spade_color, inventory.spades, CL::Dull.get(), club_color, inventory.clubs, CL::Dull.get(), diamond_color, inventory.diamonds, CL::Dull.get(), heart_color, inventory.hearts, CL::End.get(), CL::End.get());
+            println!("{}{}{}{} |:| Spades: {}{}x{} | Clubs: {}{}x{} | Diamonds: {}{}x{} | Hearts: {}{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player, CL::Dull.get(), spade_color, inventory.spades, CL::Dull.get(), club_color, inventory.clubs, CL::Dull.get(), diamond_color, inventory.diamonds, CL::Dull.get(), heart_color, inventory.hearts, CL::End.get(), CL::End.get());
 
             if goal_cards >= winner.1 {
                 if goal_cards == winner.1 {
-                    tied_winnders.push(player_name.clone());
+                    tied_winnders.push(*player);
                 } else {
-                    winner = (player_name.clone(), goal_cards);
+                    winner = (Some(*player), goal_cards);
                     tied_winnders.clear();
                 }
             }
@@ -300,19 +410,19 @@ impl MatchingEngine {
         // if there's a tie, split the pot evenly between the winners
 
         println!("=----------------------------- Results -----------------------------=");
-        if winner.0 != "".to_string() {
+        if let Some(winning_player) = winner.0 {
             if tied_winnders.is_empty() {
-                println!("{}[+] Player '{}' wins the whole pot of {} points{}", CL::Green.get(), winner.0, self.pot, CL::End.get());
-                let winner_points = self.player_points.get_mut(&winner.0).unwrap();
+                println!("{}[+] Player '{}' wins the whole pot of {} points{}", CL::Green.get(), winning_player, self.pot, CL::End.get());
+                let winner_points = self.player_points.get_mut(&winning_player).unwrap();
                 *winner_points += self.pot as i32;
             } else {
                 let split = self.pot / (tied_winnders.len() + 1);
                 println!("{}[+] Players tie for the pot of {} points{}\n", CL::Teal.get(), self.pot, CL::End.get());
                 println!("{}------ Tied Players ------{}", CL::Dull.get(), CL::End.get());
-                println!("{}{}{}{} | Goal Cards: {}x | Points: {}+{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), winner.0, CL::Dull.get(), winner.1, CL::LimeGreen.get(), split, CL::End.get(), CL::End.get());
-                for player_name in tied_winnders {
-                    println!("{}{}{}{} | Goal Cards: {}x | Points: {}+{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player_name, CL::Dull.get(), winner.1, CL::LimeGreen.get(), split, CL::End.get(), CL::End.get());
-                    let player_points = self.player_points.get_mut(&player_name).unwrap();
+                println!("{}{}{}{} | Goal Cards: {}x | Points: {}+{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), winning_player, CL::Dull.get(), winner.1, CL::LimeGreen.get(), split, CL::End.get(), CL::End.get());
+                for player in tied_winnders {
+                    println!("{}{}{}{} | Goal Cards: {}x | Points: {}+{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player, CL::Dull.get(), winner.1, CL::LimeGreen.get(), split, CL::End.get(), CL::End.get());
+                    let player_points = self.player_points.get_mut(&player).unwrap();
                     *player_points += split as i32;
                 }
             }
@@ -324,9 +434,9 @@ impl MatchingEngine {
 
         println!("=-------------------------- Updated Points -------------------------=");
         let mut inventory_string = String::from("");
-        for player_name in &self.player_names {
-            let initial_points = self.initial_points.get(player_name).unwrap();
-            let player_points = self.player_points.get(player_name).unwrap();
+        for player in &self.players {
+            let initial_points = self.initial_points.get(player).unwrap();
+            let player_points = self.player_points.get(player).unwrap();
             let point_change: i32 = *player_points as i32 - *initial_points as i32;
 
             let change_color = match point_change {
@@ -335,17 +445,17 @@ impl MatchingEngine {
                 _ => CL::Dull.get(),
             };
 
-            inventory_string += &format!("{}: {} {}({}){} | ", player_name, player_points, change_color, point_change, CL::Dull.get());
+            inventory_string += &format!("{}: {} {}({}){} | ", player, player_points, change_color, point_change, CL::Dull.get());
         }
         inventory_string.truncate(inventory_string.len() - 3);
         println!("{}{}{}", CL::Dull.get(), inventory_string, CL::End.get());
         println!("");
 
-        self.send_end_round_message().await;
+        self.send_end_round_message();
     }
 
 
-    pub async fn send_end_round_message(&mut self) {
+    pub fn send_end_round_message(&mut self) {
         // send out the round's results
         let end_round_update = EndRoundUpdate {
             card_count: self.starting_inventory.clone(),
@@ -354,40 +464,41 @@ impl MatchingEngine {
             goal_suit: self.goal_suit.clone(),
             common_suit: self.common_suit.clone(),
         };
-        let message = json!({
-            "kind": "end_round",
-            "data": end_round_update,
-        });
 
-        self.send_message(message).await;
+        self.publish(Event::EndRound(end_round_update));
 
     }
 
 
-    pub async fn end_game(&mut self) {
+    pub fn end_game(&mut self) {
+        self.phase = GamePhase::GameOver;
+
         // send out everyone's cumulative points
         let end_game_points_update = EndGamePointsUpdate {
             player_points: self.player_points.clone(),
         };
-        let message = json!({
-            "kind": "end_game",
-            "data": end_game_points_update,
-        });
 
-        self.send_message(message).await;
+        self.publish(Event::EndGame(end_game_points_update));
     }
 
 
-    pub async fn process_order(&mut self, order: Order) -> HTTPResponse {
+    pub fn process_order(&mut self, order: Order) -> HTTPResponse {
 
-        // quick check that all the HashMaps have the player_name before we start
-        if !self.player_inventories.contains_key(&order.player_name) || !self.player_points.contains_key(&order.player_name) {
+        // quick check that all the HashMaps have the player before we start
+        if !self.player_inventories.contains_key(&order.player) || !self.player_points.contains_key(&order.player) {
             return HTTPResponse {
                 status: "UNKNOWN_PLAYER".to_string(),
                 message: "Player does not exist. Please post to /register_testnet first with your chosen playerid in the headers and no body. You can choose anything on the testnet".to_string(),
             };
         }
 
+        if let GamePhase::Paused = self.phase {
+            return HTTPResponse {
+                status: "TRADING_PAUSED".to_string(),
+                message: "Trading has been paused by the admin, hang tight".to_string(),
+            };
+        }
+
         let book = match order.card {
             Card::Spade => &mut self.spades_book,
             Card::Club => &mut self.clubs_book,
@@ -398,7 +509,7 @@ impl MatchingEngine {
         let trade: Option<Trade> = match (&order.direction, order.price) {
             (Direction::Buy, Some(price)) => {
     
-                let player_points = self.player_points.get(&order.player_name).unwrap();
+                let player_points = self.player_points.get(&order.player).unwrap();
                 if *player_points < price as i32 {
                     return HTTPResponse {
                         status: "INSUFFICIENT_FUNDS".to_string(),
@@ -406,11 +517,11 @@ impl MatchingEngine {
                     };
                 }
 
-                if let Some(best_ask) = book.asks.first() {
+                if let Some(best_ask) = book.best_ask() {
                     if price >= best_ask.price {
-                        //println!("{}[-] Aggressing Player: {:?} | {:?} |:| Matched buy order!{}", CL::Green.get(), order.player_name, order.card, CL::End.get());
+                        //println!("{}[-] Aggressing Player: {:?} | {:?} |:| Matched buy order!{}", CL::Green.get(), order.player, order.card, CL::End.get());
 
-                        if order.player_name == best_ask.player_name {
+                        if order.player == best_ask.player {
                             return HTTPResponse {
                                 status: "SELF_TRADE".to_string(),
                                 message: "You can't trade with yourself!".to_string(),
@@ -420,37 +531,37 @@ impl MatchingEngine {
                         // we don't need to update either book since both will be reset after the trade
 
                         // =-= Update the Inventories =-= //
-                        let buyer_inventory = self.player_inventories.get_mut(&order.player_name).unwrap();
+                        let buyer_inventory = self.player_inventories.get_mut(&order.player).unwrap();
                         buyer_inventory.change(order.card, true);
     
-                        let seller_inventory = self.player_inventories.get_mut(&best_ask.player_name).unwrap();
+                        let seller_inventory = self.player_inventories.get_mut(&best_ask.player).unwrap();
                         seller_inventory.change(order.card, false);
     
     
                         // =-= Update the Points =-= //
-                        let buyer_points = self.player_points.get_mut(&order.player_name).unwrap();
+                        let buyer_points = self.player_points.get_mut(&order.player).unwrap();
                         *buyer_points -= best_ask.price as i32;
     
-                        let seller_points = self.player_points.get_mut(&best_ask.player_name).unwrap();
+                        let seller_points = self.player_points.get_mut(&best_ask.player).unwrap();
                         *seller_points += best_ask.price as i32;
     
     
                         // =-= Package Trade =-= //
-                        book.last_trade = Some(best_ask.price);
                         let trade = Trade {
                             card: order.card,
                             price: best_ask.price,
-                            buyer: order.player_name,
-                            seller: best_ask.player_name.clone(),
+                            buyer: order.player,
+                            seller: best_ask.player,
                         };
+                        book.last_trade = Some(trade.price);
                         Some(trade)
     
                     } else {
-                        book.update_bid(price, order.player_name);
+                        book.update_bid(price, order.player);
                         None
                     }
                 } else {
-                    book.update_bid(price, order.player_name);
+                    book.update_bid(price, order.player);
                     None
                 }
                 
@@ -458,7 +569,7 @@ impl MatchingEngine {
             (Direction::Sell, Some(price)) => {
 
                 // check if the user has the inventory to sell this Card
-                let seller_inventory = self.player_inventories.get(&order.player_name).unwrap();
+                let seller_inventory = self.player_inventories.get(&order.player).unwrap();
                 if seller_inventory.get(&order.card) == 0 {
                     return HTTPResponse {
                         status: "NO_INVENTORY".to_string(),
@@ -466,11 +577,11 @@ impl MatchingEngine {
                     };
                 }
 
-                if let Some(best_bid) = book.bids.first() {
+                if let Some(best_bid) = book.best_bid() {
                     if price <= best_bid.price {
-                        //println!("{}[-] Aggressing Player: {:?} | {:?} |:| Matched sell order!{}", CL::Red.get(), order.player_name, order.card, CL::End.get());
+                        //println!("{}[-] Aggressing Player: {:?} | {:?} |:| Matched sell order!{}", CL::Red.get(), order.player, order.card, CL::End.get());
     
-                        if order.player_name == best_bid.player_name {
+                        if order.player == best_bid.player {
                             return HTTPResponse {
                                 status: "SELF_TRADE".to_string(),
                                 message: "You can't trade with yourself!".to_string(),
@@ -480,51 +591,54 @@ impl MatchingEngine {
                         // we don't need to update either book since both will be reset after the trade
 
                         // =-= Update the Inventories =-= //
-                        let buyer_inventory = self.player_inventories.get_mut(&best_bid.player_name).unwrap();
+                        let buyer_inventory = self.player_inventories.get_mut(&best_bid.player).unwrap();
                         buyer_inventory.change(order.card, true);
     
-                        let seller_inventory = self.player_inventories.get_mut(&order.player_name).unwrap();
+                        let seller_inventory = self.player_inventories.get_mut(&order.player).unwrap();
                         seller_inventory.change(order.card, false);
     
     
                         // =-= Update the Points =-= //
-                        let buyer_points = self.player_points.get_mut(&best_bid.player_name).unwrap();
+                        let buyer_points = self.player_points.get_mut(&best_bid.player).unwrap();
                         *buyer_points -= best_bid.price as i32;
     
-                        let seller_points = self.player_points.get_mut(&order.player_name).unwrap();
+                        let seller_points = self.player_points.get_mut(&order.player).unwrap();
                         *seller_points += best_bid.price as i32;
     
     
                         // =-= Package Trade =-= //
-                        book.last_trade = Some(best_bid.price);
                         let trade = Trade {
                             card: order.card,
                             price: best_bid.price,
-                            buyer: best_bid.player_name.clone(),
-                            seller: order.player_name,
+                            buyer: best_bid.player,
+                            seller: order.player,
                         };
+                        book.last_trade = Some(trade.price);
                         Some(trade)
     
                     } else {
-                        book.update_ask(price, order.player_name);
+                        book.update_ask(price, order.player);
                         None
                     }
                 } else {
-                    book.update_ask(price, order.player_name);
+                    book.update_ask(price, order.player);
                     None
                 }
             },
             (Direction::Buy, None) => {
-                book.cancel_bid(order.player_name);
+                book.cancel_bid(order.player);
                 None
             },
             (Direction::Sell, None) => {
-                book.cancel_ask(order.player_name);
+                book.cancel_ask(order.player);
                 None
             },
         };
 
-        if let Some(_) = trade.clone() {
+        if let Some(trade) = &trade {
+            self.round_trades += 1;
+            self.round_volume += trade.price;
+
             // =-= Reset all the Books =-= //
             // - Like the website, we'll reset all the books after a match occurs
             self.spades_book.reset_quotes();
@@ -535,14 +649,14 @@ impl MatchingEngine {
 
         // =-= Print the Game =-= //
         println!("\n=---------------------------------------------------------------------------------=");
-        for (player_name, inventory) in &self.player_inventories {
+        for (player, inventory) in &self.player_inventories {
             let (spade_color, club_color, diamond_color, heart_color) = match self.goal_suit {
                 Card::Spade => (CL::LimeGreen.get(), CL::Dull.get(), CL::Dull.get(), CL::Dull.get()),
                 Card::Club => (CL::Dull.get(), CL::LimeGreen.get(), CL::Dull.get(), CL::Dull.get()),
                 Card::Diamond => (CL::Dull.get(), CL::Dull.get(), CL::LimeGreen.get(), CL::Dull.get()),
                 Card::Heart => (CL::Dull.get(), CL::Dull.get(), CL::Dull.get(), CL::LimeGreen.get()),
             };
-            println!("{}{}{}{} |:| Spades: {}{}x{} | Clubs: {}{}x{} | Diamonds: {}{}x{} | Hearts: {}{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player_name, CL::Dull.get(), spade_color, inventory.spades, CL::Dull.get(), club_color, inventory.clubs, CL::Dull.get(), diamond_color, inventory.diamonds, CL::Dull.get(), heart_color, inventory.hearts, CL::End.get(), CL::End.get());
+            println!("{}{}{}{} |:| Spades: {}{}x{} | Clubs: {}{}x{} | Diamonds: {}{}x{} | Hearts: {}{}x{}{}", CL::Dull.get(), CL::DimLightBlue.get(), player, CL::Dull.get(), spade_color, inventory.spades, CL::Dull.get(), club_color, inventory.clubs, CL::Dull.get(), diamond_color, inventory.diamonds, CL::Dull.get(), heart_color, inventory.hearts, CL::End.get(), CL::End.get());
         }
         println!("");
         let spades_bid = self.spades_book.get_best_bid();
@@ -563,9 +677,9 @@ impl MatchingEngine {
         println!("{}Diamonds  {}|:| Bid: ({}{:?}{}, {:?}) | Ask: ({}{:?}{}, {:?}) |:|{} Last trade: {}{:?}{}", diamonds_color.get(), CL::Dull.get(), CL::Green.get(), diamonds_bid.0,  CL::Dull.get(), diamonds_bid.1,  CL::PeachRed.get(),  diamonds_ask.0,  CL::Dull.get(),  diamonds_ask.1,  CL::Dull.get(),  CL::DimLightBlue.get(),  diamonds_last_trade,  CL::End.get());
         println!("{}Hearts    {}|:| Bid: ({}{:?}{}, {:?}) | Ask: ({}{:?}{}, {:?}) |:|{} Last trade: {}{:?}{}", hearts_color.get(), CL::Dull.get(), CL::Green.get(), hearts_bid.0,    CL::Dull.get(), hearts_bid.1,    CL::PeachRed.get(),  hearts_ask.0,    CL::Dull.get(),  hearts_ask.1,    CL::Dull.get(),  CL::DimLightBlue.get(),  hearts_last_trade,    CL::End.get());
         let mut inventory_string = format!("{}Points    {}|:|{} ", CL::DullGreen.get(), CL::Dull.get(), CL::DullGreen.get());
-        for player_name in &self.player_names {
-            let player_points = self.player_points.get(player_name).unwrap();
-            inventory_string += &format!("{}: {} | ", player_name, player_points);
+        for player in &self.players {
+            let player_points = self.player_points.get(player).unwrap();
+            inventory_string += &format!("{}: {} | ", player, player_points);
         }
         inventory_string.truncate(inventory_string.len() - 3);
         println!("{}{}", inventory_string, CL::End.get());
@@ -574,20 +688,16 @@ impl MatchingEngine {
 
 
         let book_event = Update {
-            spades: self.spades_book.clone(),
-            clubs: self.clubs_book.clone(),
-            diamonds: self.diamonds_book.clone(),
-            hearts: self.hearts_book.clone(),
+            spades: self.spades_book.card_book(),
+            clubs: self.clubs_book.card_book(),
+            diamonds: self.diamonds_book.card_book(),
+            hearts: self.hearts_book.card_book(),
             trade,
         };
-        let message = json!({
-            "kind": "update",
-            "data": book_event,
-        });
 
 
         // this is an interesting race if you think about it. The update will populate before the submitor is notified of the trade
-        self.send_message(message).await;
+        self.publish(Event::Update(book_event));
 
         match order.price {
             Some(price) => {
@@ -607,35 +717,227 @@ impl MatchingEngine {
         
     }
 
-    pub async fn send_book_state(&self) {
+    pub fn send_book_state(&mut self) {
+        self.send_books();
+
+        let stats_update = StatsUpdate {
+            round_trades: self.round_trades,
+            round_volume: self.round_volume,
+            player_points: self.player_points.clone(),
+        };
+        self.publish(Event::Stats(stats_update));
+    }
+
+
+    fn send_books(&mut self) {
         let book_event = Update {
-            spades: self.spades_book.clone(),
-            clubs: self.clubs_book.clone(),
-            diamonds: self.diamonds_book.clone(),
-            hearts: self.hearts_book.clone(),
+            spades: self.spades_book.card_book(),
+            clubs: self.clubs_book.card_book(),
+            diamonds: self.diamonds_book.card_book(),
+            hearts: self.hearts_book.card_book(),
             trade: None,
         };
-        let message = json!({
-            "kind": "update",
-            "data": book_event,
-        });
-        self.send_message(message).await;
+        self.publish(Event::Update(book_event));
+    }
+
+
+    fn build_view(&self) -> EngineView {
+        EngineView {
+            seq: self.seq,
+            phase: self.phase,
+            round: self.round.clone(),
+            books: Update {
+                spades: self.spades_book.card_book(),
+                clubs: self.clubs_book.card_book(),
+                diamonds: self.diamonds_book.card_book(),
+                hearts: self.hearts_book.card_book(),
+                trade: None,
+            },
+            players: self.players.clone(),
+            player_points: self.player_points.clone(),
+            player_inventories: self.player_inventories.clone(),
+        }
+    }
+
+
+    // stamps the next sequence number on the event and keeps it around for replays, it goes out to the publisher on the next flush
+    fn publish(&mut self, event: Event) {
+        self.seq += 1;
+        let event = Arc::new(event);
+        self.pending.push((self.seq, Arc::clone(&event)));
+        self.replay_buffer.push(self.seq, event);
+    }
+
+
+    // called by the hotpath after every message it handles: a new view goes out first, then the events that led up to it.
+    // Anyone who loads the view while holding the player_ws_map knows the publisher hasn't broadcast anything past view.seq yet
+    // `changed` is for state changes that didn't publish anything (players joining, points being reset), a rejected order changes nothing
+    pub fn flush(&mut self, changed: bool) {
+        if !changed && self.pending.is_empty() {
+            return;
+        }
+        self.view.store(Arc::new(self.build_view()));
+
+        // never blocks, the queue is unbounded
+        for (seq, event) in self.pending.drain(..) {
+            if let Err(e) = self.publisher.try_send((seq, event)) {
+                println!("{}[!] Failed to hand event {} to the publisher: {:?}{}", CL::Red.get(), seq, e, CL::End.get());
+            }
+        }
+    }
+
+
+}
+
+
+// =-= Engine View =-= //
+
+// The engine's state as of `seq`, everything that only reads (/inventory, snapshots on subscribe, the admin) loads this
+// instead of going through the engine. It's swapped out whole after every change so readers never see half an update
+pub type SharedView = Arc<ArcSwap<EngineView>>;
+
+#[derive(Debug, Clone)]
+pub struct EngineView {
+    pub seq: u64,
+    pub phase: GamePhase,
+    pub round: Option<RoundInfo>,
+    pub books: Update, // no trade
+    pub players: Vec<PlayerId>,
+    pub player_points: HashMap<PlayerId, i32>,
+    pub player_inventories: HashMap<PlayerId, Inventory>,
+}
+
+impl Default for EngineView {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
+impl EngineView {
+    // before the engine has done anything (or, on a gateway, before it's heard from the engine)
+    pub fn new() -> Self {
+        Self {
+            seq: 0,
+            phase: GamePhase::Waiting,
+            round: None,
+            books: Update { spades: CardBook::new(), clubs: CardBook::new(), diamonds: CardBook::new(), hearts: CardBook::new(), trade: None },
+            players: Vec::new(),
+            player_points: HashMap::new(),
+            player_inventories: HashMap::new(),
+        }
+    }
 
-    async fn send_message(&self, message: serde_json::Value) {
-        let mut removed_players = Vec::new();
-        for (player_name, sender) in self.player_ws_map_hotpath.lock().await.iter_mut() {
-            if let Err(_) = sender.send(Message::Text(message.to_string())).await {
-                println!("{}[!] Error sending message to player | Deleting from the map. Player must resubscribe{}", CL::Red.get(), CL::End.get());
-                removed_players.push(player_name.clone());
+    pub fn snapshot(&self, player_name: &str) -> Snapshot {
+        let player = PlayerId::lookup(player_name);
+        let mut orders = Vec::new();
+        for (card, book) in [(Card::Spade, &self.books.spades), (Card::Club, &self.books.clubs), (Card::Diamond, &self.books.diamonds), (Card::Heart, &self.books.hearts)] {
+            for (direction, entries) in [(Direction::Buy, &book.bids), (Direction::Sell, &book.asks)] {
+                for entry in entries.iter().filter(|entry| Some(entry.player) == player) {
+                    orders.push(OpenOrder { card, direction: direction.to_string(), price: entry.price });
+                }
             }
         }
 
-        for player_name in removed_players {
-            self.player_ws_map_hotpath.lock().await.remove(&player_name);
+        Snapshot {
+            seq: self.seq,
+            phase: self.phase,
+            round: self.round.clone(),
+            books: self.books.clone(),
+            inventory: player.and_then(|player| self.player_inventories.get(&player)).copied(),
+            orders,
+            player_points: self.player_points.clone(),
         }
     }
+}
+
 
+// =-= Replay Buffer =-= //
 
-}
\ No newline at end of file
+pub type ReplayMessages = Vec<(u64, &'static str, String)>; // seq, message type, the rendered message
+pub type BufferedEvents = Vec<(u64, Arc<Event>)>;
+
+// The latest REPLAY_BUFFER_SIZE events. The engine keeps one to catch gateways up (see link.rs), the publisher keeps the one
+// players replay from (on a gateway that's the link's copy of the engine's events), so a replay never waits on the engine
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    events: VecDeque<(u64, Arc<Event>)>,
+    pub last_seq: u64, // the newest seq that was pushed, 0 before anything was
+}
+
+impl Default for ReplayBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayBuffer {
+    pub fn new() -> Self {
+        Self { events: VecDeque::with_capacity(REPLAY_BUFFER_SIZE), last_seq: 0 }
+    }
+
+    pub fn push(&mut self, seq: u64, event: Arc<Event>) {
+        if self.events.len() == REPLAY_BUFFER_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back((seq, event));
+        self.last_seq = seq;
+    }
+
+    // forgets everything, the next event pushed is expected to be `seq + 1`
+    pub fn reset(&mut self, seq: u64) {
+        self.events.clear();
+        self.last_seq = seq;
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn oldest_seq(&self) -> Option<u64> {
+        self.events.front().map(|(seq, _)| *seq)
+    }
+
+    // everything newer than `seq`, as is
+    pub fn after(&self, seq: u64) -> BufferedEvents {
+        self.events.iter().filter(|(event_seq, _)| *event_seq > seq).cloned().collect()
+    }
+
+    // everything from `from_seq` (inclusive) + the seq it goes up to, Err(oldest available seq) when the buffer has already moved
+    // past `from_seq`
+    pub fn from_seq(&self, from_seq: u64) -> Result<(BufferedEvents, u64), u64> {
+        let oldest_seq = self.oldest_seq().unwrap_or(self.last_seq + 1);
+        if from_seq < oldest_seq && oldest_seq > 1 {
+            return Err(oldest_seq);
+        }
+        Ok((self.after(from_seq.saturating_sub(1)), self.last_seq))
+    }
+}
+
+
+// the publisher's buffer, shared w/ the websocket + SSE handlers. Locked just long enough to copy the events out
+#[derive(Clone, Default)]
+pub struct SharedReplayBuffer(Arc<Mutex<ReplayBuffer>>);
+
+impl SharedReplayBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, ReplayBuffer> {
+        self.0.lock().unwrap()
+    }
+
+    // renders everything from `from_seq` (inclusive) for one connection as (seq, kind, message) + the seq it goes up to, on the
+    // caller's thread. Err(oldest available seq) when it's too far back, the client should resubscribe for a fresh snapshot
+    pub fn replay(&self, from_seq: u64, player_name: &str, version: SchemaVersion, subscription: &Subscription) -> Result<(ReplayMessages, u64), u64> {
+        let (events, last_seq) = self.lock().from_seq(from_seq)?;
+        let messages = events.iter()
+            .filter_map(|(seq, event)| event.render(*seq, player_name, version, subscription).map(|message| (*seq, event.kind(), message)))
+            .collect();
+        Ok((messages, last_seq))
+    }
+}
@@ -6,7 +6,30 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SubscribeMessage {
     pub action: String,
-    pub playerid: String,
+    #[serde(default)]
+    pub playerid: String, // not needed when the subscribe is signed
+    #[serde(default)]
+    pub version: Option<u8>, // 1 (default) or 2
+    #[serde(default)]
+    pub channels: Option<Vec<String>>, // "book", "book.spades", "trades", "private", "game_state", "stats" | everything but stats when left out
+    #[serde(default)]
+    pub depth: Option<String>, // "bbo", "top_<n>" or "full" (default)
+    #[serde(default)]
+    pub from_seq: Option<u64>, // only for "replay"
+    #[serde(default)]
+    pub card: Option<String>, // "order" / "cancel" take the same card, price + direction as the RestAPI
+    #[serde(default)]
+    pub price: Option<usize>,
+    #[serde(default)]
+    pub direction: Option<String>,
+    #[serde(default)]
+    pub api_key: Option<String>, // api_key, timestamp (unix ms), nonce + signature sign the subscribe, see auth.rs
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -30,8 +53,60 @@ pub struct RawOrderData {
     pub direction: String, // "buy" or "sell"
 }
 
+// =-= UDS Gateway =-= //
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GatewayRequest {
+    pub action: String, // "subscribe", "unsubscribe", "order" or "cancel"
+    #[serde(default)]
+    pub playerid: String, // not needed when the request is signed
+    #[serde(default)]
+    pub card: Option<String>, // "spade", "club", "diamond", "heart"
+    #[serde(default)]
+    pub price: Option<usize>, // only for "order"
+    #[serde(default)]
+    pub direction: Option<String>, // "buy" or "sell"
+    #[serde(default)]
+    pub version: Option<u8>,
+    #[serde(default)]
+    pub channels: Option<Vec<String>>,
+    #[serde(default)]
+    pub depth: Option<String>,
+    #[serde(default)]
+    pub api_key: Option<String>, // same signing fields as the websocket subscribe, see uds_gateway.rs for what gets signed
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GatewayResponse {
+    pub status: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine_latency_ns: Option<u64>, // from handing the order to the engine's channel to getting its response back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_ns: Option<u64>, // when the gateway first saw the order (unix ns), see sequencer.rs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>, // the order's place in line at the engine
+}
+
+impl From<HTTPResponse> for GatewayResponse {
+    fn from(response: HTTPResponse) -> Self {
+        Self { status: response.status, message: response.message, engine_latency_ns: None, received_ns: None, sequence: None }
+    }
+}
+
+// =-= Admin =-= //
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct AdminRequest {
     pub action: String,
-    pub players: String,
+    #[serde(default)]
+    pub players: String, // comma separated player names, for "kick", "ban", "unban", "seat" and "issue_keys"
+    #[serde(default)]
+    pub message: Option<String>, // only for "announce"
 }
\ No newline at end of file
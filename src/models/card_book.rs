@@ -1,5 +1,6 @@
 use serde::ser::{SerializeStruct, Serializer, SerializeSeq};
 use serde::Serialize;
+use super::PlayerId;
 
 
 // =-= Serialziation =-= //
@@ -29,10 +30,9 @@ impl Serialize for CardBook {
         //}
 
         // serialize it into a Vec<BookEntrySerialized> = Vec<(integer, String)>
-        // but only serialize the first element (unless people want the full book published)
-        //let bbo_bid = self.bids.first().map(|x| (x.price, x.player_name.clone())).into_iter().collect::<Vec<_>>();
+        // the BBO / top-N variants are handled by `with_depth` before serializing (see subscription depth tiers)
         
-        let bids = self.bids.iter().map(|x| (x.price, x.player_name.clone())).into_iter().collect::<Vec<_>>();
+        let bids = self.bids.iter().map(|x| (x.price, x.player)).into_iter().collect::<Vec<_>>();
         state.serialize_field("bids", &bids)?;
 
 
@@ -45,9 +45,8 @@ impl Serialize for CardBook {
         //        price: price.to_string(),
         //    };
         //}
-        //let bbo_ask = self.asks.first().map(|x| (x.price, x.player_name.clone())).into_iter().collect::<Vec<_>>();
 
-        let asks: Vec<(usize, String)> = self.asks.iter().map(|x| (x.price, x.player_name.clone())).into_iter().collect::<Vec<_>>();
+        let asks: Vec<(usize, PlayerId)> = self.asks.iter().map(|x| (x.price, x.player)).into_iter().collect::<Vec<_>>();
         state.serialize_field("asks", &asks)?;
         
         
@@ -65,7 +64,7 @@ impl Serialize for BookEntry {
         S: Serializer,
     {
         let mut seq = serializer.serialize_seq(Some(2))?;
-        seq.serialize_element(&self.player_name)?;
+        seq.serialize_element(&self.player)?;
         seq.serialize_element(&self.price)?;
         seq.end()
     }
@@ -75,12 +74,13 @@ impl Serialize for BookEntry {
 
 // =-= Base Models =-= //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct BookEntry {
     pub price: usize,
-    pub player_name: String,
+    pub player: PlayerId,
 }
 
+// what gets published for one card, the engine quotes in a PriceLevelBook and hands these out (bids high -> low, asks low -> high)
 #[derive(Debug, Clone)]
 pub struct CardBook {
     pub bids: Vec<BookEntry>,
@@ -98,75 +98,13 @@ impl CardBook {
         }
     }
 
-    pub fn cancel_bid(&mut self, player_name: String) {
-        self.bids.retain(|bid| bid.player_name != player_name);
-    }
-
-    pub fn cancel_ask(&mut self, player_name: String) {
-        self.asks.retain(|ask| ask.player_name != player_name);
-    }
-
-    pub fn update_bid(&mut self, price: usize, player_name: String) {
-
-        let mut found = false;
-        for bid in self.bids.iter_mut() {
-            if bid.player_name == player_name {
-                bid.price = price;
-                found = true;
-                break;
-            }
-        }
-        
-        if !found { // if the player_name is not found, add a new bid
-            self.bids.push(BookEntry { price, player_name });
-        }
-        
-        self.bids.sort_by(|a, b| b.price.cmp(&a.price)); // sort the bids in descending order
-    }
-
-    pub fn update_ask(&mut self, price: usize, player_name: String) {
-        
-        let mut found = false;
-        for ask in self.asks.iter_mut() {
-            if ask.player_name == player_name {
-                ask.price = price;
-                found = true;
-                break;
-            }
+    // copy of the book with only the best `levels` on each side, `usize::MAX` keeps the full book
+    pub fn with_depth(&self, levels: usize) -> CardBook {
+        CardBook {
+            bids: self.bids.iter().take(levels).cloned().collect(),
+            asks: self.asks.iter().take(levels).cloned().collect(),
+            last_trade: self.last_trade,
         }
-        
-        if !found { // if the player_name is not found, add a new ask
-            self.asks.push(BookEntry { price, player_name });
-        }
-
-        self.asks.sort_by(|a, b| a.price.cmp(&b.price)); // sort the asks in ascending order
-    }
-
-    pub fn get_best_bid(&self) -> (Option<usize>, Option<String>) {
-        if let Some(bid) = self.bids.first() {
-            (Some(bid.price), Some(bid.player_name.clone()))
-        } else {
-            (None, None)
-        }
-    }
-
-    pub fn get_best_ask(&self) -> (Option<usize>, Option<String>) {
-        if let Some(ask) = self.asks.first() {
-            (Some(ask.price), Some(ask.player_name.clone()))
-        } else {
-            (None, None)
-        }
-    }
-
-    pub fn reset_quotes(&mut self) {
-        self.bids = Vec::new();
-        self.asks = Vec::new();
-    }
-
-    pub fn reset_full_book(&mut self) {
-        self.bids = Vec::new();
-        self.asks = Vec::new();
-        self.last_trade = None;
     }
 
 }
\ No newline at end of file
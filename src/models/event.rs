@@ -1,6 +1,6 @@
-use super::{Card, CardBook, Inventory};
+use super::{Card, CardBook, Inventory, Channel, PlayerId, Subscription, SchemaVersion, build_message};
 use serde::ser::{SerializeStruct, Serializer, SerializeMap};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 
@@ -8,17 +8,65 @@ use std::collections::HashMap;
 #[derive(Debug, Clone)]
 pub enum Event {
     Update(Update),
-    DealCards(HashMap<String, Inventory>),
-    EndRound,
+    DealCards(HashMap<PlayerId, Inventory>),
+    EndRound(EndRoundUpdate),
+    EndGame(EndGamePointsUpdate),
+    Stats(StatsUpdate),
+    Announcement(Announcement),
 }
 
+impl Event {
+    // private events render differently for every player, the rest are the same for everyone on the same version + subscription
+    pub fn is_private(&self) -> bool {
+        matches!(self, Event::DealCards(_))
+    }
 
-#[derive(Debug, Clone, Serialize)]
+    // the `kind` field of the message (and the SSE event name)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Update(_) => "update",
+            Event::DealCards(_) => "dealing_cards",
+            Event::EndRound(_) => "end_round",
+            Event::EndGame(_) => "end_game",
+            Event::Stats(_) => "stats",
+            Event::Announcement(_) => "announcement",
+        }
+    }
+
+    // whether a newer message of the same kind can stand in for this one in a slow session's queue (conflate policy). The books +
+    // points are always sent in full, a trade isn't, so an update w/ a trade is only replaceable for sessions that don't get trades
+    pub fn is_conflatable(&self, subscription: &Subscription) -> bool {
+        match self {
+            Event::Update(update) => update.trade.is_none() || !subscription.contains(Channel::Trades),
+            Event::Stats(_) => true,
+            _ => false,
+        }
+    }
+
+    // None when the player shouldn't get this event
+    pub fn render(&self, seq: u64, player_name: &str, version: SchemaVersion, subscription: &Subscription) -> Option<String> {
+        let kind = self.kind();
+        match self {
+            Event::Update(update) => subscription.filter_update(update).map(|update| build_message(kind, &update, version, seq)),
+            Event::DealCards(player_inventories) => match subscription.contains(Channel::Private) {
+                true => PlayerId::lookup(player_name).and_then(|player| player_inventories.get(&player)).map(|inventory| build_message(kind, inventory, version, seq)),
+                false => None,
+            },
+            Event::EndRound(end_round_update) => subscription.contains(Channel::GameState).then(|| build_message(kind, end_round_update, version, seq)),
+            Event::EndGame(end_game_points_update) => subscription.contains(Channel::GameState).then(|| build_message(kind, end_game_points_update, version, seq)),
+            Event::Stats(stats_update) => subscription.contains(Channel::Stats).then(|| build_message(kind, stats_update, version, seq)),
+            Event::Announcement(announcement) => Some(build_message(kind, announcement, version, seq)), // everyone gets these, whatever they're subscribed to
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub card: Card,
     pub price: usize,
-    pub buyer: String,
-    pub seller: String,
+    pub buyer: PlayerId,
+    pub seller: PlayerId,
 }
 
 
@@ -58,7 +106,7 @@ impl Serialize for Update {
 #[derive(Debug, Clone, Serialize)]
 pub struct EndGamePointsUpdate {
     #[serde(serialize_with = "serialize_player_points")]
-    pub player_points: HashMap<String, i32>,
+    pub player_points: HashMap<PlayerId, i32>,
 }
 
 
@@ -66,14 +114,24 @@ pub struct EndGamePointsUpdate {
 
 
 
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsUpdate {
+    pub round_trades: usize,
+    pub round_volume: usize, // sum of the trade prices this round
+    #[serde(serialize_with = "serialize_player_points")]
+    pub player_points: HashMap<PlayerId, i32>,
+}
+
+
+
 #[derive(Debug, Clone, Serialize)]
 pub struct EndRoundUpdate {
     #[serde(serialize_with = "serialize_card_count")]
     pub card_count: HashMap<Card, usize>,
     #[serde(serialize_with = "serialize_player_inventories")]
-    pub player_inventories: HashMap<String, Inventory>,
+    pub player_inventories: HashMap<PlayerId, Inventory>,
     #[serde(serialize_with = "serialize_player_points")]
-    pub player_points: HashMap<String, i32>,
+    pub player_points: HashMap<PlayerId, i32>,
     #[serde(serialize_with = "serialize_suite")]
     pub goal_suit: Card,
     #[serde(serialize_with = "serialize_suite")]
@@ -99,24 +157,74 @@ where
 }
 
 
+// system messages from the admin (pauses, aborts, anything they'd like to tell the players)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub message: String,
+    pub sent_at: u128, // unix ms
+}
+
+
+// =-= Snapshot =-= //
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    Waiting,   // no game running, players can register
+    Trading,   // a round is live
+    Paused,    // a round is live but the admin has stopped trading (and the round's clock)
+    RoundOver, // between rounds
+    GameOver,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundInfo {
+    pub round_number: usize,
+    pub started_at: u128, // unix ms
+    pub ends_at: u128,    // unix ms
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenOrder {
+    #[serde(serialize_with = "serialize_suite")]
+    pub card: Card,
+    pub direction: String, // "buy" or "sell"
+    pub price: usize,
+}
+
+// everything a player needs to (re)build their view of the game, sent right after every successful subscribe
+// `seq` is the last sequence number included in this state, anything after it can be requested with a replay
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub seq: u64,
+    pub phase: GamePhase,
+    pub round: Option<RoundInfo>,
+    pub books: Update,
+    pub inventory: Option<Inventory>,
+    pub orders: Vec<OpenOrder>,
+    #[serde(serialize_with = "serialize_player_points")]
+    pub player_points: HashMap<PlayerId, i32>,
+}
+
+
 // =-= Player Inventories =-= //
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PlayerInventory {
-    pub player_name: String,
+    pub player_name: PlayerId,
     pub spades: usize,
     pub clubs: usize,
     pub diamonds: usize,
     pub hearts: usize,
 }
 
-fn serialize_player_inventories<S>(player_inventories: &HashMap<String, Inventory>, serializer: S) -> Result<S::Ok, S::Error>
+fn serialize_player_inventories<S>(player_inventories: &HashMap<PlayerId, Inventory>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
 
     let player_inventories_vec: Vec<PlayerInventory> = player_inventories.iter().map(|(k, v)| PlayerInventory {
-        player_name: k.clone(),
+        player_name: *k,
         spades: v.spades,
         clubs: v.clubs,
         diamonds: v.diamonds,
@@ -131,17 +239,17 @@ where
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PlayerPoints {
-    pub player_name: String,
+    pub player_name: PlayerId,
     pub points: i32,
 }
 
-fn serialize_player_points<S>(player_points: &HashMap<String, i32>, serializer: S) -> Result<S::Ok, S::Error>
+fn serialize_player_points<S>(player_points: &HashMap<PlayerId, i32>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
     
     let player_points_vec: Vec<PlayerPoints> = player_points.iter().map(|(k, v)| PlayerPoints {
-        player_name: k.clone(),
+        player_name: *k,
         points: *v,
     }).collect();
     
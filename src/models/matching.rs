@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
-use super::CL;
+use super::{CL, PlayerId};
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Direction {
     Buy,
     Sell,
@@ -19,9 +19,9 @@ impl ToString for Direction {
 
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
-    pub player_name: String,
+    pub player: PlayerId,
     pub card: Card,
     pub direction: Direction,
     pub price: Option<usize>,
@@ -6,5 +6,13 @@ pub mod event;
 pub use event::*;
 pub mod card_book;
 pub use card_book::*;
+pub mod price_level_book;
+pub use price_level_book::*;
 pub mod matching;
 pub use matching::*;
+pub mod player;
+pub use player::*;
+pub mod schema_v2;
+pub use schema_v2::*;
+pub mod subscription;
+pub use subscription::*;
@@ -0,0 +1,92 @@
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, LazyLock, RwLock};
+
+
+// =-= Player Ids =-= //
+
+// Every player is interned to a small number when they register and that's all the engine, the books, orders, trades and events
+// carry around, so quoting + trading never clones, hashes or compares a name. The name is only looked up again where it's written
+// out: serializing a message, a log line. (Not to be confused with the `playerid` header, that's the player's secret)
+//
+// The directory is append-only and shared by the whole process since the serde impls that write names out don't get any context
+// passed to them. An id never changes hands, a name that registers again (e.g. after a kick) gets its old id back
+
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct PlayerId(u32);
+
+struct Directory {
+    names: ArcSwap<Vec<Arc<str>>>, // indexed by id, loaded without a lock on every serialization
+    ids: RwLock<HashMap<Arc<str>, PlayerId>>,
+}
+
+static DIRECTORY: LazyLock<Directory> = LazyLock::new(|| Directory {
+    names: ArcSwap::from_pointee(Vec::new()),
+    ids: RwLock::new(HashMap::new()),
+});
+
+impl PlayerId {
+    // the name's id, a new one if it hasn't been seen before
+    pub fn intern(player_name: &str) -> PlayerId {
+        if let Some(player) = PlayerId::lookup(player_name) {
+            return player;
+        }
+
+        let mut ids = DIRECTORY.ids.write().unwrap();
+        if let Some(player) = ids.get(player_name) { // interned by someone else while we were waiting on the lock
+            return *player;
+        }
+        let player_name: Arc<str> = Arc::from(player_name);
+        let mut names = Vec::clone(&DIRECTORY.names.load()); // copy on write, registrations are rare
+        let player = PlayerId(names.len() as u32);
+        names.push(Arc::clone(&player_name));
+        DIRECTORY.names.store(Arc::new(names));
+        ids.insert(player_name, player);
+        player
+    }
+
+    // None if no one's registered under that name yet
+    pub fn lookup(player_name: &str) -> Option<PlayerId> {
+        DIRECTORY.ids.read().unwrap().get(player_name).copied()
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        Arc::clone(&DIRECTORY.names.load()[self.0 as usize])
+    }
+}
+
+// logs print the name the same way a String would
+impl fmt::Debug for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &*self.name())
+    }
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+// always written out as the player's name, the clients never see the id
+impl Serialize for PlayerId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+// only the engine link reads names back in (see link.rs), a name it hasn't seen yet is interned on the spot
+impl<'de> Deserialize<'de> for PlayerId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let player_name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(PlayerId::intern(&player_name))
+    }
+}
@@ -0,0 +1,311 @@
+use super::{BookEntry, CardBook, PlayerId};
+use std::collections::{HashMap, VecDeque};
+
+
+// =-= Price Level Book =-= //
+
+// The matching engine's book for one card. Prices only go from 1 to 99 (the gateways reject anything else), so every price gets
+// its own slot in an array with a FIFO queue of the players quoting it (first in gets filled first), a map of where each player's
+// quote sits and the best price on each side. Quoting, moving a quote, cancelling and reading the top never sort or scan a whole side
+// Everything published (updates, snapshots, the view) is a CardBook built from it with `card_book()`
+
+pub const MAX_PRICE: usize = 99;
+
+#[derive(Debug, Clone)]
+pub struct PriceLevelBook {
+    bids: PriceLevels,
+    asks: PriceLevels,
+    pub last_trade: Option<usize>,
+}
+
+impl Default for PriceLevelBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriceLevelBook {
+    pub fn new() -> Self {
+        Self {
+            bids: PriceLevels::new(Side::Bid),
+            asks: PriceLevels::new(Side::Ask),
+            last_trade: None,
+        }
+    }
+
+    pub fn update_bid(&mut self, price: usize, player: PlayerId) {
+        self.bids.quote(price, player);
+    }
+
+    pub fn update_ask(&mut self, price: usize, player: PlayerId) {
+        self.asks.quote(price, player);
+    }
+
+    pub fn cancel_bid(&mut self, player: PlayerId) {
+        self.bids.remove(player);
+    }
+
+    pub fn cancel_ask(&mut self, player: PlayerId) {
+        self.asks.remove(player);
+    }
+
+    pub fn best_bid(&self) -> Option<&BookEntry> {
+        self.bids.best()
+    }
+
+    pub fn best_ask(&self) -> Option<&BookEntry> {
+        self.asks.best()
+    }
+
+    pub fn get_best_bid(&self) -> (Option<usize>, Option<PlayerId>) {
+        match self.best_bid() {
+            Some(bid) => (Some(bid.price), Some(bid.player)),
+            None => (None, None),
+        }
+    }
+
+    pub fn get_best_ask(&self) -> (Option<usize>, Option<PlayerId>) {
+        match self.best_ask() {
+            Some(ask) => (Some(ask.price), Some(ask.player)),
+            None => (None, None),
+        }
+    }
+
+    pub fn has_quotes(&self, player: PlayerId) -> bool {
+        self.bids.quotes.contains_key(&player) || self.asks.quotes.contains_key(&player)
+    }
+
+    // bids high -> low and asks low -> high, in queue order within a price
+    pub fn card_book(&self) -> CardBook {
+        CardBook {
+            bids: self.bids.entries(),
+            asks: self.asks.entries(),
+            last_trade: self.last_trade,
+        }
+    }
+
+    pub fn reset_quotes(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    pub fn reset_full_book(&mut self) {
+        self.reset_quotes();
+        self.last_trade = None;
+    }
+}
+
+
+// =-= Price Levels =-= //
+
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Bid,
+    Ask,
+}
+
+#[derive(Debug, Clone)]
+struct PriceLevels {
+    side: Side,
+    levels: Vec<VecDeque<BookEntry>>, // indexed by price, 0 is never used
+    quotes: HashMap<PlayerId, usize>, // player -> the price they're quoting, one quote per player per side
+    best: Option<usize>,
+}
+
+impl PriceLevels {
+    fn new(side: Side) -> Self {
+        Self {
+            side,
+            levels: vec![VecDeque::new(); MAX_PRICE + 1],
+            quotes: HashMap::new(),
+            best: None,
+        }
+    }
+
+    fn is_better(&self, price: usize, than: usize) -> bool {
+        match self.side {
+            Side::Bid => price > than,
+            Side::Ask => price < than,
+        }
+    }
+
+    // a new price sends the player to the back of that price's queue, quoting the same price again keeps their place
+    fn quote(&mut self, price: usize, player: PlayerId) {
+        if price == 0 || price > MAX_PRICE {
+            return;
+        }
+        match self.quotes.insert(player, price) {
+            Some(previous) if previous == price => return,
+            Some(previous) => self.take(previous, player),
+            None => {},
+        }
+
+        self.levels[price].push_back(BookEntry { price, player });
+        if self.best.is_none_or(|best| self.is_better(price, best)) {
+            self.best = Some(price);
+        }
+    }
+
+    fn remove(&mut self, player: PlayerId) -> bool {
+        match self.quotes.remove(&player) {
+            Some(price) => {
+                self.take(price, player);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // pulls the player's entry out of a level and moves the best price on if that emptied it
+    fn take(&mut self, price: usize, player: PlayerId) {
+        let level = &mut self.levels[price];
+        if let Some(position) = level.iter().position(|entry| entry.player == player) {
+            level.remove(position);
+        }
+        if level.is_empty() && self.best == Some(price) {
+            self.best = self.next_best(price);
+        }
+    }
+
+    // walks away from the old best until someone's quoting, at most MAX_PRICE steps
+    fn next_best(&self, from: usize) -> Option<usize> {
+        match self.side {
+            Side::Bid => (1..from).rev().find(|&price| !self.levels[price].is_empty()),
+            Side::Ask => (from + 1..=MAX_PRICE).find(|&price| !self.levels[price].is_empty()),
+        }
+    }
+
+    fn best(&self) -> Option<&BookEntry> {
+        self.best.and_then(|price| self.levels[price].front())
+    }
+
+    // only the levels from the best price outwards can have anyone in them
+    fn entries(&self) -> Vec<BookEntry> {
+        let Some(best) = self.best else {
+            return Vec::new();
+        };
+        let mut entries = Vec::with_capacity(self.quotes.len());
+        match self.side {
+            Side::Bid => self.levels[1..=best].iter().rev().for_each(|level| entries.extend(level.iter().copied())),
+            Side::Ask => self.levels[best..].iter().for_each(|level| entries.extend(level.iter().copied())),
+        }
+        entries
+    }
+
+    // only touches the levels someone is quoting
+    fn clear(&mut self) {
+        for (_, price) in self.quotes.drain() {
+            self.levels[price].clear();
+        }
+        self.best = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players() -> [PlayerId; 3] {
+        ["BookTestA", "BookTestB", "BookTestC"].map(PlayerId::intern)
+    }
+
+    fn bids(book: &PriceLevelBook) -> Vec<(usize, PlayerId)> {
+        book.card_book().bids.iter().map(|entry| (entry.price, entry.player)).collect()
+    }
+
+    fn asks(book: &PriceLevelBook) -> Vec<(usize, PlayerId)> {
+        book.card_book().asks.iter().map(|entry| (entry.price, entry.player)).collect()
+    }
+
+    #[test]
+    fn first_in_at_a_price_is_first_out() {
+        let [a, b, c] = players();
+        let mut book = PriceLevelBook::new();
+        book.update_bid(10, a);
+        book.update_bid(10, b);
+        book.update_bid(9, c);
+
+        assert_eq!(book.get_best_bid(), (Some(10), Some(a)));
+        assert_eq!(bids(&book), vec![(10, a), (10, b), (9, c)]);
+
+        // a better price jumps the queue, same as the old sorted book
+        book.update_bid(11, c);
+        assert_eq!(bids(&book), vec![(11, c), (10, a), (10, b)]);
+    }
+
+    #[test]
+    fn requoting_goes_to_the_back_of_the_queue() {
+        let [a, b, c] = players();
+        let mut book = PriceLevelBook::new();
+        book.update_ask(20, a);
+        book.update_ask(20, b);
+        book.update_ask(25, c);
+
+        // same price again keeps the place
+        book.update_ask(20, a);
+        assert_eq!(asks(&book), vec![(20, a), (20, b), (25, c)]);
+
+        // away + back again is a new quote
+        book.update_ask(25, a);
+        assert_eq!(asks(&book), vec![(20, b), (25, c), (25, a)]);
+        book.update_ask(20, a);
+        assert_eq!(asks(&book), vec![(20, b), (20, a), (25, c)]);
+        assert_eq!(book.get_best_ask(), (Some(20), Some(b)));
+    }
+
+    #[test]
+    fn cancelling_moves_the_best_price_on() {
+        let [a, b, c] = players();
+        let mut book = PriceLevelBook::new();
+        book.update_bid(30, a);
+        book.update_bid(30, b);
+        book.update_bid(12, c);
+        book.update_ask(40, a);
+
+        book.cancel_bid(a);
+        assert_eq!(book.get_best_bid(), (Some(30), Some(b)));
+        assert!(book.has_quotes(a), "the ask is still up");
+
+        book.cancel_bid(b);
+        assert_eq!(book.get_best_bid(), (Some(12), Some(c)));
+
+        // nothing to cancel is a no-op
+        book.cancel_bid(b);
+        book.cancel_bid(c);
+        assert_eq!(book.get_best_bid(), (None, None));
+        assert!(bids(&book).is_empty());
+
+        book.cancel_ask(a);
+        assert!(!book.has_quotes(a));
+        assert_eq!(book.get_best_ask(), (None, None));
+    }
+
+    #[test]
+    fn an_aggressor_meets_the_front_of_the_best_level() {
+        let [a, b, c] = players();
+        let mut book = PriceLevelBook::new();
+        book.update_ask(8, c);
+        book.update_ask(6, a);
+        book.update_ask(6, b);
+
+        // a buy at 9 crosses, the engine trades w/ whoever's first at the best ask and at their price
+        let best_ask = *book.best_ask().unwrap();
+        assert_eq!((best_ask.price, best_ask.player), (6, a));
+
+        // a buy at 5 doesn't cross and rests on the other side
+        book.update_bid(5, c);
+        assert_eq!(book.get_best_bid(), (Some(5), Some(c)));
+
+        // a sell at 5 crosses the bid
+        let best_bid = *book.best_bid().unwrap();
+        assert_eq!((best_bid.price, best_bid.player), (5, c));
+
+        book.last_trade = Some(best_bid.price);
+        book.reset_quotes();
+        assert_eq!((book.get_best_bid(), book.get_best_ask()), ((None, None), (None, None)));
+        assert_eq!(book.card_book().last_trade, Some(5));
+        book.reset_full_book();
+        assert_eq!(book.card_book().last_trade, None);
+    }
+}
@@ -0,0 +1,250 @@
+use super::{Card, CardBook, BookEntry, PlayerId, Update, Trade, Inventory, EndRoundUpdate, EndGamePointsUpdate, StatsUpdate, Snapshot, Announcement};
+use serde::ser::{SerializeStruct, Serializer, SerializeMap};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+
+// =-= Schema Versions =-= //
+
+// v1 is the original schema (stringified trades, `[player_name, price]` entries, etc), v2 is the typed one.
+// The version is picked by the client at subscribe time and v1 stays the default so older bots keep working
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum SchemaVersion {
+    V1,
+    V2,
+}
+
+impl SchemaVersion {
+    pub fn from_request(version: Option<u8>) -> Option<SchemaVersion> {
+        match version {
+            None | Some(1) => Some(SchemaVersion::V1),
+            Some(2) => Some(SchemaVersion::V2),
+            _ => None,
+        }
+    }
+}
+
+
+// wraps a model so it serializes with the v2 schema instead of the legacy one
+pub struct V2<'a, T>(pub &'a T);
+
+
+// both carry `seq` so either can spot a gap + replay it, v1 only gained the field (everything else is left as it was)
+pub fn build_message<T>(kind: &str, data: &T, version: SchemaVersion, seq: u64) -> String
+where
+    T: Serialize,
+    for<'a> V2<'a, T>: Serialize,
+{
+    match version {
+        SchemaVersion::V1 => json!({
+            "kind": kind,
+            "seq": seq,
+            "data": data,
+        }).to_string(),
+        SchemaVersion::V2 => serde_json::to_string(&MessageV2 {
+            kind,
+            version: 2,
+            seq,
+            data: V2(data),
+        }).unwrap(),
+    }
+}
+
+// serialized directly (not through a `serde_json::Value`) so the field order is the one declared here
+#[derive(Serialize)]
+struct MessageV2<'a, T> where V2<'a, T>: Serialize {
+    kind: &'a str,
+    version: u8,
+    seq: u64,
+    data: V2<'a, T>,
+}
+
+
+// =-= Books =-= //
+
+// every entry is an object with the same field order everywhere: price first, then the player
+impl Serialize for V2<'_, BookEntry> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("BookEntry", 2)?;
+        state.serialize_field("price", &self.0.price)?;
+        state.serialize_field("player_name", &self.0.player)?;
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, CardBook> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let bids = self.0.bids.iter().map(V2).collect::<Vec<_>>();
+        let asks = self.0.asks.iter().map(V2).collect::<Vec<_>>();
+
+        let mut state = serializer.serialize_struct("CardBook", 3)?;
+        state.serialize_field("bids", &bids)?;
+        state.serialize_field("asks", &asks)?;
+        state.serialize_field("last_trade", &self.0.last_trade)?; // null when there hasn't been a trade
+        state.end()
+    }
+}
+
+
+// =-= Trades & Updates =-= //
+
+impl Serialize for V2<'_, Trade> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Trade", 4)?;
+        state.serialize_field("card", &self.0.card.to_string())?;
+        state.serialize_field("price", &self.0.price)?;
+        state.serialize_field("buyer", &self.0.buyer)?;
+        state.serialize_field("seller", &self.0.seller)?;
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, Update> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Update", 5)?;
+        state.serialize_field("spades", &V2(&self.0.spades))?;
+        state.serialize_field("clubs", &V2(&self.0.clubs))?;
+        state.serialize_field("diamonds", &V2(&self.0.diamonds))?;
+        state.serialize_field("hearts", &V2(&self.0.hearts))?;
+        state.serialize_field("trade", &self.0.trade.as_ref().map(V2))?;
+        state.end()
+    }
+}
+
+
+// =-= Inventories & Points =-= //
+
+impl Serialize for V2<'_, Inventory> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerInventoryV2 {
+    player_name: PlayerId,
+    spades: usize,
+    clubs: usize,
+    diamonds: usize,
+    hearts: usize,
+}
+
+#[derive(Serialize)]
+struct PlayerPointsV2 {
+    player_name: PlayerId,
+    points: i32,
+}
+
+fn sorted_points(player_points: &HashMap<PlayerId, i32>) -> Vec<PlayerPointsV2> {
+    let mut points = player_points.iter().map(|(player, points)| PlayerPointsV2 { player_name: *player, points: *points }).collect::<Vec<_>>();
+    points.sort_by_cached_key(|points| points.player_name.name());
+    points
+}
+
+impl Serialize for V2<'_, EndRoundUpdate> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let card_count = CardCountV2(&self.0.card_count);
+
+        let mut inventories = self.0.player_inventories.iter().map(|(player, inventory)| PlayerInventoryV2 {
+            player_name: *player,
+            spades: inventory.spades,
+            clubs: inventory.clubs,
+            diamonds: inventory.diamonds,
+            hearts: inventory.hearts,
+        }).collect::<Vec<_>>();
+        inventories.sort_by_cached_key(|inventory| inventory.player_name.name());
+
+        let mut state = serializer.serialize_struct("EndRoundUpdate", 5)?;
+        state.serialize_field("card_count", &card_count)?;
+        state.serialize_field("player_inventories", &inventories)?;
+        state.serialize_field("player_points", &sorted_points(&self.0.player_points))?;
+        state.serialize_field("goal_suit", &self.0.goal_suit.to_string())?;
+        state.serialize_field("common_suit", &self.0.common_suit.to_string())?;
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, EndGamePointsUpdate> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("EndGamePointsUpdate", 1)?;
+        state.serialize_field("player_points", &sorted_points(&self.0.player_points))?;
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, StatsUpdate> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("StatsUpdate", 3)?;
+        state.serialize_field("round_trades", &self.0.round_trades)?;
+        state.serialize_field("round_volume", &self.0.round_volume)?;
+        state.serialize_field("player_points", &sorted_points(&self.0.player_points))?;
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, Announcement> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer) // nothing to fix up from v1
+    }
+}
+
+impl Serialize for V2<'_, Snapshot> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Snapshot", 7)?;
+        state.serialize_field("seq", &self.0.seq)?;
+        state.serialize_field("phase", &self.0.phase)?;
+        state.serialize_field("round", &self.0.round)?;
+        state.serialize_field("books", &V2(&self.0.books))?;
+        state.serialize_field("inventory", &self.0.inventory)?;
+        state.serialize_field("orders", &self.0.orders)?;
+        state.serialize_field("player_points", &sorted_points(&self.0.player_points))?;
+        state.end()
+    }
+}
+
+// always emits all four suits in book order, missing suits are 0
+struct CardCountV2<'a>(&'a HashMap<Card, usize>);
+
+impl Serialize for CardCountV2<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(4))?;
+        for card in [Card::Spade, Card::Club, Card::Diamond, Card::Heart] {
+            map.serialize_entry(&format!("{}s", card.to_string()), self.0.get(&card).unwrap_or(&0))?;
+        }
+        map.end()
+    }
+}
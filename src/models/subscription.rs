@@ -0,0 +1,211 @@
+use super::{Card, CardBook, Trade, Update, V2};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+
+// =-= Channels =-= //
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum Channel {
+    Book(Card),
+    Trades,
+    Private,   // dealing_cards (only ever your own inventory)
+    GameState, // end_round / end_game
+    Stats,
+}
+
+impl Channel {
+    // "book" on its own expands to all four suits
+    pub fn parse(channel: &str) -> Option<Vec<Channel>> {
+        match channel {
+            "book" => Some(vec![Channel::Book(Card::Spade), Channel::Book(Card::Club), Channel::Book(Card::Diamond), Channel::Book(Card::Heart)]),
+            "book.spades" => Some(vec![Channel::Book(Card::Spade)]),
+            "book.clubs" => Some(vec![Channel::Book(Card::Club)]),
+            "book.diamonds" => Some(vec![Channel::Book(Card::Diamond)]),
+            "book.hearts" => Some(vec![Channel::Book(Card::Heart)]),
+            "trades" => Some(vec![Channel::Trades]),
+            "private" => Some(vec![Channel::Private]),
+            "game_state" => Some(vec![Channel::GameState]),
+            "stats" => Some(vec![Channel::Stats]),
+            _ => None,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        match self {
+            Channel::Book(Card::Spade) => 1 << 0,
+            Channel::Book(Card::Club) => 1 << 1,
+            Channel::Book(Card::Diamond) => 1 << 2,
+            Channel::Book(Card::Heart) => 1 << 3,
+            Channel::Trades => 1 << 4,
+            Channel::Private => 1 << 5,
+            Channel::GameState => 1 << 6,
+            Channel::Stats => 1 << 7,
+        }
+    }
+}
+
+
+// =-= Depth =-= //
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum Depth {
+    Bbo,
+    Top(usize),
+    Full,
+}
+
+impl Depth {
+    // "bbo", "full" or "top_<n>"
+    pub fn parse(depth: &str) -> Option<Depth> {
+        match depth {
+            "bbo" => Some(Depth::Bbo),
+            "full" => Some(Depth::Full),
+            _ => match depth.strip_prefix("top_").map(|levels| levels.parse::<usize>()) {
+                Some(Ok(levels)) if levels > 0 => Some(Depth::Top(levels)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn levels(&self) -> usize {
+        match self {
+            Depth::Bbo => 1,
+            Depth::Top(levels) => *levels,
+            Depth::Full => usize::MAX,
+        }
+    }
+}
+
+
+// =-= Subscription =-= //
+
+// channels are kept as a bitmask so a subscription is cheap to copy, compare and use as a cache key when fanning out
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct Subscription {
+    channels: u8,
+    pub depth: Depth,
+}
+
+impl Subscription {
+    // what a subscribe without a channel list gets, this is the same feed v1 has always had
+    pub fn legacy() -> Self {
+        let mut subscription = Self { channels: 0, depth: Depth::Full };
+        for channel in ["book", "trades", "private", "game_state"] {
+            subscription.add(&Channel::parse(channel).unwrap());
+        }
+        subscription
+    }
+
+    pub fn new(channels: &[Channel], depth: Depth) -> Self {
+        let mut subscription = Self { channels: 0, depth };
+        subscription.add(channels);
+        subscription
+    }
+
+    pub fn add(&mut self, channels: &[Channel]) {
+        for channel in channels {
+            self.channels |= channel.bit();
+        }
+    }
+
+    pub fn remove(&mut self, channels: &[Channel]) {
+        for channel in channels {
+            self.channels &= !channel.bit();
+        }
+    }
+
+    pub fn contains(&self, channel: Channel) -> bool {
+        self.channels & channel.bit() != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels == 0
+    }
+
+    // returns None when nothing in the update is relevant to this subscription
+    pub fn filter_update(&self, update: &Update) -> Option<SubscribedUpdate> {
+        let book = |card: Card, book: &CardBook| {
+            match self.contains(Channel::Book(card)) {
+                true => Some(book.with_depth(self.depth.levels())),
+                false => None,
+            }
+        };
+
+        let filtered = SubscribedUpdate {
+            spades: book(Card::Spade, &update.spades),
+            clubs: book(Card::Club, &update.clubs),
+            diamonds: book(Card::Diamond, &update.diamonds),
+            hearts: book(Card::Heart, &update.hearts),
+            trade: update.trade.clone(),
+            include_trade: self.contains(Channel::Trades),
+        };
+
+        let has_books = filtered.spades.is_some() || filtered.clubs.is_some() || filtered.diamonds.is_some() || filtered.hearts.is_some();
+        let has_trade = filtered.include_trade && filtered.trade.is_some();
+        if !has_books && !has_trade {
+            return None;
+        }
+        Some(filtered)
+    }
+}
+
+
+// =-= Subscribed Update =-= //
+
+// an `Update` with only the books / trade a subscription asked for (and the books cut down to its depth)
+#[derive(Debug, Clone)]
+pub struct SubscribedUpdate {
+    pub spades: Option<CardBook>,
+    pub clubs: Option<CardBook>,
+    pub diamonds: Option<CardBook>,
+    pub hearts: Option<CardBook>,
+    pub trade: Option<Trade>,
+    pub include_trade: bool,
+}
+
+// mirrors the `Update` serialization exactly when every channel is subscribed so v1 clients see no difference
+impl Serialize for SubscribedUpdate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Update", 5)?;
+        for (name, book) in [("clubs", &self.clubs), ("diamonds", &self.diamonds), ("hearts", &self.hearts), ("spades", &self.spades)] {
+            if let Some(book) = book {
+                state.serialize_field(name, book)?;
+            }
+        }
+
+        if self.include_trade {
+            if let Some(trade) = &self.trade {
+                let trade_str = format!("{},{},{},{}", trade.card.to_string().to_lowercase(), trade.price, trade.buyer, trade.seller);
+                state.serialize_field("trade", &trade_str)?;
+            } else {
+                state.serialize_field("trade", &String::new())?;
+            }
+        }
+
+        state.end()
+    }
+}
+
+impl Serialize for V2<'_, SubscribedUpdate> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Update", 5)?;
+        for (name, book) in [("spades", &self.0.spades), ("clubs", &self.0.clubs), ("diamonds", &self.0.diamonds), ("hearts", &self.0.hearts)] {
+            if let Some(book) = book {
+                state.serialize_field(name, &V2(book))?;
+            }
+        }
+
+        if self.0.include_trade {
+            state.serialize_field("trade", &self.0.trade.as_ref().map(V2))?;
+        }
+
+        state.end()
+    }
+}
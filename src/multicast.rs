@@ -0,0 +1,208 @@
+use super::{Card, CardBook, BookEntry, PlayerId, Update, Trade, CL, V2};
+use serde::Serialize;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Mutex};
+
+
+// One datagram per book update goes out to the multicast group, so every receiver gets it at the same time (no walking the player map)
+// Packets carry their own feed `seq` (contiguous, unlike the engine's seq which also counts private events) so receivers can spot gaps,
+// and recover by pulling a snapshot over TCP then applying only the packets with a higher `seq`
+
+pub type FeedSnapshotHandle = Arc<Mutex<FeedSnapshot>>;
+
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+
+#[derive(Debug, Clone)]
+pub struct FeedSnapshot {
+    pub seq: u64, // feed seq of the last packet these books include
+    pub books: Update,
+}
+
+
+#[derive(Serialize)]
+struct BookDelta {
+    card: String,
+    side: &'static str, // "bid" or "ask"
+    action: &'static str, // "add" or "remove"
+    price: usize,
+    player_name: PlayerId,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum FeedPacket<'a> {
+    Delta {
+        seq: u64,
+        engine_seq: u64,
+        deltas: Vec<BookDelta>,
+        trade: Option<V2<'a, Trade>>,
+    },
+    Heartbeat { seq: u64 }, // nothing changed, lets receivers notice a missed packet without waiting for the next change
+}
+
+#[derive(Serialize)]
+struct SnapshotMessage<'a> {
+    kind: &'static str,
+    seq: u64,
+    books: V2<'a, Update>,
+}
+
+
+pub struct MulticastPublisher {
+    socket: UdpSocket,
+    group: SocketAddr,
+    seq: u64,
+    snapshot: FeedSnapshotHandle,
+}
+
+impl MulticastPublisher {
+    // `interface` is the local address to send from (127.0.0.1 keeps it on loopback for testing)
+    pub fn new(group: SocketAddr, interface: &str) -> io::Result<(Self, FeedSnapshotHandle)> {
+        let socket = UdpSocket::bind((interface, 0))?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(1)?;
+        socket.set_nonblocking(true)?; // never stall the publisher on the feed, a dropped packet is recovered w/ a snapshot
+
+        let snapshot = Arc::new(Mutex::new(FeedSnapshot {
+            seq: 0,
+            books: Update {
+                spades: CardBook::new(),
+                clubs: CardBook::new(),
+                diamonds: CardBook::new(),
+                hearts: CardBook::new(),
+                trade: None,
+            },
+        }));
+
+        let publisher = Self {
+            socket,
+            group,
+            seq: 0,
+            snapshot: Arc::clone(&snapshot),
+        };
+        Ok((publisher, snapshot))
+    }
+
+
+    // diffs the update against the last published books, sends it as one packet and rolls the snapshot forward
+    pub async fn publish(&mut self, engine_seq: u64, update: &Update) {
+        let mut snapshot = self.snapshot.lock().await;
+
+        let mut deltas = Vec::new();
+        for (card, previous, current) in [
+            (Card::Spade, &snapshot.books.spades, &update.spades),
+            (Card::Club, &snapshot.books.clubs, &update.clubs),
+            (Card::Diamond, &snapshot.books.diamonds, &update.diamonds),
+            (Card::Heart, &snapshot.books.hearts, &update.hearts),
+        ] {
+            book_deltas(card, "bid", &previous.bids, &current.bids, &mut deltas);
+            book_deltas(card, "ask", &previous.asks, &current.asks, &mut deltas);
+        }
+
+        let packet = match deltas.is_empty() && update.trade.is_none() {
+            true => FeedPacket::Heartbeat { seq: self.seq },
+            false => {
+                self.seq += 1;
+                FeedPacket::Delta {
+                    seq: self.seq,
+                    engine_seq,
+                    deltas,
+                    trade: update.trade.as_ref().map(V2),
+                }
+            }
+        };
+
+        match serde_json::to_vec(&packet) {
+            Ok(packet) if packet.len() <= MAX_DATAGRAM_SIZE => {
+                if let Err(e) = self.socket.send_to(&packet, self.group) {
+                    println!("{}[!] MULTICAST |:| Failed to send seq {}: {:?}{}", CL::Red.get(), self.seq, e, CL::End.get());
+                }
+            },
+            Ok(packet) => println!("{}[!] MULTICAST |:| Packet for seq {} is too big for a datagram ({} bytes){}", CL::Red.get(), self.seq, packet.len(), CL::End.get()),
+            Err(e) => println!("{}[!] MULTICAST |:| Failed to serialize seq {}: {:?}{}", CL::Red.get(), self.seq, e, CL::End.get()),
+        }
+
+        // the snapshot moves forward even if the send failed, receivers see the gap and come back for it
+        snapshot.seq = self.seq;
+        snapshot.books = Update {
+            spades: update.spades.clone(),
+            clubs: update.clubs.clone(),
+            diamonds: update.diamonds.clone(),
+            hearts: update.hearts.clone(),
+            trade: None,
+        };
+    }
+}
+
+
+// entries are matched on (price, player_name), anything only in `previous` was removed (filled / cancelled) and anything only in `current` was added
+fn book_deltas(card: Card, side: &'static str, previous: &[BookEntry], current: &[BookEntry], deltas: &mut Vec<BookDelta>) {
+    let mut removed: Vec<&BookEntry> = previous.iter().collect();
+    let mut added = Vec::new();
+    for entry in current {
+        match removed.iter().position(|old| old.price == entry.price && old.player == entry.player) {
+            Some(index) => {
+                removed.swap_remove(index);
+            },
+            None => added.push(entry),
+        }
+    }
+
+    for (action, entries) in [("remove", removed), ("add", added)] {
+        for entry in entries {
+            deltas.push(BookDelta { card: card.to_string(), side, action, price: entry.price, player_name: entry.player });
+        }
+    }
+}
+
+
+// =-= Snapshot Service =-= //
+
+// connect, read one line of JSON (`{"kind": "snapshot", "seq", "books"}`), get disconnected
+pub async fn run_snapshot_service(addr: String, snapshot: FeedSnapshotHandle, mut shutdown: oneshot::Receiver<()>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}[!] MULTICAST |:| Failed to bind the snapshot service to {}: {:?}{}", CL::Red.get(), addr, e, CL::End.get());
+            return;
+        }
+    };
+    println!("{}[+] MULTICAST |:| Snapshot service listening on {}{}", CL::Green.get(), addr, CL::End.get());
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                break;
+            }
+            result = listener.accept() => {
+                let (mut stream, addr) = match result {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        println!("{}[!] MULTICAST |:| Error accepting a snapshot request: {:?}{}", CL::Red.get(), e, CL::End.get());
+                        continue;
+                    }
+                };
+
+                let message = {
+                    let snapshot = snapshot.lock().await;
+                    serde_json::to_string(&SnapshotMessage { kind: "snapshot", seq: snapshot.seq, books: V2(&snapshot.books) })
+                };
+                tokio::spawn(async move {
+                    let message = match message {
+                        Ok(message) => message + "\n",
+                        Err(_) => return,
+                    };
+                    if let Err(e) = stream.write_all(message.as_bytes()).await {
+                        println!("{}[!] MULTICAST |:| Failed to send a snapshot to {:?}: {:?}{}", CL::DullRed.get(), addr, e, CL::End.get());
+                    }
+                    let _ = stream.shutdown().await;
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::{Card, Direction, Order, GatewayResponse, HTTPResponse, PlayerId, MAX_PRICE};
+use super::api_error::ApiError;
+use super::order_queue::OrderQueue;
+use super::rate_limit::{Endpoint, RateLimiter};
+use super::sequencer::Received;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+// =-= Order Entry =-= //
+
+// orders sent over a connection that's already been authenticated (UDS gateway + websocket), same checks as /order and /cancel
+// then straight onto the matching engine's queue
+#[derive(Clone)]
+pub struct OrderEntry {
+    pub started_game: Arc<AtomicBool>,
+    pub order_queue: Arc<OrderQueue>,
+    pub rate_limiter: Arc<RateLimiter>, // shared w/ the RestAPI
+}
+
+impl OrderEntry {
+    // `action` is "order" or "cancel" (which ignores the price), `received` is when the frame came off the socket
+    pub async fn submit(&self, player_name: String, action: &str, card: Option<&str>, price: Option<usize>, direction: Option<&str>, received: Received) -> GatewayResponse {
+        let error = |error: ApiError| GatewayResponse { received_ns: Some(received.0), ..GatewayResponse::from(HTTPResponse::from(error)) };
+
+        if !self.started_game.load(Ordering::Acquire) {
+            return error(ApiError::NoGame);
+        }
+
+        let endpoint = match action {
+            "cancel" => Endpoint::Cancel,
+            _ => Endpoint::Order,
+        };
+        if let Err(e) = self.rate_limiter.take(&player_name, endpoint).await {
+            return error(e);
+        }
+
+        let card = match card {
+            Some("spade") => Card::Spade,
+            Some("club") => Card::Club,
+            Some("diamond") => Card::Diamond,
+            Some("heart") => Card::Heart,
+            _ => return error(ApiError::InvalidCard),
+        };
+
+        let direction = match direction {
+            Some("buy") => Direction::Buy,
+            Some("sell") => Direction::Sell,
+            _ => return error(ApiError::InvalidDirection),
+        };
+
+        let price = match (endpoint, price) {
+            (Endpoint::Cancel, _) => None,
+            (_, Some(price)) if price > 0 && price <= MAX_PRICE => Some(price),
+            _ => return error(ApiError::InvalidPrice),
+        };
+
+        let player = match PlayerId::lookup(&player_name) {
+            Some(player) => player,
+            None => return error(ApiError::UnknownPlayer),
+        };
+
+        let order = Order {
+            player,
+            card,
+            direction,
+            price,
+        };
+
+        let start = minstant::Instant::now();
+        match self.order_queue.submit(order, received).await {
+            Ok(answer) => GatewayResponse {
+                status: answer.response.status,
+                message: answer.response.message,
+                engine_latency_ns: Some(start.elapsed().as_nanos() as u64),
+                received_ns: Some(received.0),
+                sequence: Some(answer.sequence),
+            },
+            Err(e) => error(e),
+        }
+    }
+}
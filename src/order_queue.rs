@@ -0,0 +1,462 @@
+use super::{Order, CL};
+use super::api_error::ApiError;
+use super::matching_engine::MatchingEngine;
+use super::ring::{self, Consumer, Producer, Responder, ResponseSlots};
+use super::link::{EngineLink, LinkReply, LinkRequest};
+use super::sequencer::{OrderAnswer, Received, Sequencer};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::sync::oneshot::Sender as OneshotSender;
+use tokio::time::{timeout, Duration};
+use kanal::{AsyncReceiver, AsyncSender};
+
+
+// =-= Order Queue =-= //
+
+// The one way into the matching engine (RestAPI, websocket + UDS gateway, the admin and the game loop). The hotpath thread owns
+// the engine outright and works through this queue in order, orders and everything else alike. It's bounded so a stalled engine
+// can't pile up orders without limit: a full queue turns new orders away w/ BUSY, and nobody waits longer than the timeout for an answer
+// What carries the messages is up to the QueueTransport, the rest of the server doesn't know the difference. On a gateway
+// (ROLE=gateway) the engine is in another process and everything goes over the engine link to the queue over there
+
+pub const ORDER_QUEUE_DEPTH: usize = 1024; // orders, ORDER_QUEUE_DEPTH to override
+pub const ENGINE_TIMEOUT: Duration = Duration::from_millis(1_000); // ENGINE_TIMEOUT_MS to override
+pub const BOOK_STATE_INTERVAL: Duration = Duration::from_secs(5); // the engine sends out the books after this long without a message, --book-interval-ms to override
+const SPINS_BEFORE_YIELD: u32 = 1024; // empty polls before a spinning hotpath yields the core + looks at the clock
+const RING_FULL_RETRY: Duration = Duration::from_micros(100); // how often a task looks for room in a full ring
+
+pub type EngineTask = Box<dyn FnOnce(&mut MatchingEngine) + Send>;
+
+pub enum EngineMessage {
+    Order(Order, Received, ResponseSender),
+    Task(EngineTask), // admin commands, registrations, round transitions
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueTransport {
+    Kanal, // kanal's bounded channel + a oneshot per order for the answer
+    Ring, // lock-free ring + preallocated response slots, the hotpath parks when it's empty
+    RingSpin, // same ring but the hotpath busy-spins on it, for when it has a core to itself
+    Link, // a gateway's, the engine's own queue is behind the link (not an ORDER_TRANSPORT)
+}
+
+impl QueueTransport {
+    pub fn parse(transport: &str) -> Option<Self> {
+        match transport {
+            "kanal" => Some(QueueTransport::Kanal),
+            "ring" => Some(QueueTransport::Ring),
+            "ring_spin" => Some(QueueTransport::RingSpin),
+            _ => None,
+        }
+    }
+}
+
+// where the engine answers an order
+pub enum ResponseSender {
+    Oneshot(OneshotSender<OrderAnswer>),
+    Slot(Responder<OrderAnswer>),
+}
+
+impl ResponseSender {
+    // whoever sent it already gave up (ENGINE_TIMEOUT)
+    pub fn is_closed(&self) -> bool {
+        match self {
+            ResponseSender::Oneshot(sender) => sender.is_closed(),
+            ResponseSender::Slot(responder) => responder.is_closed(),
+        }
+    }
+
+    pub fn send(self, response: OrderAnswer) -> Result<(), OrderAnswer> {
+        match self {
+            ResponseSender::Oneshot(sender) => sender.send(response),
+            ResponseSender::Slot(responder) => responder.send(response),
+        }
+    }
+}
+
+enum QueueSender {
+    Kanal(AsyncSender<EngineMessage>),
+    Ring(Producer<EngineMessage>, Arc<ResponseSlots<OrderAnswer>>),
+    Link(Arc<EngineLink>),
+}
+
+// the engine's end of the queue, `run` is the hotpath
+pub struct EngineReceiver {
+    incoming: Incoming,
+    sequencer: Sequencer,
+}
+
+enum Incoming {
+    Kanal(AsyncReceiver<EngineMessage>),
+    Ring(Consumer<EngineMessage>),
+}
+
+
+pub struct OrderQueue {
+    sender: QueueSender,
+    pub transport: QueueTransport,
+    pub timeout: Duration,
+    pub sequencer_window: Duration,
+    busy: AtomicU64, // orders turned away because the queue was full
+    timeouts: AtomicU64, // orders + tasks the engine didn't answer in time
+    out_of_order: Arc<AtomicU64>, // orders the sequencer let go after a later one, see Sequencer
+}
+
+// what GET /queue answers w/
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub transport: QueueTransport,
+    pub depth: usize,
+    pub capacity: usize,
+    pub timeout_ms: u64,
+    pub busy: u64,
+    pub timeouts: u64,
+    pub sequencer_window_us: u64,
+    pub out_of_order: u64,
+}
+
+impl OrderQueue {
+    // the engine's end of the queue goes to the hotpath
+    pub fn new(capacity: usize, timeout: Duration, transport: QueueTransport, sequencer_window: Duration) -> (Self, EngineReceiver) {
+        let capacity = capacity.max(1); // 0 would make every order wait on the engine
+        let (sender, incoming) = match transport {
+            QueueTransport::Kanal => {
+                let (sender, receiver) = kanal::bounded_async(capacity);
+                (QueueSender::Kanal(sender), Incoming::Kanal(receiver))
+            },
+            QueueTransport::Ring | QueueTransport::RingSpin => {
+                let (producer, consumer) = ring::mpsc(capacity, transport == QueueTransport::RingSpin); // rounded up to a power of 2
+                // a slot's held from submit until the answer's read, so answered-but-not-yet-read orders need room on top of the queue
+                let responses = ResponseSlots::new(producer.capacity() * 2);
+                (QueueSender::Ring(producer, responses), Incoming::Ring(consumer))
+            },
+            QueueTransport::Link => panic!("[!] A linked queue has no engine on this end, see OrderQueue::link"),
+        };
+        let out_of_order = Arc::new(AtomicU64::new(0));
+        let queue = Self {
+            sender,
+            transport,
+            timeout,
+            sequencer_window,
+            busy: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            out_of_order: Arc::clone(&out_of_order),
+        };
+        (queue, EngineReceiver { incoming, sequencer: Sequencer::new(sequencer_window, out_of_order) })
+    }
+
+    // a gateway's queue, there's no engine on this end
+    pub fn link(engine: Arc<EngineLink>, timeout: Duration) -> Self {
+        Self {
+            sender: QueueSender::Link(engine),
+            transport: QueueTransport::Link,
+            timeout,
+            sequencer_window: Duration::ZERO, // the engine's process sequences
+            busy: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            out_of_order: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn engine_link(&self) -> Option<&Arc<EngineLink>> {
+        match &self.sender {
+            QueueSender::Link(engine) => Some(engine),
+            _ => None,
+        }
+    }
+
+    // never waits for room in the queue, only (up to the timeout) for the engine's answer. `received` is when the gateway first saw it
+    pub async fn submit(&self, order: Order, received: Received) -> Result<OrderAnswer, ApiError> {
+        // dropping our end on a timeout tells the engine to skip the order if it hasn't gotten to it yet
+        let answer = match &self.sender {
+            QueueSender::Kanal(sender) => {
+                let (oneshot_sender, receiver) = oneshot::channel();
+                match sender.try_send(EngineMessage::Order(order, received, ResponseSender::Oneshot(oneshot_sender))) {
+                    Ok(true) => {},
+                    Ok(false) => return Err(self.turn_away()),
+                    Err(e) => {
+                        println!("{}[!] Failed to send order to matching engine: {:?}{}", CL::Red.get(), e, CL::End.get());
+                        return Err(ApiError::EngineUnavailable);
+                    }
+                }
+                timeout(self.timeout, async { receiver.await.ok() }).await
+            },
+            QueueSender::Ring(producer, responses) => {
+                let Some((mut waiter, responder)) = responses.acquire() else {
+                    return Err(self.turn_away());
+                };
+                if producer.push(EngineMessage::Order(order, received, ResponseSender::Slot(responder))).is_err() {
+                    return Err(self.turn_away());
+                }
+                timeout(self.timeout, waiter.recv()).await
+            },
+            // the engine's queue does the turning away, BUSY + the rest come back as they are
+            QueueSender::Link(engine) => match engine.request(LinkRequest::Order(order, received), self.timeout).await {
+                Ok(LinkReply::Order(answer)) => return answer,
+                Ok(_) => return Err(ApiError::EngineUnavailable),
+                Err(ApiError::EngineTimeout(_)) => return Err(self.timed_out()),
+                Err(error) => return Err(error),
+            },
+        };
+
+        match answer {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(ApiError::EngineUnavailable),
+            Err(_) => Err(self.timed_out()),
+        }
+    }
+
+    fn timed_out(&self) -> ApiError {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+        println!("{}[!] ORDER |:| The matching engine didn't answer within {}ms ({} orders queued){}", CL::Orange.get(), self.timeout.as_millis(), self.depth(), CL::End.get());
+        ApiError::EngineTimeout(self.timeout.as_millis() as u64)
+    }
+
+    fn turn_away(&self) -> ApiError {
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        ApiError::Busy(self.depth())
+    }
+
+    // runs `task` on the engine's thread, in line w/ the orders. Unlike orders these wait for room in the queue instead of
+    // being turned away, but the whole thing is still bounded by the timeout. A task that times out after it was queued still runs
+    pub async fn call<R, F>(&self, task: F) -> Result<R, ApiError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut MatchingEngine) -> R + Send + 'static,
+    {
+        if let QueueSender::Link(engine) = &self.sender {
+            // closures can't cross the link, anything a gateway needs from the engine has its own LinkRequest
+            println!("{}[!] ENGINE |:| A task was sent to a gateway's queue, the engine is at {}{}", CL::Red.get(), engine.addr, CL::End.get());
+            return Err(ApiError::EngineUnavailable);
+        }
+        let (oneshot_sender, receiver) = oneshot::channel();
+        let task: EngineTask = Box::new(move |matching_engine| {
+            let result = task(matching_engine);
+            matching_engine.flush(true); // before answering, so the caller's next read already sees it
+            let _ = oneshot_sender.send(result);
+        });
+
+        let answer = timeout(self.timeout, async {
+            self.send_task(task).await?;
+            receiver.await.map_err(|_| ApiError::EngineUnavailable)
+        });
+        match answer.await {
+            Ok(result) => result,
+            Err(_) => {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                println!("{}[!] ENGINE |:| The matching engine didn't get to a task within {}ms ({} messages queued){}", CL::Orange.get(), self.timeout.as_millis(), self.depth(), CL::End.get());
+                Err(ApiError::EngineTimeout(self.timeout.as_millis() as u64))
+            }
+        }
+    }
+
+    async fn send_task(&self, task: EngineTask) -> Result<(), ApiError> {
+        match &self.sender {
+            QueueSender::Kanal(sender) => sender.send(EngineMessage::Task(task)).await.map_err(|e| {
+                println!("{}[!] Failed to send a task to the matching engine: {:?}{}", CL::Red.get(), e, CL::End.get());
+                ApiError::EngineUnavailable
+            }),
+            // the ring has nothing to wait on, so look again every so often (tasks are rare, the ring's rarely full)
+            QueueSender::Ring(producer, _) => {
+                let mut message = EngineMessage::Task(task);
+                loop {
+                    match producer.push(message) {
+                        Ok(()) => return Ok(()),
+                        Err(back) => message = back,
+                    }
+                    tokio::time::sleep(RING_FULL_RETRY).await;
+                }
+            },
+            QueueSender::Link(_) => Err(ApiError::EngineUnavailable), // `call` never gets here
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match &self.sender {
+            QueueSender::Kanal(sender) => sender.len(),
+            QueueSender::Ring(producer, _) => producer.len(),
+            QueueSender::Link(engine) => engine.in_flight(),
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            transport: self.transport,
+            depth: self.depth(),
+            capacity: match &self.sender {
+                QueueSender::Kanal(sender) => sender.capacity(),
+                QueueSender::Ring(producer, _) => producer.capacity(),
+                QueueSender::Link(_) => 0,
+            },
+            timeout_ms: self.timeout.as_millis() as u64,
+            busy: self.busy.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            sequencer_window_us: self.sequencer_window.as_micros() as u64,
+            out_of_order: self.out_of_order.load(Ordering::Relaxed),
+        }
+    }
+
+    // the stats of the queue in front of the engine, wherever that is
+    pub async fn engine_stats(&self) -> Result<QueueStats, ApiError> {
+        match &self.sender {
+            QueueSender::Link(engine) => match engine.request(LinkRequest::Stats, self.timeout).await? {
+                LinkReply::Stats(stats) => Ok(stats),
+                _ => Err(ApiError::EngineUnavailable),
+            },
+            _ => Ok(self.stats()),
+        }
+    }
+}
+
+
+// =-= Hotpath =-= //
+
+impl EngineReceiver {
+    // works through the queue until shutdown, sending out the books whenever it's been quiet for `book_interval`
+    // While the sequencer's holding orders the hotpath doesn't park, it keeps looking at the queue + the clock until they're out
+    pub async fn run(self, matching_engine: &mut MatchingEngine, book_interval: Duration, mut shutdown: oneshot::Receiver<()>) {
+        let EngineReceiver { incoming, mut sequencer } = self;
+        match incoming {
+            Incoming::Kanal(receiver) => {
+                // kanal drops a message that was already handed to a recv() future if that future gets dropped, so the same one
+                // is kept across the timer and only replaced once it's given us something
+                let mut next_message = Box::pin(receiver.recv());
+                loop {
+                    let timer = tokio::time::sleep(book_interval);
+                    let holding = sequencer.is_holding();
+
+                    tokio::select! {
+                        biased;
+                        _ = &mut shutdown => {
+                            break;
+                        }
+                        result = &mut next_message => {
+                            match result {
+                                Ok(message) => {
+                                    next_message.set(receiver.recv());
+                                    handle(matching_engine, &mut sequencer, message);
+                                },
+                                Err(e) => {
+                                    // only happens once the queue's closed, nothing's coming after this
+                                    println!("{}[!] Matching Engine Receiver Failed: {:?}{}", CL::Red.get(), e, CL::End.get());
+                                    break;
+                                }
+                            }
+                        }
+                        _ = tokio::task::yield_now(), if holding => {
+                            release(matching_engine, &mut sequencer);
+                        }
+                        _ = timer => {
+                            send_book_state(matching_engine);
+                        }
+                    }
+                }
+            },
+            // never awaits, so nothing else gets to run on the hotpath's runtime (nothing else needs to)
+            Incoming::Ring(mut consumer) if consumer.is_spinning() => {
+                let mut last_message = Instant::now();
+                let mut handled = false;
+                let mut spins = 0;
+                loop {
+                    if let Some(message) = consumer.pop() {
+                        handle(matching_engine, &mut sequencer, message);
+                        handled = true;
+                        spins = 0;
+                        continue;
+                    }
+                    if sequencer.is_holding() {
+                        release(matching_engine, &mut sequencer);
+                    }
+                    spins += 1;
+                    if spins < SPINS_BEFORE_YIELD {
+                        std::hint::spin_loop();
+                        continue;
+                    }
+
+                    // costs nothing when the hotpath has its core to itself, lets the gateway in when it doesn't
+                    spins = 0;
+                    std::thread::yield_now();
+                    if !matches!(shutdown.try_recv(), Err(TryRecvError::Empty)) {
+                        break;
+                    }
+                    if handled {
+                        last_message = Instant::now();
+                        handled = false;
+                    } else if last_message.elapsed() >= book_interval {
+                        send_book_state(matching_engine);
+                        last_message = Instant::now();
+                    }
+                }
+            },
+            Incoming::Ring(mut consumer) => {
+                loop {
+                    let timer = tokio::time::sleep(book_interval);
+                    let holding = sequencer.is_holding();
+
+                    tokio::select! {
+                        biased;
+                        _ = &mut shutdown => {
+                            break;
+                        }
+                        message = consumer.recv() => { // only takes a message off the ring when it returns it
+                            handle(matching_engine, &mut sequencer, message);
+                        }
+                        _ = tokio::task::yield_now(), if holding => {
+                            release(matching_engine, &mut sequencer);
+                        }
+                        _ = timer => {
+                            send_book_state(matching_engine);
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+// orders go through the sequencer, tasks (admin, registrations, the game loop) run as soon as they're here but only after every
+// order still held, so nothing stamped before an end of round / pause / kick gets matched after it
+fn handle(matching_engine: &mut MatchingEngine, sequencer: &mut Sequencer, message: EngineMessage) {
+    match message {
+        EngineMessage::Order(order_data, received, response_sender) => {
+            sequencer.hold(order_data, received, response_sender);
+            release(matching_engine, sequencer);
+        },
+        EngineMessage::Task(task) => {
+            match_orders(matching_engine, sequencer, Sequencer::release_now);
+            task(matching_engine); // flushes + answers for itself
+        },
+    }
+}
+
+// matches every order the sequencer's done holding
+fn release(matching_engine: &mut MatchingEngine, sequencer: &mut Sequencer) {
+    match_orders(matching_engine, sequencer, Sequencer::release);
+}
+
+fn match_orders(matching_engine: &mut MatchingEngine, sequencer: &mut Sequencer, next: fn(&mut Sequencer) -> Option<(Order, ResponseSender)>) {
+    while let Some((order_data, response_sender)) = next(sequencer) {
+        // whoever sent it already gave up (ENGINE_TIMEOUT), so they were told it might not go through
+        if response_sender.is_closed() {
+            println!("{}[!] Skipping a timed out order from {}{}", CL::Orange.get(), order_data.player, CL::End.get());
+            continue;
+        }
+        let sequence = sequencer.next_sequence();
+        let response = matching_engine.process_order(order_data);
+        matching_engine.flush(false); // before answering, so the player's next read already sees it
+        if let Err(e) = response_sender.send(OrderAnswer { response, sequence }) {
+            println!("{}[!] Failed to send the response back to the RestAPI: {:?}{}", CL::Red.get(), e, CL::End.get()); // how to handle this? assume that the HTTP Connection was dropped?
+        }
+    }
+}
+
+fn send_book_state(matching_engine: &mut MatchingEngine) {
+    matching_engine.send_book_state();
+    matching_engine.flush(false);
+}
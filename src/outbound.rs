@@ -0,0 +1,250 @@
+use super::CL;
+use super::deflate::Bandwidth;
+use super::frame::SharedFrame;
+use super::websocket::WsSender;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+
+// =-= Outbound Queues =-= //
+
+// Every websocket session gets its own bounded queue + a writer task that drains it into the socket, so the publisher only ever
+// queues a message and moves on to the next session. What happens when a session's queue is full is up to the SlowConsumerPolicy.
+// Replies to the session's own requests (subscribe, orders, pongs, ..) skip the bound, there's only ever as many as it asked for
+
+pub const WS_OUTBOUND_QUEUE: usize = 256; // messages, WS_OUTBOUND_QUEUE to override
+const WRITER_SHUTDOWN: Duration = Duration::from_secs(1); // how long a closing session waits for its writer to get the rest out
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    Drop, // close the connection, the client can reconnect + replay what it missed
+    Conflate, // newer `update` / `stats` messages replace the queued ones (they carry the full books / points anyway), never a trade
+    Block, // wait for room, which holds up every session behind this one (how it worked before the queues)
+}
+
+impl SlowConsumerPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "drop" => Some(SlowConsumerPolicy::Drop),
+            "conflate" => Some(SlowConsumerPolicy::Conflate),
+            "block" => Some(SlowConsumerPolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutboundConfig {
+    pub capacity: usize,
+    pub policy: SlowConsumerPolicy,
+}
+
+
+pub enum Outgoing {
+    Message(Message),
+    Shared(SharedFrame), // published, the same frame is queued for every session that gets it
+    Flush, // gets out whatever tungstenite queued on its own (pongs, the closing handshake)
+}
+
+struct Queued {
+    outgoing: Outgoing,
+    kind: Option<&'static str>, // None for replies
+    conflatable: bool, // a newer message of the same kind can replace it, see Event::is_conflatable
+    seq: u64,
+    queued_at: Instant,
+}
+
+// how far behind the session is, for the admin
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct OutboundStats {
+    pub queued: usize,
+    pub max_queued: usize,
+    pub oldest_queued_ms: u64, // how long the message at the front has been waiting
+    pub seq_lag: u64, // last seq queued for this session - last seq written to the socket
+    pub last_queued_seq: u64,
+    pub last_sent_seq: u64,
+    pub sent: u64,
+    pub conflated: u64, // replaced by a newer message before they went out
+    pub blocked_ms: u64, // time the publisher spent waiting on this session (block policy)
+    pub last_write_us: u64,
+    pub bandwidth: Bandwidth,
+}
+
+struct OutboxState {
+    queue: VecDeque<Queued>,
+    closed: bool, // nothing more gets queued, the writer stops once the queue is empty
+    stats: OutboundStats,
+}
+
+pub struct Outbox {
+    config: OutboundConfig,
+    state: Mutex<OutboxState>,
+    ready: Notify, // the writer waits on this
+    space: Notify, // a blocked publisher waits on this
+    dropped: Notify, // the session waits on this, fires when it's dropped for being too slow
+}
+
+impl Outbox {
+    pub fn new(config: OutboundConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(OutboxState { queue: VecDeque::new(), closed: false, stats: OutboundStats::default() }),
+            ready: Notify::new(),
+            space: Notify::new(),
+            dropped: Notify::new(),
+        }
+    }
+
+    // a reply to the session itself, Err once the session is closing
+    pub fn reply(&self, outgoing: Outgoing) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err("the session is closing".to_string());
+        }
+        state.queue.push_back(Queued { outgoing, kind: None, conflatable: false, seq: 0, queued_at: Instant::now() });
+        state.stats.max_queued = state.stats.max_queued.max(state.queue.len());
+        drop(state);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    // a published message, Err means the session is gone (or was just dropped) and should come out of the map
+    pub async fn publish(&self, seq: u64, kind: &'static str, conflatable: bool, frame: SharedFrame) -> Result<(), String> {
+        let mut queued = Some(Queued { outgoing: Outgoing::Shared(frame), kind: Some(kind), conflatable, seq, queued_at: Instant::now() });
+        let mut blocked_since = None;
+        loop {
+            let space = self.space.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err("the session is closing".to_string());
+                }
+
+                if state.queue.len() >= self.config.capacity {
+                    match self.config.policy {
+                        SlowConsumerPolicy::Drop => {
+                            state.closed = true;
+                            state.queue.clear();
+                            let frame = CloseFrame { code: CloseCode::Again, reason: "too slow, please reconnect and replay what you missed".into() };
+                            state.queue.push_back(Queued { outgoing: Outgoing::Message(Message::Close(Some(frame))), kind: None, conflatable: false, seq, queued_at: Instant::now() });
+                            drop(state);
+                            self.ready.notify_one();
+                            self.dropped.notify_one();
+                            return Err(format!("outbound queue full ({} messages)", self.config.capacity));
+                        },
+                        SlowConsumerPolicy::Conflate => {
+                            // the oldest queued message of the same kind that can be replaced makes room, if there isn't one it goes
+                            // over the limit (cards being dealt, rounds ending, announcements + trades all have to get there)
+                            let same_kind = state.queue.iter().position(|queued| queued.conflatable && queued.kind == Some(kind));
+                            if let Some(position) = same_kind {
+                                state.queue.remove(position);
+                                state.stats.conflated += 1;
+                            }
+                        },
+                        SlowConsumerPolicy::Block => {
+                            blocked_since.get_or_insert_with(Instant::now);
+                        },
+                    }
+                }
+
+                if state.queue.len() < self.config.capacity || self.config.policy == SlowConsumerPolicy::Conflate {
+                    if let Some(since) = blocked_since {
+                        state.stats.blocked_ms += since.elapsed().as_millis() as u64;
+                    }
+                    state.stats.last_queued_seq = seq;
+                    state.queue.extend(queued.take());
+                    state.stats.max_queued = state.stats.max_queued.max(state.queue.len());
+                    drop(state);
+                    self.ready.notify_one();
+                    return Ok(());
+                }
+            }
+            space.await;
+        }
+    }
+
+    // for the writer, None once the session is closed and everything's been written
+    pub async fn next(&self) -> Option<(Outgoing, u64)> {
+        loop {
+            let ready = self.ready.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(queued) = state.queue.pop_front() {
+                    drop(state);
+                    self.space.notify_one();
+                    return Some((queued.outgoing, queued.seq));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            ready.await;
+        }
+    }
+
+    fn written(&self, seq: u64, write_time: Duration, bandwidth: Bandwidth) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.sent += 1;
+        state.stats.last_sent_seq = state.stats.last_sent_seq.max(seq);
+        state.stats.last_write_us = write_time.as_micros() as u64;
+        state.stats.bandwidth = bandwidth;
+    }
+
+    // nothing else gets queued, the writer finishes what's there and stops
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_one();
+        self.space.notify_one();
+    }
+
+    // resolves once the publisher has dropped the session for falling behind (drop policy)
+    pub async fn dropped(&self) {
+        self.dropped.notified().await
+    }
+
+    pub fn stats(&self) -> OutboundStats {
+        let state = self.state.lock().unwrap();
+        let mut stats = state.stats;
+        stats.queued = state.queue.len();
+        stats.oldest_queued_ms = state.queue.front().map(|queued| queued.queued_at.elapsed().as_millis() as u64).unwrap_or_default();
+        stats.seq_lag = stats.last_queued_seq.saturating_sub(stats.last_sent_seq);
+        stats
+    }
+}
+
+
+// owns the write half of the socket for the whole session
+pub async fn run_writer(mut sender: WsSender, outbox: std::sync::Arc<Outbox>) {
+    while let Some((outgoing, seq)) = outbox.next().await {
+        let start = Instant::now();
+        let result = match outgoing {
+            Outgoing::Message(message) => sender.send(message).await,
+            Outgoing::Shared(frame) => sender.send_shared(&frame).await,
+            Outgoing::Flush => sender.flush().await,
+        };
+        outbox.written(seq, start.elapsed(), sender.bandwidth);
+
+        if let Err(e) = result {
+            println!("{}[!] WS |:| Write failed, closing the outbound queue: {:?}{}", CL::DullRed.get(), e, CL::End.get());
+            outbox.close();
+            break;
+        }
+    }
+}
+
+// gives the writer a moment to get the rest out (a close frame, most likely) then stops it
+pub async fn shutdown_writer(outbox: &Outbox, writer: tokio::task::JoinHandle<()>) {
+    outbox.close();
+    let abort = writer.abort_handle();
+    if tokio::time::timeout(WRITER_SHUTDOWN, writer).await.is_err() {
+        abort.abort();
+    }
+}
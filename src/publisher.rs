@@ -0,0 +1,106 @@
+use super::{Event, SchemaVersion, Subscription, CL};
+use super::websocket::{PlayerWsMap, PlayerConnection};
+use super::multicast::MulticastPublisher;
+use super::link::Replicas;
+use super::frame::SharedFrame;
+use super::matching_engine::SharedReplayBuffer;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use kanal::{AsyncReceiver, AsyncSender};
+
+
+// =-= Publisher =-= //
+
+// The matching engine only stamps a seq on every event and drops it in here, this side (on its own core) renders the messages
+// and does all the socket writes. The queue is unbounded so the engine never waits on it, a slow socket only holds up the publisher
+// Gateways connected over the engine link get every event from here too (and run their own publisher for their sessions)
+// Every event goes into the replay buffer before it's broadcast, so a session's replay can pick up whatever it hasn't been sent yet
+
+pub type EventSender = AsyncSender<(u64, Arc<Event>)>;
+pub type EventReceiver = AsyncReceiver<(u64, Arc<Event>)>;
+
+pub fn event_queue() -> (EventSender, EventReceiver) {
+    kanal::unbounded_async()
+}
+
+
+pub async fn run(
+    receiver: EventReceiver,
+    player_ws_map: PlayerWsMap,
+    mut multicast: Option<MulticastPublisher>,
+    replicas: Arc<Replicas>,
+    replay_buffer: Option<SharedReplayBuffer>, // None on a gateway, the engine link fills its buffer
+    mut shutdown_rx: oneshot::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                break;
+            }
+            result = receiver.recv() => {
+                match result {
+                    Ok((seq, event)) => {
+                        if let Some(replay_buffer) = &replay_buffer {
+                            replay_buffer.lock().push(seq, Arc::clone(&event));
+                        }
+                        replicas.forward(seq, &event).await;
+                        publish(seq, &event, &player_ws_map, &mut multicast).await;
+                    },
+                    Err(e) => {
+                        println!("{}[!] Publisher Receiver Failed: {:?}{}", CL::Red.get(), e, CL::End.get());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+pub async fn publish(seq: u64, event: &Event, player_ws_map: &PlayerWsMap, multicast: &mut Option<MulticastPublisher>) {
+    // the multicast feed goes out first, it's one send for everyone
+    if let (Some(multicast), Event::Update(update)) = (multicast, event) {
+        multicast.publish(seq, update).await;
+    }
+
+    // players with the same version + subscription get the exact same message, so each combination is only serialized once
+    // and everyone in it shares the frame
+    let mut rendered: HashMap<(SchemaVersion, Subscription), Option<SharedFrame>> = HashMap::new();
+    broadcast(seq, event, player_ws_map, |player_name, connection| {
+        match event.is_private() {
+            true => event.render(seq, player_name, connection.version, &connection.subscription).map(SharedFrame::from),
+            false => rendered.entry((connection.version, connection.subscription))
+                .or_insert_with(|| event.render(seq, player_name, connection.version, &connection.subscription).map(SharedFrame::from))
+                .clone(),
+        }
+    }).await;
+}
+
+
+// `render` picks what (if anything) each connection gets
+async fn broadcast<F>(seq: u64, event: &Event, player_ws_map: &PlayerWsMap, mut render: F)
+where
+    F: FnMut(&String, &PlayerConnection) -> Option<SharedFrame>,
+{
+    let mut player_ws_map_guard = player_ws_map.lock().await;
+    let mut removed_sessions = Vec::new();
+    for (session_id, connection) in player_ws_map_guard.iter_mut() {
+        if seq <= connection.from_seq {
+            continue; // already in the snapshot / replay they got when they subscribed
+        }
+        let frame = match render(&connection.player_name, connection) {
+            Some(frame) => frame,
+            None => continue,
+        };
+        let conflatable = event.is_conflatable(&connection.subscription);
+        if connection.sender.send_event(seq, event.kind(), conflatable, frame).await.is_err() {
+            println!("{}[!] Error sending message to player | Deleting from the map. Player must resubscribe{}", CL::Red.get(), CL::End.get());
+            removed_sessions.push(*session_id);
+        }
+    }
+
+    for session_id in removed_sessions {
+        player_ws_map_guard.remove(&session_id);
+    }
+}
@@ -0,0 +1,208 @@
+use super::api_error::ApiError;
+use super::link::{EngineLink, LinkReply, LinkRequest, CHECK_TIMEOUT};
+use actix_web::HttpResponse;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+
+// =-= Token Buckets =-= //
+
+// Every player gets a bucket of `burst` tokens that refills continuously at `refill_per_second`, every request takes its
+// endpoint's weight out of it. Buckets are topped up lazily when they're used so there's no reset loop (and no window to burst across)
+// A gateway charges the engine's buckets over the link, so a player spreading their requests over every gateway still gets one bucket
+
+pub const RATE_LIMIT_BURST: u32 = 20; // tokens, RATE_LIMIT_BURST to override
+pub const RATE_LIMIT_REFILL_PER_SECOND: u32 = 20; // tokens, RATE_LIMIT_REFILL_PER_SECOND to override
+
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Endpoint {
+    Order,
+    Cancel,
+    Inventory,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub burst: u32,
+    pub refill_per_second: u32,
+    pub order_weight: u32,
+    pub cancel_weight: u32, // cheap so a player can always pull their quotes
+    pub inventory_weight: u32, // queries are the expensive ones
+}
+
+impl Default for RateLimitConfig {
+    // 10 orders/s sustained like the old fixed window, 20 cancels/s or 5 inventory queries/s
+    fn default() -> Self {
+        Self { burst: RATE_LIMIT_BURST, refill_per_second: RATE_LIMIT_REFILL_PER_SECOND, order_weight: 2, cancel_weight: 1, inventory_weight: 4 }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn weight(&self, endpoint: Endpoint) -> u32 {
+        match endpoint {
+            Endpoint::Order => self.order_weight,
+            Endpoint::Cancel => self.cancel_weight,
+            Endpoint::Inventory => self.inventory_weight,
+        }
+    }
+
+    // "order=2,cancel=1,inventory=4", endpoints that are left out keep their weight. Nothing changes if any of them is off
+    pub fn set_weights(&mut self, weights: &str) -> Result<(), String> {
+        let mut config = *self;
+        for weight in weights.split(',').map(str::trim).filter(|weight| !weight.is_empty()) {
+            let (endpoint, weight) = match weight.split_once('=').map(|(endpoint, weight)| (endpoint.trim(), weight.trim().parse::<u32>())) {
+                Some((endpoint, Ok(weight))) => (endpoint, weight),
+                _ => return Err(format!("'{}' should look like <endpoint>=<weight>", weight)),
+            };
+            match endpoint {
+                "order" => config.order_weight = weight,
+                "cancel" => config.cancel_weight = weight,
+                "inventory" => config.inventory_weight = weight,
+                _ => return Err(format!("unknown endpoint '{}', expected order, cancel or inventory", endpoint)),
+            }
+        }
+        config.validate()?;
+        *self = config;
+        Ok(())
+    }
+
+    // a weight over the burst (or a bucket that never refills) would turn every request away for good
+    pub fn validate(&self) -> Result<(), String> {
+        if self.burst == 0 {
+            return Err("the burst should be at least 1 token".to_string());
+        }
+        if self.refill_per_second == 0 {
+            return Err("the refill should be at least 1 token/s".to_string());
+        }
+        for (endpoint, weight) in [("order", self.order_weight), ("cancel", self.cancel_weight), ("inventory", self.inventory_weight)] {
+            if weight == 0 || weight > self.burst {
+                return Err(format!("{}={} should be 1-{} (the burst)", endpoint, weight, self.burst));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// what's sent back in the X-RateLimit-* headers
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimitStatus {
+    pub limit: u32, // bucket size
+    pub remaining: u32, // whole tokens left
+    pub reset_ms: u64, // until the bucket is full again
+    pub retry_after_ms: u64, // until this request would've fit, 0 if it went through
+}
+
+impl RateLimitStatus {
+    pub fn apply(&self, mut response: HttpResponse) -> HttpResponse {
+        let headers = response.headers_mut();
+        headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(self.limit));
+        headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(self.remaining));
+        headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(self.reset_ms));
+        if self.retry_after_ms > 0 {
+            headers.insert(HeaderName::from_static("retry-after"), HeaderValue::from(self.retry_after_ms.div_ceil(1000))); // whole seconds
+        }
+        response
+    }
+}
+
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+// also the list of players who're allowed to trade, anyone without a bucket is unknown
+pub struct RateLimiter {
+    pub config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, TokenBucket>>, // playername -> bucket
+    engine: Option<Arc<EngineLink>>, // a gateway's buckets are only the player list, `take` goes to the engine's
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self { config, buckets: Mutex::new(HashMap::new()), engine: None }
+    }
+
+    pub fn link(config: RateLimitConfig, engine: Arc<EngineLink>) -> Self {
+        Self { config, buckets: Mutex::new(HashMap::new()), engine: Some(engine) }
+    }
+
+    // starts the player off w/ a full bucket, false if they already had one
+    pub async fn register(&self, player_name: &str) -> bool {
+        let mut buckets = self.buckets.lock().await;
+        if buckets.contains_key(player_name) {
+            return false;
+        }
+        buckets.insert(player_name.to_string(), TokenBucket { tokens: self.config.burst as f64, last_refill: Instant::now() });
+        true
+    }
+
+    pub async fn remove(&self, player_name: &str) {
+        self.buckets.lock().await.remove(player_name);
+    }
+
+    pub async fn clear(&self) {
+        self.buckets.lock().await.clear();
+    }
+
+    pub async fn players(&self) -> Vec<String> {
+        self.buckets.lock().await.keys().cloned().collect()
+    }
+
+    // exactly these players from now on (a gateway catching up w/ the engine), anyone who's kept keeps their bucket
+    pub async fn replace(&self, player_names: &[String]) {
+        let keep = player_names.iter().collect::<HashSet<&String>>();
+        let mut buckets = self.buckets.lock().await;
+        buckets.retain(|player_name, _| keep.contains(player_name));
+        for player_name in player_names {
+            buckets.entry(player_name.clone()).or_insert_with(|| TokenBucket { tokens: self.config.burst as f64, last_refill: Instant::now() });
+        }
+    }
+
+    pub async fn take(&self, player_name: &str, endpoint: Endpoint) -> Result<RateLimitStatus, ApiError> {
+        if let Some(engine) = &self.engine {
+            return match engine.request(LinkRequest::Take { player_name: player_name.to_string(), endpoint }, CHECK_TIMEOUT).await? {
+                LinkReply::Take(status) => status,
+                _ => Err(ApiError::EngineUnavailable),
+            };
+        }
+
+        let burst = self.config.burst as f64;
+        let refill_per_second = self.config.refill_per_second as f64;
+        let weight = self.config.weight(endpoint) as f64;
+
+        let mut buckets = self.buckets.lock().await;
+        let bucket = match buckets.get_mut(player_name) {
+            Some(bucket) => bucket,
+            None => return Err(ApiError::UnknownPlayer),
+        };
+
+        let now = Instant::now();
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * refill_per_second).min(burst);
+        bucket.last_refill = now;
+
+        let allowed = bucket.tokens >= weight;
+        if allowed {
+            bucket.tokens -= weight;
+        }
+
+        let ms_until = |tokens: f64| ((tokens - bucket.tokens).max(0.0) / refill_per_second * 1000.0).ceil() as u64;
+        let status = RateLimitStatus {
+            limit: self.config.burst,
+            remaining: bucket.tokens as u32,
+            reset_ms: ms_until(burst),
+            retry_after_ms: if allowed { 0 } else { ms_until(weight) },
+        };
+
+        match allowed {
+            true => Ok(status),
+            false => Err(ApiError::RateLimit(status)),
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+
+// =-= Ring Buffers =-= //
+
+// Fixed size lock-free queues for getting orders from the gateway to the engine without kanal's locks (and a preallocated slot
+// for the engine's answer instead of a oneshot per order). Every slot in the ring carries a sequence number that says whose turn
+// it is: `index` means a producer can write it, `index + 1` means the consumer can read it, so producers and the consumer never
+// touch the same counter. Several producers take turns on the tail w/ a CAS, a lone producer (`spsc`) skips the CAS altogether
+// There's only ever one consumer (the hotpath). It either parks on a Notify until something's pushed, or busy-spins and
+// producers don't bother waking it
+
+#[repr(align(64))] // own cache line, so the producers bumping the tail don't keep knocking the consumer's head out of cache
+struct CachePadded<T>(T);
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Ring<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    head: CachePadded<AtomicUsize>, // next to read
+    tail: CachePadded<AtomicUsize>, // next to write
+    spin: bool, // the consumer busy-spins, nobody needs waking up
+    notify: Notify,
+}
+
+// a slot's value is only ever touched by whoever its sequence number hands it to
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn new(capacity: usize, spin: bool) -> Self {
+        let capacity = capacity.max(2).next_power_of_two(); // so the index is a mask instead of a division
+        Self {
+            slots: (0..capacity).map(|index| Slot { sequence: AtomicUsize::new(index), value: UnsafeCell::new(MaybeUninit::uninit()) }).collect(),
+            mask: capacity - 1,
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            spin,
+            notify: Notify::new(),
+        }
+    }
+
+    // `exclusive` = the caller is the only one pushing, so there's nobody to race for the tail
+    fn push(&self, value: T, exclusive: bool) -> Result<(), T> {
+        let mut tail = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[tail & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence == tail {
+                if exclusive {
+                    self.tail.0.store(tail + 1, Ordering::Relaxed);
+                } else if let Err(current) = self.tail.0.compare_exchange_weak(tail, tail + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    tail = current; // another producer got it first
+                    continue;
+                }
+                unsafe { (*slot.value.get()).write(value) };
+                slot.sequence.store(tail + 1, Ordering::Release);
+                if !self.spin {
+                    self.notify.notify_one();
+                }
+                return Ok(());
+            }
+            if sequence < tail {
+                return Err(value); // still holds what was pushed a lap ago, full
+            }
+            tail = self.tail.0.load(Ordering::Relaxed); // someone pushed after we loaded the tail
+        }
+    }
+
+    fn pop(&self, exclusive: bool) -> Option<T> {
+        let mut head = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[head & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence == head + 1 {
+                if exclusive {
+                    self.head.0.store(head + 1, Ordering::Relaxed);
+                } else if let Err(current) = self.head.0.compare_exchange_weak(head, head + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    head = current;
+                    continue;
+                }
+                let value = unsafe { (*slot.value.get()).assume_init_read() };
+                slot.sequence.store(head + self.slots.len(), Ordering::Release); // free for the producer one lap ahead
+                return Some(value);
+            }
+            if sequence < head + 1 {
+                return None; // nothing's been pushed here yet, empty
+            }
+            head = self.head.0.load(Ordering::Relaxed);
+        }
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.0.load(Ordering::Relaxed);
+        self.tail.0.load(Ordering::Relaxed).saturating_sub(head)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        while self.pop(true).is_some() {}
+    }
+}
+
+
+// =-= Producers + Consumer =-= //
+
+// any number of producers, one consumer
+pub fn mpsc<T>(capacity: usize, spin: bool) -> (Producer<T>, Consumer<T>) {
+    let ring = Arc::new(Ring::new(capacity, spin));
+    (Producer { ring: Arc::clone(&ring) }, Consumer { ring })
+}
+
+// one producer, one consumer. Can't be cloned, so the tail can't be raced for
+pub fn spsc<T>(capacity: usize, spin: bool) -> (SpscProducer<T>, Consumer<T>) {
+    let ring = Arc::new(Ring::new(capacity, spin));
+    (SpscProducer { ring: Arc::clone(&ring) }, Consumer { ring })
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        Self { ring: Arc::clone(&self.ring) }
+    }
+}
+
+impl<T> Producer<T> {
+    // never waits, hands the value back if the ring is full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.ring.push(value, false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+}
+
+pub struct SpscProducer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> SpscProducer<T> {
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        self.ring.push(value, true)
+    }
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        self.ring.pop(true)
+    }
+
+    // parks until something's pushed. Nothing is taken off the ring unless it's returned, so it's fine to drop this in a select!
+    // Only for a ring that isn't spinning, producers don't wake anyone up otherwise
+    pub async fn recv(&mut self) -> T {
+        loop {
+            if let Some(value) = self.ring.pop(true) {
+                return value;
+            }
+            let notified = self.ring.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable(); // registered before looking again, so a push in between still wakes us
+            if let Some(value) = self.ring.pop(true) {
+                return value;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn is_spinning(&self) -> bool {
+        self.ring.spin
+    }
+}
+
+
+// =-= Response Slots =-= //
+
+// A fixed pool of slots for the engine's answers, handed out per order and given back once both sides are done w/ it (instead
+// of allocating a oneshot per order). Whoever's waiting can give up (the ENGINE_TIMEOUT), which the engine can check before it
+// bothers w/ the order, the same as a closed oneshot
+
+const FREE: u8 = 0;
+const WAITING: u8 = 1; // the engine hasn't answered yet
+const READY: u8 = 2; // answered, the value's in the slot
+const ABANDONED: u8 = 3; // the waiter gave up
+const DROPPED: u8 = 4; // the engine let go without answering
+
+struct ResponseSlot<T> {
+    state: AtomicU8,
+    value: UnsafeCell<Option<T>>,
+    notify: Notify,
+}
+
+pub struct ResponseSlots<T> {
+    slots: Box<[ResponseSlot<T>]>,
+    free: Ring<usize>, // indexes into `slots`, taken + given back from any thread
+}
+
+// same as the ring, the state says who owns the value
+unsafe impl<T: Send> Send for ResponseSlot<T> {}
+unsafe impl<T: Send> Sync for ResponseSlot<T> {}
+
+impl<T> ResponseSlots<T> {
+    pub fn new(count: usize) -> Arc<Self> {
+        let count = count.max(1);
+        let free = Ring::new(count, true);
+        for index in 0..count {
+            let _ = free.push(index, false);
+        }
+        Arc::new(Self {
+            slots: (0..count).map(|_| ResponseSlot { state: AtomicU8::new(FREE), value: UnsafeCell::new(None), notify: Notify::new() }).collect(),
+            free,
+        })
+    }
+
+    // None when every slot is out, i.e. that many orders are already waiting on the engine
+    pub fn acquire(self: &Arc<Self>) -> Option<(ResponseWaiter<T>, Responder<T>)> {
+        let index = self.free.pop(false)?;
+        self.slots[index].state.store(WAITING, Ordering::Relaxed);
+        Some((
+            ResponseWaiter { slots: Arc::clone(self), index },
+            Responder { slots: Arc::clone(self), index, answered: false },
+        ))
+    }
+
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    // only once neither side will look at the slot again
+    fn release(&self, index: usize) {
+        let slot = &self.slots[index];
+        unsafe { *slot.value.get() = None };
+        slot.state.store(FREE, Ordering::Release);
+        let _ = self.free.push(index, false); // never more indexes than room for them
+    }
+}
+
+// the engine's end
+pub struct Responder<T> {
+    slots: Arc<ResponseSlots<T>>,
+    index: usize,
+    answered: bool,
+}
+
+impl<T> Responder<T> {
+    pub fn is_closed(&self) -> bool {
+        self.slots.slots[self.index].state.load(Ordering::Acquire) == ABANDONED
+    }
+
+    // hands the value back if the waiter already gave up
+    pub fn send(mut self, value: T) -> Result<(), T> {
+        self.answered = true;
+        let slot = &self.slots.slots[self.index];
+        unsafe { *slot.value.get() = Some(value) };
+        match slot.state.compare_exchange(WAITING, READY, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                slot.notify.notify_one();
+                Ok(())
+            },
+            Err(_) => {
+                let value = unsafe { (*slot.value.get()).take() };
+                self.slots.release(self.index);
+                Err(value.expect("the value was just put there"))
+            }
+        }
+    }
+}
+
+impl<T> Drop for Responder<T> {
+    fn drop(&mut self) {
+        if self.answered {
+            return;
+        }
+        let slot = &self.slots.slots[self.index];
+        match slot.state.compare_exchange(WAITING, DROPPED, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => slot.notify.notify_one(),
+            Err(_) => self.slots.release(self.index), // abandoned, nobody else is coming back for it
+        }
+    }
+}
+
+// the end whoever submitted the order waits on
+pub struct ResponseWaiter<T> {
+    slots: Arc<ResponseSlots<T>>,
+    index: usize,
+}
+
+impl<T> ResponseWaiter<T> {
+    // None if the engine dropped its end without answering. Fine to drop halfway through (e.g. a timeout)
+    pub async fn recv(&mut self) -> Option<T> {
+        let slot = &self.slots.slots[self.index];
+        loop {
+            let notified = slot.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            match slot.state.load(Ordering::Acquire) {
+                READY => return unsafe { (*slot.value.get()).take() },
+                DROPPED => return None,
+                _ => notified.await, // can be a leftover wake up from the slot's last order, the loop looks again
+            }
+        }
+    }
+}
+
+impl<T> Drop for ResponseWaiter<T> {
+    fn drop(&mut self) {
+        let slot = &self.slots.slots[self.index];
+        // still waiting: leave it to the engine to give the slot back once it sees this
+        if slot.state.compare_exchange(WAITING, ABANDONED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            self.slots.release(self.index);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn producers_keep_their_own_order() {
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 10_000;
+        let (producer, mut consumer) = mpsc::<(usize, usize)>(64, true); // a lot smaller than what goes through, so it fills up
+        let handles = (0..PRODUCERS).map(|id| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    let mut value = (id, i);
+                    while let Err(back) = producer.push(value) {
+                        value = back;
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let mut next = [0; PRODUCERS];
+        let mut received = 0;
+        while received < PRODUCERS * PER_PRODUCER {
+            match consumer.pop() {
+                Some((id, i)) => {
+                    assert_eq!(i, next[id], "producer {} out of order", id);
+                    next[id] += 1;
+                    received += 1;
+                },
+                None => thread::yield_now(),
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(consumer.pop().is_none());
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn full_ring_hands_the_value_back() {
+        let (producer, mut consumer) = mpsc::<usize>(3, true); // rounded up to 4
+        assert_eq!(producer.capacity(), 4);
+        for value in 0..4 {
+            assert!(producer.push(value).is_ok());
+        }
+        assert_eq!(producer.push(4), Err(4));
+        assert_eq!(producer.len(), 4);
+
+        assert_eq!(consumer.pop(), Some(0));
+        assert!(producer.push(4).is_ok()); // the freed slot, a lap later
+        assert_eq!(std::iter::from_fn(|| consumer.pop()).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert!(consumer.pop().is_none());
+    }
+
+    #[test]
+    fn spsc_wraps_around() {
+        let (mut producer, mut consumer) = spsc::<usize>(2, true);
+        for value in 0..10 {
+            assert!(producer.push(value).is_ok());
+            assert_eq!(consumer.pop(), Some(value));
+        }
+    }
+
+    #[test]
+    fn values_left_in_the_ring_are_dropped() {
+        let value = Arc::new(());
+        let (producer, consumer) = mpsc::<Arc<()>>(4, true);
+        producer.push(Arc::clone(&value)).unwrap();
+        producer.push(Arc::clone(&value)).unwrap();
+        drop(producer);
+        drop(consumer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[tokio::test]
+    async fn recv_wakes_up_on_a_push() {
+        let (producer, mut consumer) = mpsc::<usize>(4, false);
+        let pushing = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(20));
+            producer.push(7).unwrap();
+        });
+        assert_eq!(consumer.recv().await, 7);
+        pushing.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn answered_slot_goes_back_to_the_pool() {
+        let slots = ResponseSlots::<usize>::new(1);
+        let (mut waiter, responder) = slots.acquire().unwrap();
+        assert!(slots.acquire().is_none());
+        assert!(!responder.is_closed());
+        assert!(responder.send(5).is_ok());
+        assert_eq!(waiter.recv().await, Some(5));
+        drop(waiter);
+        assert_eq!(slots.available(), 1);
+    }
+
+    #[test]
+    fn responder_sees_an_abandoned_waiter() {
+        let slots = ResponseSlots::<usize>::new(1);
+        let (waiter, responder) = slots.acquire().unwrap();
+        drop(waiter); // gave up before the engine answered
+        assert!(responder.is_closed());
+        assert_eq!(slots.available(), 0, "the engine still holds it");
+        assert_eq!(responder.send(5), Err(5));
+        assert_eq!(slots.available(), 1);
+
+        // abandoned + then dropped without an answer gives it back too
+        let (waiter, responder) = slots.acquire().unwrap();
+        drop(waiter);
+        drop(responder);
+        assert_eq!(slots.available(), 1);
+    }
+
+    #[tokio::test]
+    async fn slot_is_reused_after_the_engine_drops_it() {
+        let slots = ResponseSlots::<usize>::new(1);
+        let (mut waiter, responder) = slots.acquire().unwrap();
+        drop(responder); // let go without answering
+        assert_eq!(waiter.recv().await, None);
+        drop(waiter);
+        assert_eq!(slots.available(), 1);
+
+        // the same slot, w/ nothing left over from the last order
+        let (mut waiter, responder) = slots.acquire().unwrap();
+        assert!(!responder.is_closed());
+        assert!(responder.send(9).is_ok());
+        assert_eq!(waiter.recv().await, Some(9));
+    }
+}
@@ -0,0 +1,180 @@
+use super::{Order, HTTPResponse};
+use super::order_queue::ResponseSender;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+// =-= Sequencer =-= //
+
+// The order two orders reach the engine's queue in is up to actix's workers, the locks on the way there and (ROLE=gateway) which
+// gateway's link gets there first, not which one came in first. So every order is stamped w/ the time the gateway first saw it
+// (the REST API's middleware before the body's read, the websocket + unix socket as soon as the frame's off the socket) and w/
+// SEQUENCER_WINDOW_US set the hotpath holds each order until it's that old and lets them go in stamp order. Orders stamped less
+// than the window apart are matched in the order they came in, at the cost of every order waiting out the window
+// Off by default (orders are matched in the order they reach the queue). Either way every order the engine gets to is given the
+// next sequence number, and the client gets the stamp + the sequence back
+
+pub const SEQUENCER_WINDOW: Duration = Duration::ZERO; // SEQUENCER_WINDOW_US to turn it on (e.g. 200)
+
+// unix ns, so stamps from gateways in different processes can be compared (their clocks have to agree to within the window)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Received(pub u64);
+
+impl Received {
+    pub fn now() -> Self {
+        Received(unix_ns())
+    }
+
+    // the stamp the REST API's middleware put on the request
+    pub fn of(req: &HttpRequest) -> Self {
+        req.extensions().get::<Received>().copied().unwrap_or_else(Received::now)
+    }
+
+    // X-Received-Ns on every /order + /cancel response, X-Order-Sequence once the engine got to the order
+    pub fn apply(&self, sequence: Option<u64>, mut response: HttpResponse) -> HttpResponse {
+        let headers = response.headers_mut();
+        headers.insert(HeaderName::from_static("x-received-ns"), HeaderValue::from(self.0));
+        if let Some(sequence) = sequence {
+            headers.insert(HeaderName::from_static("x-order-sequence"), HeaderValue::from(sequence));
+        }
+        response
+    }
+}
+
+fn unix_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos() as u64).unwrap_or(0)
+}
+
+// the engine's answer to an order + the sequence number it was matched under
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderAnswer {
+    pub response: HTTPResponse,
+    pub sequence: u64,
+}
+
+
+struct Held {
+    received: Received,
+    arrival: u64, // ties go to whichever reached the queue first
+    release_at: u64, // unix ns
+    order: Order,
+    response_sender: ResponseSender,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        (self.received, self.arrival) == (other.received, other.arrival)
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.received, self.arrival).cmp(&(other.received, other.arrival))
+    }
+}
+
+// lives on the hotpath, between the queue and the engine
+pub struct Sequencer {
+    window: u64, // ns, 0 lets every order straight through
+    held: BinaryHeap<Reverse<Held>>,
+    arrivals: u64,
+    released: Received, // the newest stamp let go so far
+    next_sequence: u64,
+    out_of_order: Arc<AtomicU64>, // orders let go after one stamped later than them, shared w/ the OrderQueue's stats
+}
+
+impl Sequencer {
+    pub fn new(window: Duration, out_of_order: Arc<AtomicU64>) -> Self {
+        Self {
+            window: window.as_nanos() as u64,
+            held: BinaryHeap::new(),
+            arrivals: 0,
+            released: Received(0),
+            next_sequence: 1,
+            out_of_order,
+        }
+    }
+
+    pub fn hold(&mut self, order: Order, received: Received, response_sender: ResponseSender) {
+        // a stamp from the future (a gateway's clock is ahead) still only waits out the window from when it got here
+        let release_at = match self.window {
+            0 => 0,
+            window => received.0.min(unix_ns()) + window,
+        };
+        self.arrivals += 1;
+        self.held.push(Reverse(Held { received, arrival: self.arrivals, release_at, order, response_sender }));
+    }
+
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    // the oldest held order if it's waited out the window
+    pub fn release(&mut self) -> Option<(Order, ResponseSender)> {
+        let Reverse(next) = self.held.peek()?;
+        if self.window > 0 && next.release_at > unix_ns() {
+            return None;
+        }
+        self.release_now()
+    }
+
+    // the oldest held order whether it's waited out the window or not, for draining everything ahead of a task
+    pub fn release_now(&mut self) -> Option<(Order, ResponseSender)> {
+        let Reverse(held) = self.held.pop()?;
+        if held.received < self.released {
+            self.out_of_order.fetch_add(1, Ordering::Relaxed); // it got here more than a window late, SEQUENCER_WINDOW_US might be too small
+        } else {
+            self.released = held.received;
+        }
+        Some((held.order, held.response_sender))
+    }
+
+    // for an order the engine's about to match
+    pub fn next_sequence(&mut self) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        sequence
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Card, Direction, PlayerId};
+    use tokio::sync::oneshot;
+
+    fn order(price: usize) -> (Order, ResponseSender) {
+        let (sender, _) = oneshot::channel();
+        (Order { player: PlayerId::intern("SequencerTest"), card: Card::Spade, direction: Direction::Buy, price: Some(price) }, ResponseSender::Oneshot(sender))
+    }
+
+    #[test]
+    fn release_now_drains_in_stamp_order() {
+        let mut sequencer = Sequencer::new(Duration::from_secs(60), Arc::new(AtomicU64::new(0)));
+        let now = unix_ns();
+        let (later, sender) = order(2);
+        sequencer.hold(later, Received(now + 1_000), sender);
+        let (earlier, sender) = order(1);
+        sequencer.hold(earlier, Received(now), sender);
+
+        assert!(sequencer.release().is_none(), "both are still inside the window");
+        let prices = std::iter::from_fn(|| sequencer.release_now()).map(|(order, _)| order.price).collect::<Vec<_>>();
+        assert_eq!(prices, vec![Some(1), Some(2)]);
+        assert!(!sequencer.is_holding());
+    }
+}
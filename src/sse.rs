@@ -0,0 +1,112 @@
+use super::{SchemaVersion, Subscription, CL, build_message};
+use super::api_error::{ApiError, respond};
+use super::auth::{Auth, Identity};
+use super::matching_engine::{SharedReplayBuffer, SharedView};
+use super::frame::SharedFrame;
+use super::websocket::{PlayerWsMap, PlayerConnection, Transport, next_session_id, PING_INTERVAL};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+
+
+// room for a full replay on connect plus some slack, a client that falls this far behind gets dropped
+const SSE_BUFFER_SIZE: usize = 4096;
+
+
+// =-= Server-Sent Events =-= //
+
+// same `update` / `dealing_cards` / `end_round` / `end_game` messages as the websocket (v1, everything but stats)
+// `playerid` header (or a signed request) is optional, without it you just don't get the private `dealing_cards` events
+// `Last-Event-ID` (sent automatically by EventSource on reconnect) resumes from the replay buffer, otherwise the stream starts w/ a snapshot
+#[get("/stream")]
+async fn stream_handler(
+    req: HttpRequest,
+    engine_view: web::Data<SharedView>,
+    replay_buffer: web::Data<SharedReplayBuffer>,
+    playerid_playername_map: web::Data<Arc<RwLock<HashMap<String, String>>>>,
+    player_ws_map: web::Data<PlayerWsMap>,
+    max_sessions_per_player: web::Data<usize>,
+    auth: web::Data<Arc<Auth>>,
+) -> impl Responder {
+    let headers = req.headers();
+
+    let player_name = match auth.authenticate_http(&req, &[]).await {
+        Ok(Some(Identity::Player(player_name))) => Some(player_name),
+        Ok(Some(Identity::Admin)) => None, // public events only
+        Ok(None) => match headers.get("playerid").map(|player_id| player_id.to_str()) {
+            Some(Ok(player_id)) => match playerid_playername_map.read().await.get(player_id) {
+                Some(player_name) => Some(player_name.clone()),
+                None => return respond(&req, Err(ApiError::UnknownPlayer)),
+            },
+            Some(Err(_)) => return respond(&req, Err(ApiError::ParseError("Failed to read the 'playerid' header".to_string()))),
+            None => None, // public events only
+        },
+        Err(error) => return respond(&req, Err(error)),
+    };
+
+    let last_event_id = headers.get("Last-Event-ID")
+        .and_then(|last_event_id| last_event_id.to_str().ok())
+        .and_then(|last_event_id| last_event_id.parse::<u64>().ok());
+
+    let session_id = next_session_id();
+    let (sender, receiver) = mpsc::channel::<String>(SSE_BUFFER_SIZE);
+    let mut connection = PlayerConnection::new(player_name.clone().unwrap_or_default(), Transport::Sse(sender), SchemaVersion::V1, Subscription::legacy());
+
+    // Last-Event-ID is the last seq the client got, so the replay starts right after it. It's rendered before the player_ws_map is
+    // locked, whatever's published in the meantime is added on once it is (see the websocket's replay)
+    let replay = last_event_id.and_then(|last_event_id| {
+        replay_buffer.replay(last_event_id + 1, &connection.player_name, SchemaVersion::V1, &connection.subscription).ok()
+    });
+
+    // same as the websocket subscribe: hold the player_ws_map so nothing is broadcast between the snapshot / replay and registering
+    let mut player_ws_map_guard = player_ws_map.lock().await;
+
+    if let Some(player_name) = &player_name {
+        let open_sessions = player_ws_map_guard.values().filter(|connection| &connection.player_name == player_name).count();
+        if open_sessions >= **max_sessions_per_player {
+            println!("{}[!] SSE |:| Session limit reached for {:?} | {} open{}", CL::Orange.get(), player_name, open_sessions, CL::End.get());
+            return respond(&req, Err(ApiError::TooManySessions(format!("{} already has {} open sessions (the limit is {}), please close one before opening another", player_name, open_sessions, **max_sessions_per_player))));
+        }
+    }
+
+    let replay = replay.and_then(|(mut messages, last_seq)| {
+        let (newer, last_seq) = replay_buffer.replay(last_seq + 1, &connection.player_name, SchemaVersion::V1, &connection.subscription).ok()?;
+        messages.extend(newer);
+        Some((messages, last_seq))
+    });
+    let (initial_messages, last_seq) = match replay {
+        Some((messages, seq)) => (messages, seq),
+        None => {
+            let snapshot = engine_view.load().snapshot(&connection.player_name);
+            (vec![(snapshot.seq, "snapshot", build_message("snapshot", &snapshot, SchemaVersion::V1, snapshot.seq))], snapshot.seq)
+        }
+    };
+    for (seq, kind, message) in initial_messages {
+        if let Err(e) = connection.sender.send_event(seq, kind, false, SharedFrame::from(message)).await {
+            println!("{}[!] SSE |:| Failed to queue the initial messages: {}{}", CL::Red.get(), e, CL::End.get());
+            return respond(&req, Err(ApiError::ReplayUnavailable("Too many messages to replay, please reconnect without 'Last-Event-ID' for a fresh snapshot".to_string())));
+        }
+    }
+
+    connection.from_seq = last_seq; // the snapshot / replay goes up to here
+    player_ws_map_guard.insert(session_id, connection);
+    drop(player_ws_map_guard);
+    println!("{}[+] SSE |:| Stream opened: {:?} | session {} | Last-Event-ID: {:?}{}", CL::DullTeal.get(), player_name, session_id, last_event_id, CL::End.get());
+
+    // the publisher drops the session the next time it fails to push to it (i.e. after the client goes away and this stream is dropped)
+    // comments are ignored by clients, they just keep proxies from timing out the connection between rounds
+    let keep_alive = tokio::time::interval(PING_INTERVAL);
+    let body = futures_util::stream::unfold((receiver, keep_alive), |(mut receiver, mut keep_alive)| async move {
+        let chunk = tokio::select! {
+            message = receiver.recv() => message?,
+            _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+        };
+        Some((Ok::<_, actix_web::Error>(web::Bytes::from(chunk)), (receiver, keep_alive)))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
@@ -0,0 +1,56 @@
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+
+// Same config is used for https (REST / SSE) and wss, pass a client CA to require client certificates signed by it
+pub fn load_server_config(cert_path: &str, key_path: &str, client_ca_path: Option<&str>) -> Result<ServerConfig, String> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up the TLS protocol versions: {:?}", e))?;
+
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).map_err(|e| format!("Invalid client CA certificate in {}: {:?}", client_ca_path, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| format!("Failed to build the client certificate verifier: {:?}", e))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+
+    builder.with_single_cert(certs, key).map_err(|e| format!("Invalid certificate / key pair: {:?}", e))
+}
+
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {:?}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read the certificates in {}: {:?}", path, e))?;
+
+    match certs.is_empty() {
+        true => Err(format!("No certificates found in {}", path)),
+        false => Ok(certs),
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {:?}", path, e))?;
+    match rustls_pemfile::private_key(&mut BufReader::new(file)) {
+        Ok(Some(key)) => Ok(key),
+        Ok(None) => Err(format!("No private key found in {}", path)),
+        Err(e) => Err(format!("Failed to read the private key in {}: {:?}", path, e)),
+    }
+}
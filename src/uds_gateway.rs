@@ -0,0 +1,267 @@
+use super::{SchemaVersion, GatewayRequest, GatewayResponse, HTTPResponse, CL, build_message};
+use super::matching_engine::SharedView;
+use super::auth::{Auth, Identity, SignedRequest};
+use super::order_entry::OrderEntry;
+use super::sequencer::Received;
+use super::frame::SharedFrame;
+use super::websocket::{PlayerWsMap, PlayerConnection, Transport, next_session_id, parse_channels, parse_subscription};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, oneshot, RwLock};
+
+
+// Frames in both directions are a u32 (big endian) length followed by that many bytes of JSON
+// client -> gateway: `GatewayRequest`, gateway -> client: `GatewayResponse` or the same market data messages the websocket sends
+
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+const UDS_BUFFER_SIZE: usize = 4096; // outbound frames per connection, a client that falls this far behind gets dropped from market data
+
+
+#[derive(Clone)]
+pub struct GatewayState {
+    pub playerid_playername_map: Arc<RwLock<HashMap<String, String>>>,
+    pub order_entry: OrderEntry,
+    pub engine_view: SharedView,
+    pub player_ws_map: PlayerWsMap,
+    pub max_sessions: usize,
+    pub auth: Arc<Auth>,
+}
+
+
+pub async fn run(path: String, state: GatewayState, mut shutdown: oneshot::Receiver<()>) {
+    let _ = std::fs::remove_file(&path); // left over from a previous run
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}[!] UDS |:| Failed to bind {}: {:?}{}", CL::Red.get(), path, e, CL::End.get());
+            return;
+        }
+    };
+    println!("{}[+] UDS |:| Gateway listening on {}{}", CL::Green.get(), path, CL::End.get());
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                break;
+            }
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_connection(stream, state.clone()));
+                    },
+                    Err(e) => println!("{}[!] UDS |:| Error accepting a connection: {:?}{}", CL::Red.get(), e, CL::End.get()),
+                }
+            }
+        }
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
+
+
+async fn handle_connection(stream: UnixStream, state: GatewayState) {
+    let session_id = next_session_id();
+    println!("{}[+] UDS |:| Connection established | session {}{}", CL::Green.get(), session_id, CL::End.get());
+
+    // responses and market data both go through the writer task so frames never interleave
+    let (mut reader, mut writer) = stream.into_split();
+    let (sender, mut receiver) = mpsc::channel::<SharedFrame>(UDS_BUFFER_SIZE);
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = write_frame(&mut writer, message.as_str()).await {
+                println!("{}[!] UDS |:| Failed to write a frame: {:?}{}", CL::DullRed.get(), e, CL::End.get());
+                break;
+            }
+        }
+    });
+
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                println!("{}[!] UDS |:| Closing session {}: {:?}{}", CL::DullRed.get(), session_id, e, CL::End.get());
+                break;
+            }
+        };
+        let received = Received::now(); // an order's stamp for the sequencer
+
+        let response = match serde_json::from_slice::<GatewayRequest>(&frame) {
+            Ok(request) => handle_request(request, received, session_id, &sender, &state).await,
+            Err(_) => Some(GatewayResponse {
+                status: "PARSE_ERROR".to_string(),
+                message: "Failed to parse the frame. Please send a JSON `GatewayRequest` with 'action' and 'playerid'".to_string(),
+                engine_latency_ns: None,
+                received_ns: None,
+                sequence: None,
+            }),
+        };
+
+        if let Some(response) = response {
+            let response = serde_json::to_string(&response).unwrap_or_default();
+            if sender.send(SharedFrame::from(response)).await.is_err() {
+                break; // writer is gone
+            }
+        }
+    }
+
+    // once the map's copy of the sender is gone the writer drains whatever's left and exits
+    state.player_ws_map.lock().await.remove(&session_id);
+    drop(sender);
+    let _ = writer_task.await;
+    println!("{}[-] UDS |:| Session ended | session {}{}", CL::Dull.get(), session_id, CL::End.get());
+}
+
+
+async fn read_frame(reader: &mut OwnedReadHalf) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None), // clean disconnect between frames
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is over the {} byte limit", length, MAX_FRAME_SIZE)));
+    }
+
+    let mut frame = vec![0u8; length];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+async fn write_frame(writer: &mut OwnedWriteHalf, message: &str) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + message.len());
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message.as_bytes());
+    writer.write_all(&frame).await
+}
+
+
+// None when the response was already queued (subscribe sends it before the snapshot)
+async fn handle_request(request: GatewayRequest, received: Received, session_id: u64, sender: &mpsc::Sender<SharedFrame>, state: &GatewayState) -> Option<GatewayResponse> {
+    // every request is signed on its own ("UDS" as the method, the action as the path and `card,price,direction` as the body
+    // so a signature can't be lifted onto a different order), or carries the old `playerid`
+    let signed_request = SignedRequest::from_fields(&request.api_key, request.timestamp, &request.nonce, &request.signature);
+    let signed_body = format!("{},{},{}", request.card.as_deref().unwrap_or_default(), request.price.map(|price| price.to_string()).unwrap_or_default(), request.direction.as_deref().unwrap_or_default());
+    let player_name = match state.auth.authenticate(signed_request, "UDS", &request.action, signed_body.as_bytes()).await {
+        Ok(Some(Identity::Player(player_name))) => Some(player_name),
+        Ok(Some(Identity::Admin)) => None,
+        Ok(None) => state.playerid_playername_map.read().await.get(&request.playerid).cloned(),
+        Err(error) => return Some(HTTPResponse::from(error).into()),
+    };
+    let player_name = match player_name {
+        Some(player_name) => player_name,
+        None => return Some(GatewayResponse {
+            status: "UNKNOWN_PLAYER".to_string(),
+            message: "Player name not found. Have you sent a post to /register_testnet?".to_string(),
+            engine_latency_ns: None,
+            received_ns: None,
+            sequence: None,
+        }),
+    };
+
+    match request.action.as_str() {
+        "subscribe" => subscribe(&request, player_name, session_id, sender, state).await,
+        "unsubscribe" => Some(unsubscribe(&request, session_id, state).await),
+        "order" | "cancel" => Some(state.order_entry.submit(player_name, &request.action, request.card.as_deref(), request.price, request.direction.as_deref(), received).await),
+        _ => Some(GatewayResponse {
+            status: "UNAUTHORIZED_ACTION".to_string(),
+            message: "Unauthorized action, please send 'subscribe', 'unsubscribe', 'order' or 'cancel' as the action".to_string(),
+            engine_latency_ns: None,
+            received_ns: None,
+            sequence: None,
+        }),
+    }
+}
+
+
+async fn subscribe(request: &GatewayRequest, player_name: String, session_id: u64, sender: &mpsc::Sender<SharedFrame>, state: &GatewayState) -> Option<GatewayResponse> {
+    let version = match SchemaVersion::from_request(request.version) {
+        Some(version) => version,
+        None => return Some(GatewayResponse {
+            status: "UNSUPPORTED_VERSION".to_string(),
+            message: "Unsupported schema version, please send 'version' as 1 or 2 (or leave it out for 1)".to_string(),
+            engine_latency_ns: None,
+            received_ns: None,
+            sequence: None,
+        }),
+    };
+    let subscription = match parse_subscription(&request.channels, &request.depth) {
+        Ok(subscription) => subscription,
+        Err(response) => return Some(response.into()),
+    };
+
+    // same as the websocket: holding the player_ws_map keeps the snapshot's seq in line with the first update queued after it
+    let mut player_ws_map_guard = state.player_ws_map.lock().await;
+
+    let open_sessions = player_ws_map_guard.iter()
+        .filter(|(id, connection)| **id != session_id && connection.player_name == player_name)
+        .count();
+    if open_sessions >= state.max_sessions {
+        return Some(GatewayResponse {
+            status: "TOO_MANY_SESSIONS".to_string(),
+            message: format!("{} already has {} open sessions (the limit is {}), please close one before subscribing on another", player_name, open_sessions, state.max_sessions),
+            engine_latency_ns: None,
+            received_ns: None,
+            sequence: None,
+        });
+    }
+
+    let welcome = GatewayResponse {
+        status: "SUCCESS".to_string(),
+        message: format!("Welcome to the tesetnet, {}! You've been subscribed for further data updates", player_name),
+        engine_latency_ns: None,
+        received_ns: None,
+        sequence: None,
+    };
+    let snapshot = state.engine_view.load().snapshot(&player_name);
+    for message in [serde_json::to_string(&welcome).unwrap_or_default(), build_message("snapshot", &snapshot, version, snapshot.seq)] {
+        if sender.try_send(SharedFrame::from(message)).is_err() {
+            return None; // backed up or gone, the read loop will find out
+        }
+    }
+
+    println!("{}[+] UDS |:| Successfully subscribed to the stream: {:?} | session {}{}", CL::DullTeal.get(), player_name, session_id, CL::End.get());
+    let mut connection = PlayerConnection::new(player_name, Transport::Uds(sender.clone()), version, subscription);
+    connection.from_seq = snapshot.seq;
+    player_ws_map_guard.insert(session_id, connection);
+    None
+}
+
+
+async fn unsubscribe(request: &GatewayRequest, session_id: u64, state: &GatewayState) -> GatewayResponse {
+    let channels = match &request.channels {
+        Some(channels) => match parse_channels(channels) {
+            Ok(channels) => Some(channels),
+            Err(response) => return response.into(),
+        },
+        None => None,
+    };
+
+    let mut player_ws_map_guard = state.player_ws_map.lock().await;
+    let remove_connection = match (player_ws_map_guard.get_mut(&session_id), &channels) {
+        (Some(connection), Some(channels)) => {
+            connection.subscription.remove(channels);
+            connection.subscription.is_empty()
+        },
+        (Some(_), None) => true,
+        (None, _) => return GatewayResponse {
+            status: "NOT_SUBSCRIBED".to_string(),
+            message: "This connection isn't subscribed to anything yet".to_string(),
+            engine_latency_ns: None,
+            received_ns: None,
+            sequence: None,
+        },
+    };
+    if remove_connection {
+        player_ws_map_guard.remove(&session_id);
+    }
+
+    GatewayResponse { status: "SUCCESS".to_string(), message: "Unsubscribed".to_string(), engine_latency_ns: None, received_ns: None, sequence: None }
+}
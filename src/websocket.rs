@@ -0,0 +1,742 @@
+use super::{SchemaVersion, Subscription, Channel, Depth, SubscribeMessage, HTTPResponse, CL, build_message};
+use super::api_error::ApiError;
+use super::matching_engine::{SharedReplayBuffer, SharedView};
+use super::auth::{Auth, Identity, SignedRequest};
+use super::order_entry::OrderEntry;
+use super::sequencer::Received;
+use super::deflate::{self, Bandwidth, DeflateConfig, InflateStream};
+use super::outbound::{self, Outbox, OutboundConfig, Outgoing};
+use super::frame::SharedFrame;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
+use futures_util::stream::{SplitSink, StreamExt};
+use futures_util::SinkExt;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{Bytes, Error as WsError, Message};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{CloseCode, Data, OpCode};
+
+
+// plain TCP or TLS, boxed so the rest of the session doesn't care which
+pub trait WsIo: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> WsIo for T {}
+
+pub type WsStream = WebSocketStream<InflateStream<Box<dyn WsIo>>>;
+pub type PlayerWsMap = Arc<Mutex<HashMap<u64, PlayerConnection>>>; // session id -> websocket (a player can have several)
+
+pub const PING_INTERVAL: Duration = Duration::from_secs(15);
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60); // no frames at all (pongs included) for this long and the socket gets closed
+const REPLAY_INTERVAL: Duration = Duration::from_secs(1); // a session can ask for one replay this often
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+// shared by every transport so websocket and SSE sessions can live in the same map
+pub fn next_session_id() -> u64 {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+
+#[derive(Clone)]
+pub struct WsSettings {
+    pub max_sessions: usize, // per player
+    pub tls_acceptor: Option<TlsAcceptor>,
+    pub deflate: Option<DeflateConfig>, // None turns permessage-deflate off for everyone
+    pub outbound: OutboundConfig, // every session's queue size + what to do when it's full
+    pub order_entry: OrderEntry, // `order` / `cancel` once the socket has subscribed
+    pub replay_buffer: SharedReplayBuffer, // the publisher's (or this gateway's link's), what `replay` reads from
+}
+
+
+// write half of a websocket, compresses text messages if the client negotiated permessage-deflate and keeps count of the bytes saved
+pub struct WsSender {
+    sink: SplitSink<WsStream, Message>,
+    deflate: Option<DeflateConfig>,
+    pub bandwidth: Bandwidth,
+}
+
+impl WsSender {
+    fn new(sink: SplitSink<WsStream, Message>, deflate: Option<DeflateConfig>) -> Self {
+        Self {
+            sink,
+            deflate,
+            bandwidth: Bandwidth::default(),
+        }
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WsError> {
+        let text = match message {
+            Message::Text(text) => text,
+            message => return self.sink.send(message).await,
+        };
+
+        let compressed = match self.deflate {
+            Some(deflate) if text.len() >= deflate.threshold => deflate::deflate_message(text.as_bytes(), deflate.level),
+            _ => None,
+        };
+        match compressed {
+            Some(compressed) => self.send_compressed(text.len(), Bytes::from(compressed)).await,
+            None => {
+                self.bandwidth.record(text.len(), text.len());
+                self.sink.send(Message::Text(text)).await
+            }
+        }
+    }
+
+    // a published message, compressed at most once however many sessions it goes out on
+    // and never copied per session, the Message just takes another reference to the frame's bytes
+    pub async fn send_shared(&mut self, frame: &SharedFrame) -> Result<(), WsError> {
+        let text = frame.text();
+        match self.deflate.and_then(|deflate| frame.deflated(deflate)) {
+            Some(compressed) => self.send_compressed(text.len(), compressed.clone()).await,
+            None => {
+                self.bandwidth.record(text.len(), text.len());
+                self.sink.send(Message::Text(text.clone())).await
+            }
+        }
+    }
+
+    async fn send_compressed(&mut self, original_len: usize, compressed: Bytes) -> Result<(), WsError> {
+        self.bandwidth.record(original_len, compressed.len());
+        let mut frame = Frame::message(compressed, OpCode::Data(Data::Text), true);
+        frame.header_mut().rsv1 = true; // marks the message as compressed
+        self.sink.send(Message::Frame(frame)).await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), WsError> {
+        self.sink.flush().await
+    }
+}
+
+
+pub enum Transport {
+    WebSocket(Arc<Outbox>), // the session's writer task drains it into the socket
+    Sse(mpsc::Sender<String>), // the SSE response body reads from the other end
+    Uds(mpsc::Sender<SharedFrame>), // the gateway's writer task frames + writes whatever comes through
+}
+
+impl Transport {
+    // seq + kind become the `id:` / `event:` lines for SSE, websockets get the message as is (`conflatable` only matters to their queue)
+    pub async fn send_event(&mut self, seq: u64, kind: &'static str, conflatable: bool, frame: SharedFrame) -> Result<(), String> {
+        match self {
+            Transport::WebSocket(outbox) => outbox.publish(seq, kind, conflatable, frame).await,
+            Transport::Sse(sender) => sender.try_send(format!("id: {}\nevent: {}\ndata: {}\n\n", seq, kind, frame.as_str())).map_err(|e| format!("{:?}", e)), // full means the client isn't keeping up
+            Transport::Uds(sender) => sender.try_send(frame).map_err(|e| format!("{:?}", e)),
+        }
+    }
+
+    pub fn websocket(&self) -> Option<&Arc<Outbox>> {
+        match self {
+            Transport::WebSocket(outbox) => Some(outbox),
+            _ => None,
+        }
+    }
+}
+
+
+pub struct PlayerConnection {
+    pub player_name: String,
+    pub sender: Transport,
+    pub version: SchemaVersion,
+    pub subscription: Subscription,
+    pub rtt: Option<Duration>, // from the last ping / pong round trip
+    pub from_seq: u64, // the publisher skips anything up to this seq, the snapshot / replay they got already covers it
+}
+
+impl PlayerConnection {
+    pub fn new(player_name: String, sender: Transport, version: SchemaVersion, subscription: Subscription) -> Self {
+        Self {
+            player_name,
+            sender,
+            version,
+            subscription,
+            rtt: None,
+            from_seq: 0,
+        }
+    }
+}
+
+
+// the session always keeps its own handle on the outbox, once the player subscribes the player_ws_map gets one too so the publisher can queue to it
+struct Session {
+    id: u64,
+    addr: SocketAddr,
+    outbox: Arc<Outbox>,
+    writer: Option<JoinHandle<()>>,
+    player_name: Option<String>,
+    player_ws_map: PlayerWsMap,
+    max_sessions: usize,
+    started_at: Instant,
+    last_seen: Instant,
+    rtt: Option<Duration>,
+    last_replay: Option<Instant>,
+}
+
+impl Session {
+    fn new(addr: SocketAddr, sender: WsSender, outbound: OutboundConfig, player_ws_map: PlayerWsMap, max_sessions: usize) -> Self {
+        let now = Instant::now();
+        let outbox = Arc::new(Outbox::new(outbound));
+        let writer = tokio::spawn(outbound::run_writer(sender, Arc::clone(&outbox)));
+        Self {
+            id: next_session_id(),
+            addr,
+            outbox,
+            writer: Some(writer),
+            player_name: None,
+            player_ws_map,
+            max_sessions,
+            started_at: now,
+            last_seen: now,
+            rtt: None,
+            last_replay: None,
+        }
+    }
+
+    // Err means the socket is gone (or the publisher dropped it for being too slow) and the session should end
+    fn send(&self, message: Message) -> Result<(), String> {
+        self.outbox.reply(Outgoing::Message(message))
+    }
+
+    fn reply<T: serde::Serialize>(&self, response: T) {
+        let message = match serde_json::to_string(&response) {
+            Ok(message) => Message::text(message),
+            Err(_) => return,
+        };
+
+        if let Err(e) = self.send(message) {
+            println!("{}[!] WS |:| Failed to reply to the client: {}{}", CL::Red.get(), e, CL::End.get());
+        }
+    }
+
+    // the payload is the ping's send time (nanos since the session started) so the pong tells us the RTT without keeping any state
+    fn ping(&self) -> Result<(), String> {
+        let sent_at = self.started_at.elapsed().as_nanos() as u64;
+        self.send(Message::Ping(Bytes::copy_from_slice(&sent_at.to_be_bytes())))
+    }
+
+    async fn pong(&mut self, payload: &[u8]) {
+        let sent_at = match <[u8; 8]>::try_from(payload) {
+            Ok(bytes) => Duration::from_nanos(u64::from_be_bytes(bytes)),
+            Err(_) => return, // unsolicited pong (used as a heartbeat), nothing to measure
+        };
+        let rtt = match self.started_at.elapsed().checked_sub(sent_at) {
+            Some(rtt) => rtt,
+            None => return,
+        };
+        self.rtt = Some(rtt);
+
+        if let Some(connection) = self.player_ws_map.lock().await.get_mut(&self.id) {
+            connection.rtt = Some(rtt);
+        }
+    }
+
+    // tungstenite queues the pong (or the closing handshake) for us when it reads a ping / close, flushing just gets it out right away
+    fn flush(&self) -> Result<(), String> {
+        self.outbox.reply(Outgoing::Flush)
+    }
+
+    fn close(&self, code: CloseCode, reason: &str) {
+        let frame = CloseFrame { code, reason: reason.to_string().into() };
+        if let Err(e) = self.send(Message::Close(Some(frame))) {
+            println!("{}[!] WS |:| Failed to send the close frame to {:?}: {}{}", CL::DullRed.get(), self.addr, e, CL::End.get());
+        }
+    }
+
+    // returns what was sent over the session's lifetime
+    async fn cleanup(&mut self) -> Bandwidth {
+        self.player_ws_map.lock().await.remove(&self.id);
+        if let Some(writer) = self.writer.take() {
+            outbound::shutdown_writer(&self.outbox, writer).await;
+        }
+        self.outbox.stats().bandwidth
+    }
+}
+
+
+// for the admin: drops every session the player has open (websockets get a close frame first), returns how many there were
+pub async fn close_player_sessions(player_ws_map: &PlayerWsMap, player_name: &str, reason: &str) -> usize {
+    close_sessions(player_ws_map, |connection| connection.player_name == player_name, reason).await
+}
+
+// for a gateway that lost track of the engine's events, everyone has to subscribe again for a fresh snapshot
+pub async fn close_all_sessions(player_ws_map: &PlayerWsMap, reason: &str) -> usize {
+    close_sessions(player_ws_map, |_| true, reason).await
+}
+
+async fn close_sessions<F>(player_ws_map: &PlayerWsMap, close: F, reason: &str) -> usize
+where
+    F: Fn(&PlayerConnection) -> bool,
+{
+    let mut player_ws_map_guard = player_ws_map.lock().await;
+    let session_ids = player_ws_map_guard.iter()
+        .filter(|(_, connection)| close(connection))
+        .map(|(session_id, _)| *session_id)
+        .collect::<Vec<u64>>();
+
+    for session_id in &session_ids {
+        if let Some(connection) = player_ws_map_guard.remove(session_id) {
+            if let Some(outbox) = connection.sender.websocket() {
+                let frame = CloseFrame { code: CloseCode::Policy, reason: reason.to_string().into() };
+                let _ = outbox.reply(Outgoing::Message(Message::Close(Some(frame)))); // SSE + UDS streams just end when their sender is dropped
+            }
+        }
+    }
+    session_ids.len()
+}
+
+
+pub fn parse_channels(channels: &[String]) -> Result<Vec<Channel>, HTTPResponse> {
+    let mut parsed = Vec::new();
+    for channel in channels {
+        match Channel::parse(channel) {
+            Some(channels) => parsed.extend(channels),
+            None => return Err(HTTPResponse {
+                status: "INVALID_CHANNEL".to_string(),
+                message: format!("Unknown channel '{}'. Please send any of 'book', 'book.spades', 'book.clubs', 'book.diamonds', 'book.hearts', 'trades', 'private', 'game_state' or 'stats'", channel),
+            }),
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn parse_subscription(channels: &Option<Vec<String>>, depth: &Option<String>) -> Result<Subscription, HTTPResponse> {
+    let depth = match depth {
+        Some(depth) => match Depth::parse(depth) {
+            Some(depth) => depth,
+            None => return Err(HTTPResponse {
+                status: "INVALID_DEPTH".to_string(),
+                message: "For the depth, please send either `bbo`, `top_<n>` (e.g. `top_5`) or `full`".to_string(),
+            }),
+        },
+        None => Depth::Full,
+    };
+
+    match channels {
+        Some(channels) => Ok(Subscription::new(&parse_channels(channels)?, depth)),
+        None => {
+            let mut subscription = Subscription::legacy();
+            subscription.depth = depth;
+            Ok(subscription)
+        }
+    }
+}
+
+
+pub async fn handle_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    player_ws_map: PlayerWsMap,
+    playerid_playername_map: Arc<RwLock<HashMap<String, String>>>,
+    engine_view: SharedView,
+    auth: Arc<Auth>,
+    settings: WsSettings,
+) {
+    println!("[+] WS |:| Incoming TCP connection from: {:?}", addr);
+
+    let stream: Box<dyn WsIo> = match &settings.tls_acceptor {
+        Some(tls_acceptor) => match tls_acceptor.accept(stream).await {
+            Ok(tls_stream) => Box::new(tls_stream),
+            Err(e) => {
+                println!("{}[!] WS |:| TLS handshake failed for {:?}: {:?}{}", CL::Orange.get(), addr, e, CL::End.get());
+                return;
+            }
+        },
+        None => Box::new(stream),
+    };
+
+    // permessage-deflate is only switched on underneath tungstenite once the client has asked for it in the handshake
+    let inflate_enabled = Arc::new(AtomicBool::new(false));
+    let stream = InflateStream::new(stream, Arc::clone(&inflate_enabled));
+    let mut deflate = None;
+    #[allow(clippy::result_large_err)] // the error type is tungstenite's, we never return it
+    let negotiate_deflate = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+        let offers = request.headers().get_all("Sec-WebSocket-Extensions").iter()
+            .filter_map(|offer| offer.to_str().ok())
+            .collect::<Vec<&str>>()
+            .join(",");
+        if let (Some(config), Some(extension)) = (settings.deflate, deflate::negotiate(&offers)) {
+            response.headers_mut().insert("Sec-WebSocket-Extensions", HeaderValue::from_static(extension));
+            inflate_enabled.store(true, Ordering::Release);
+            deflate = Some(config);
+        }
+        Ok(response)
+    };
+
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, negotiate_deflate).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            println!("{}[!] Error accepting WS connection: {:?}{}", CL::Red.get(), e, CL::End.get());
+            return;
+        }
+    };
+    println!("{}[+] WS |:| WebSocket connection established: {:?} | compression: {:?}{}", CL::Green.get(), addr, deflate, CL::End.get());
+
+    let (sender, mut receiver) = ws_stream.split();
+    let mut session = Session::new(addr, WsSender::new(sender, deflate), settings.outbound, player_ws_map, settings.max_sessions);
+
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    ping_interval.tick().await; // the first tick fires right away
+    let outbox = Arc::clone(&session.outbox);
+
+    loop {
+        tokio::select! {
+            msg = receiver.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        println!("{}[!] WS |:| Connection error from {:?}: {:?}{}", CL::DullRed.get(), addr, e, CL::End.get());
+                        break;
+                    },
+                    None => {
+                        println!("{}[!] WS |:| Connection dropped without a close frame: {:?}{}", CL::DullRed.get(), addr, CL::End.get());
+                        break;
+                    }
+                };
+                session.last_seen = Instant::now();
+
+                match msg {
+                    Message::Text(text) => {
+                        let received = Received::now(); // an order's stamp for the sequencer, before anything else gets to take time
+                        println!("{}[-] WS |:| Received a message: {:?}{}", CL::Dull.get(), text, CL::End.get());
+                        handle_text(&mut session, &text, received, &playerid_playername_map, &engine_view, &auth, &settings).await;
+                    },
+                    Message::Binary(_) => {
+
+                        // =-= UNSUPPORTED_FRAME =-= //
+                        println!("{}[!] WS |:| Binary frame from {:?}, only text frames are supported{}", CL::Orange.get(), addr, CL::End.get());
+                        session.reply(HTTPResponse {
+                            status: "UNSUPPORTED_FRAME".to_string(),
+                            message: "Binary frames aren't supported, please send JSON as a text frame".to_string()
+                        });
+
+                    },
+                    Message::Ping(_) => {
+                        if let Err(e) = session.flush() {
+                            println!("{}[!] WS |:| Failed to answer a ping from {:?}: {}{}", CL::DullRed.get(), addr, e, CL::End.get());
+                            break;
+                        }
+                    },
+                    Message::Pong(payload) => session.pong(&payload).await,
+                    Message::Close(frame) => {
+                        match frame {
+                            Some(frame) => println!("{}[!] WS |:| Connection has been closed: {:?} | {} {:?}{}", CL::DullRed.get(), addr, frame.code, frame.reason, CL::End.get()),
+                            None => println!("{}[!] WS |:| Connection has been closed: {:?} | no reason given{}", CL::DullRed.get(), addr, CL::End.get()),
+                        }
+                        let _ = session.flush(); // sends the close frame tungstenite queued in reply
+                        break;
+                    },
+                    Message::Frame(_) => {}
+                }
+            },
+            _ = ping_interval.tick() => {
+                if session.last_seen.elapsed() >= IDLE_TIMEOUT {
+                    println!("{}[!] WS |:| Closing idle connection: {:?} | nothing received for {:?}{}", CL::DullRed.get(), addr, session.last_seen.elapsed(), CL::End.get());
+                    session.close(CloseCode::Away, "idle timeout");
+                    break;
+                }
+
+                if let Err(e) = session.ping() {
+                    println!("{}[!] WS |:| Failed to ping {:?}, closing the session: {}{}", CL::DullRed.get(), addr, e, CL::End.get());
+                    break;
+                }
+            },
+            _ = outbox.dropped() => {
+                println!("{}[!] WS |:| Dropping slow consumer: {:?} | player: {:?} | outbound queue full{}", CL::Orange.get(), addr, session.player_name, CL::End.get());
+                break;
+            }
+        }
+    }
+
+    let bandwidth = session.cleanup().await;
+    println!("{}[-] WS |:| Session ended: {:?} | player: {:?} | last rtt: {:?} | sent {} bytes ({} before compression){}", CL::Dull.get(), addr, session.player_name, session.rtt, bandwidth.wire_bytes, bandwidth.raw_bytes, CL::End.get());
+}
+
+
+async fn handle_text(session: &mut Session, text: &str, received: Received, playerid_playername_map: &Arc<RwLock<HashMap<String, String>>>, engine_view: &SharedView, auth: &Auth, settings: &WsSettings) {
+    if let Ok(message) = serde_json::from_str::<SubscribeMessage>(text) {
+        match message.action.as_str() {
+            "subscribe" => subscribe(session, &message, playerid_playername_map, engine_view, auth).await,
+            "unsubscribe" => unsubscribe(session, &message).await,
+            "replay" => replay(session, &message, &settings.replay_buffer).await,
+            "order" | "cancel" => order(session, &message, received, &settings.order_entry).await,
+            _ => {
+
+                // =-= UNAUTHORIZED_ACTION =-= //
+                println!("{}[!] WS |:| Unrecognized action: {:?} | Please send 'subscribe' with 'playerid'{}", CL::Orange.get(), message.action, CL::End.get());
+                session.reply(HTTPResponse {
+                    status: "UNAUTHORIZED_ACTION".to_string(),
+                    message: "Unauthorized action, please send 'subscribe', 'unsubscribe', 'replay', 'order' or 'cancel' as the action".to_string()
+                });
+
+            }
+        }
+    } else {
+
+        // =-= PARSE_ERROR =-= //
+        println!("{}[!] WS |:| Failed to parse the WS message{}", CL::Orange.get(), CL::End.get());
+        session.reply(HTTPResponse {
+            status: "PARSE_ERROR".to_string(),
+            message: "Failed to parse the message. Please send a JSON message with fields 'subscribe' and 'playerid' that match up with your PlayerName (in the testnet, send a random playerid)".to_string()
+        });
+
+    }
+}
+
+
+async fn subscribe(session: &mut Session, message: &SubscribeMessage, playerid_playername_map: &Arc<RwLock<HashMap<String, String>>>, engine_view: &SharedView, auth: &Auth) {
+    println!("{}[-] WS |:| Attempting to subscribe to the exchange{}", CL::Dull.get(), CL::End.get());
+
+    let version = match SchemaVersion::from_request(message.version) {
+        Some(version) => version,
+        None => {
+
+            // =-= UNSUPPORTED_VERSION =-= //
+            println!("{}[!] WS |:| Unsupported schema version: {:?}{}", CL::Orange.get(), message.version, CL::End.get());
+            session.reply(HTTPResponse {
+                status: "UNSUPPORTED_VERSION".to_string(),
+                message: "Unsupported schema version, please send 'version' as 1 or 2 (or leave it out for 1)".to_string()
+            });
+            return;
+        }
+    };
+
+    let subscription = match parse_subscription(&message.channels, &message.depth) {
+        Ok(subscription) => subscription,
+        Err(response) => {
+            println!("{}[!] WS |:| Invalid subscription: {}{}", CL::Orange.get(), response.message, CL::End.get());
+            session.reply(response);
+            return;
+        }
+    };
+
+    // signed w/ an API key, or the old `playerid` (while unsigned subscribes are allowed)
+    let signed_request = SignedRequest::from_fields(&message.api_key, message.timestamp, &message.nonce, &message.signature);
+    let player_name = match auth.authenticate(signed_request, "WS", "subscribe", &[]).await {
+        Ok(Some(Identity::Player(player_name))) => Some(player_name),
+        Ok(Some(Identity::Admin)) => {
+            session.reply(HTTPResponse::from(ApiError::Unauthorized("The admin key can't subscribe".to_string())));
+            return;
+        },
+        Ok(None) => playerid_playername_map.read().await.get(&message.playerid).cloned(),
+        Err(error) => {
+
+            // =-= UNAUTHORIZED =-= //
+            println!("{}[!] WS |:| Failed to authenticate the subscribe: {}{}", CL::Orange.get(), error.message(), CL::End.get());
+            session.reply(HTTPResponse::from(error));
+            return;
+        }
+    };
+
+    let player_name = match player_name {
+        Some(player_name) => player_name,
+        None => {
+
+            // =-= ACCOUNT_NOT_FOUND =-= //
+            println!("{}[!] WS |:| Account not found for the password given: {}{}", CL::Orange.get(), message.playerid, CL::End.get());
+            session.reply(HTTPResponse {
+                status: "UNKNOWN_PLAYER".to_string(),
+                message: "Player name not found. Have you sent a post to /register_testnet?".to_string()
+            });
+            return;
+        }
+    };
+
+    // the engine publishes its view before the events that follow it, so while we hold the player_ws_map (the publisher can't
+    // broadcast) the view's seq is exactly where the live stream picks up. The publisher skips whatever it still has queued up to
+    // the snapshot's seq, so it lines up with the first update the player receives afterwards
+    let mut player_ws_map_guard = session.player_ws_map.lock().await;
+
+    let open_sessions = player_ws_map_guard.iter()
+        .filter(|(session_id, connection)| **session_id != session.id && connection.player_name == player_name)
+        .count();
+    if open_sessions >= session.max_sessions {
+        drop(player_ws_map_guard);
+
+        // =-= TOO_MANY_SESSIONS =-= //
+        println!("{}[!] WS |:| Session limit reached for {:?} | {} open{}", CL::Orange.get(), player_name, open_sessions, CL::End.get());
+        session.reply(HTTPResponse {
+            status: "TOO_MANY_SESSIONS".to_string(),
+            message: format!("{} already has {} open sessions (the limit is {}), please close one before subscribing on another", player_name, open_sessions, session.max_sessions)
+        });
+        return;
+    }
+
+    player_ws_map_guard.remove(&session.id); // resubscribing replaces the old subscription
+
+    // =-= SUCCESS =-= //
+    println!("{}[+] WS |:| Successfully subscribed to the stream: {:?} | session {}{}", CL::DullTeal.get(), player_name, session.id, CL::End.get());
+    let welcome = HTTPResponse {
+        status: "SUCCESS".to_string(),
+        message: format!("Welcome to the tesetnet, {}! You've been subscribed for further data updates", player_name)
+    };
+    let snapshot = engine_view.load().snapshot(&player_name);
+    let messages = [
+        serde_json::to_string(&welcome).unwrap_or_default(),
+        build_message("snapshot", &snapshot, version, snapshot.seq),
+    ];
+    for message in messages {
+        if let Err(e) = session.send(Message::text(message)) {
+            println!("{}[!] WS |:| Failed to send the snapshot: {}{}", CL::Red.get(), e, CL::End.get());
+            return;
+        }
+    }
+
+    let mut connection = PlayerConnection::new(player_name.clone(), Transport::WebSocket(Arc::clone(&session.outbox)), version, subscription);
+    connection.rtt = session.rtt;
+    connection.from_seq = snapshot.seq;
+    player_ws_map_guard.insert(session.id, connection);
+    session.player_name = Some(player_name);
+}
+
+
+// the socket was authenticated when it subscribed, so orders just go out as that player (same rate limit as the RestAPI)
+async fn order(session: &mut Session, message: &SubscribeMessage, received: Received, order_entry: &OrderEntry) {
+    let player_name = match &session.player_name {
+        Some(player_name) => player_name.clone(),
+        None => {
+            session.reply(HTTPResponse {
+                status: "NOT_SUBSCRIBED".to_string(),
+                message: "Please subscribe before sending orders".to_string()
+            });
+            return;
+        }
+    };
+
+    let response = order_entry.submit(player_name, &message.action, message.card.as_deref(), message.price, message.direction.as_deref(), received).await;
+    session.reply(response);
+}
+
+
+async fn replay(session: &mut Session, message: &SubscribeMessage, replay_buffer: &SharedReplayBuffer) {
+    let (player_name, from_seq) = match (&session.player_name, message.from_seq) {
+        (Some(player_name), Some(from_seq)) => (player_name.clone(), from_seq),
+        (_, None) => {
+            session.reply(HTTPResponse {
+                status: "PARSE_ERROR".to_string(),
+                message: "Please send 'from_seq' with the first sequence number you're missing".to_string()
+            });
+            return;
+        },
+        _ => {
+            session.reply(HTTPResponse {
+                status: "NOT_SUBSCRIBED".to_string(),
+                message: "Please subscribe before requesting a replay".to_string()
+            });
+            return;
+        }
+    };
+
+    // =-= RATE_LIMIT =-= //
+    if let Some(wait) = session.last_replay.and_then(|last_replay| REPLAY_INTERVAL.checked_sub(last_replay.elapsed())) {
+        session.reply(HTTPResponse {
+            status: "RATE_LIMIT".to_string(),
+            message: format!("Only one replay every {}ms, please try again in {}ms", REPLAY_INTERVAL.as_millis(), wait.as_millis().max(1))
+        });
+        return;
+    }
+    session.last_replay = Some(Instant::now());
+
+    let subscribed = session.player_ws_map.lock().await.get(&session.id).map(|connection| (connection.version, connection.subscription));
+    let (version, subscription) = match subscribed {
+        Some(subscribed) => subscribed,
+        None => {
+            session.reply(HTTPResponse {
+                status: "NOT_SUBSCRIBED".to_string(),
+                message: "Please subscribe before requesting a replay".to_string()
+            });
+            return;
+        }
+    };
+
+    // rendered w/o holding the player_ws_map, then whatever was published in the meantime is rendered (usually nothing) + everything
+    // is queued while it's held, so nothing newer can be broadcast to this session before the replay's out
+    let replayed = replay_buffer.replay(from_seq, &player_name, version, &subscription);
+    let mut player_ws_map_guard = session.player_ws_map.lock().await;
+    let replayed = replayed.and_then(|(mut messages, last_seq)| {
+        let (newer, last_seq) = replay_buffer.replay(last_seq + 1, &player_name, version, &subscription)?;
+        messages.extend(newer);
+        Ok((messages, last_seq))
+    });
+    let (response, messages, last_seq) = match replayed {
+        Ok((messages, last_seq)) => (HTTPResponse { status: "SUCCESS".to_string(), message: format!("Replaying {} messages from seq {}", messages.len(), from_seq) }, messages, Some(last_seq)),
+        Err(oldest_seq) => (HTTPResponse { status: "REPLAY_UNAVAILABLE".to_string(), message: format!("Messages before seq {} are no longer buffered, please subscribe again for a fresh snapshot", oldest_seq) }, Vec::new(), None),
+    };
+    println!("{}[-] WS |:| Replay for {:?} from seq {} | {}{}", CL::Dull.get(), player_name, from_seq, response.status, CL::End.get());
+    match (player_ws_map_guard.get_mut(&session.id), last_seq) {
+        (Some(connection), Some(last_seq)) => connection.from_seq = connection.from_seq.max(last_seq), // don't send them again if the publisher hasn't gotten to them yet
+        (Some(_), None) => {},
+        (None, _) => return, // unsubscribed in the meantime
+    }
+
+    let response = Message::text(serde_json::to_string(&response).unwrap_or_default());
+    for message in std::iter::once(response).chain(messages.into_iter().map(|(_, _, message)| Message::text(message))) {
+        if let Err(e) = session.send(message) {
+            println!("{}[!] WS |:| Failed to send the replay: {}{}", CL::Red.get(), e, CL::End.get());
+            break;
+        }
+    }
+}
+
+
+async fn unsubscribe(session: &mut Session, message: &SubscribeMessage) {
+    let player_name = match &session.player_name {
+        Some(player_name) => player_name.clone(),
+        None => {
+            session.reply(HTTPResponse {
+                status: "NOT_SUBSCRIBED".to_string(),
+                message: "This connection isn't subscribed to anything yet".to_string()
+            });
+            return;
+        }
+    };
+
+    let channels = match &message.channels {
+        Some(channels) => match parse_channels(channels) {
+            Ok(channels) => Some(channels),
+            Err(response) => {
+                session.reply(response);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let mut player_ws_map_guard = session.player_ws_map.lock().await;
+    let remove_connection = match (player_ws_map_guard.get_mut(&session.id), &channels) {
+        (Some(connection), Some(channels)) => {
+            connection.subscription.remove(channels);
+            connection.subscription.is_empty()
+        },
+        (Some(_), None) => true, // no channel list means unsubscribe from everything
+        (None, _) => {
+            drop(player_ws_map_guard);
+            session.reply(HTTPResponse {
+                status: "NOT_SUBSCRIBED".to_string(),
+                message: "This connection isn't subscribed to anything yet".to_string()
+            });
+            return;
+        }
+    };
+
+    // the socket stays open (the session keeps its outbox) so the client can subscribe again later
+    if remove_connection {
+        player_ws_map_guard.remove(&session.id);
+    }
+    drop(player_ws_map_guard);
+
+    println!("{}[+] WS |:| Unsubscribed: {:?} | {:?}{}", CL::DullTeal.get(), player_name, message.channels, CL::End.get());
+    session.reply(HTTPResponse {
+        status: "SUCCESS".to_string(),
+        message: "Unsubscribed".to_string()
+    });
+}